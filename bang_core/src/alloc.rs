@@ -1,16 +1,16 @@
-use arena::Sink;
-pub use arena::{Arena, ArenaGuard, Id, Managed};
+pub use arena::{Arena, ArenaGuard, Id, Managed, ManagedReader};
+use arena::{HashMap, HashSet, Sink, SortedMap};
 
 #[repr(C)]
 pub struct Mem<'frame> {
-    pub alloc_seq: usize,
+    pub alloc_seq: u64,
     pub arena: &'frame mut ArenaGuard<'frame>,
 }
 
 impl<'f> Mem<'f> {
     pub fn new(arena: &'f mut ArenaGuard<'f>) -> Self {
         Mem {
-            alloc_seq: 0,
+            alloc_seq: arena.alloc_seq,
             arena,
         }
     }
@@ -23,6 +23,16 @@ impl<'f> Mem<'f> {
         self.arena.alloc_val(val)
     }
 
+    /// Like `vec`, but the contents are dropped at the end of the frame.
+    pub fn vec_with_drop<T: Send + 'static>(&mut self) -> &'f mut Vec<T> {
+        self.arena.alloc_vec_with_drop()
+    }
+
+    /// Like `val`, but the value is dropped at the end of the frame.
+    pub fn val_with_drop<T: Send + 'static>(&mut self, val: T) -> &'f mut T {
+        self.arena.alloc_val_with_drop(val)
+    }
+
     pub fn slice<T>(&mut self, slice: &[T]) -> &'f mut [T] {
         self.arena.alloc_slice(slice)
     }
@@ -46,6 +56,18 @@ impl<'f> Mem<'f> {
     pub fn sink<'s, T>(&'s mut self) -> Sink<'s, 'f, T> {
         self.arena.alloc_sink()
     }
+
+    pub fn hash_map<K, V>(&mut self) -> &'f mut HashMap<'f, K, V> {
+        self.arena.alloc_hash_map()
+    }
+
+    pub fn hash_set<T>(&mut self) -> &'f mut HashSet<'f, T> {
+        self.arena.alloc_hash_set()
+    }
+
+    pub fn sorted_map<K, V>(&mut self) -> &'f mut SortedMap<'f, K, V> {
+        self.arena.alloc_sorted_map()
+    }
 }
 
 #[cfg(test)]
@@ -70,5 +92,15 @@ mod tests {
         assert_eq!(*val, 420);
         assert_eq!(slice, &[1, 2, 3]);
         assert_eq!(slice_from_iter, &[4, 5, 6]);
+
+        let map = mem.hash_map();
+        map.insert("a", 1);
+        let set = mem.hash_set();
+        set.insert(2);
+        let sorted = mem.sorted_map();
+        sorted.insert(3, "c");
+        assert_eq!(map.get("a"), Some(&1));
+        assert!(set.contains(&2));
+        assert_eq!(sorted.first(), Some((&3, &"c")));
     }
 }
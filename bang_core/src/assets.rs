@@ -0,0 +1,51 @@
+/// An asset file embedded in the executable with `include_assets!`
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedAsset {
+    /// The path the asset is looked up with, e.g. `assets/paltex/bubu.paltex`
+    pub path: &'static str,
+    pub bytes: &'static [u8],
+}
+
+/// Embeds asset files in the executable at compile time, returning a
+/// `&'static [EmbeddedAsset]`. Register them with the runtime before starting
+/// it, and `RtCtx::load_textures` reads them from memory instead of the disk.
+///
+/// The paths are relative to the directory of the crate manifest, or to
+/// `dir`, if given:
+///
+/// ```ignore
+/// let assets = include_assets!(dir = "../..", "assets/paltex/bubu.paltex");
+/// ```
+#[macro_export]
+macro_rules! include_assets {
+    (dir = $dir:literal, $($path:literal),* $(,)?) => {
+        &[$($crate::assets::EmbeddedAsset {
+            path: $path,
+            bytes: include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $path)),
+        }),*]
+    };
+    ($($path:literal),* $(,)?) => {
+        $crate::include_assets!(dir = ".", $($path),*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmbeddedAsset;
+
+    #[test]
+    fn test_include_assets() {
+        let assets: &[EmbeddedAsset] = include_assets!(
+            dir = "..",
+            "assets/paltex/bubu.paltex",
+            "assets/paltex/toge.paltex",
+        );
+        assert_eq!(assets.len(), 2);
+        assert_eq!(assets[1].path, "assets/paltex/toge.paltex");
+        let bytes = std::fs::read("../assets/paltex/toge.paltex").unwrap();
+        assert_eq!(assets[1].bytes, bytes);
+
+        let assets: &[EmbeddedAsset] = include_assets!("Cargo.toml");
+        assert!(assets[0].bytes.starts_with(b"[package]"));
+    }
+}
@@ -34,6 +34,7 @@ unsafe impl<T> AsBytes for [T] where T: AsBytes {}
 unsafe impl<T, const N: usize> AsBytes for [T; N] where T: AsBytes {}
 
 unsafe impl AsBytes for u8 {}
+unsafe impl AsBytes for u16 {}
 unsafe impl AsBytes for f32 {}
 unsafe impl AsBytes for ScreenPos {}
 
@@ -70,7 +71,7 @@ impl<'f> Cmd<'f> {
 #[derive(Debug)]
 #[repr(C)]
 pub struct DrawFrame<'f> {
-    pub alloc_seq: usize,
+    pub alloc_seq: u64,
     pub cmds: &'f [Cmd<'f>],
 }
 
@@ -80,7 +81,7 @@ pub static DRAW_FRAME_DUMMY: DrawFrame = DrawFrame {
 };
 
 impl<'f> DrawFrame<'f> {
-    pub fn with_cmds(cmds: &'f [Cmd], seq: usize) -> Self {
+    pub fn with_cmds(cmds: &'f [Cmd], seq: u64) -> Self {
         DrawFrame {
             alloc_seq: seq,
             cmds,
@@ -117,13 +118,13 @@ mod tests {
             (4.0, -4.0),
         ];
         let frame = DrawFrame::debug_dummies(&dummies, &mut alloc);
-        assert_eq!(frame.alloc_seq, alloc.alloc_seq);
+        assert_eq!(frame.alloc_seq, 1);
         assert_eq!(frame.cmds.len(), 1);
-        match &frame.cmds[0] {
-            &Cmd::DrawSQuads { .. } => {
+        match frame.cmds[0] {
+            Cmd::DrawSQuads { .. } => {
                 unreachable!()
             }
-            &Cmd::DrawDummies { pos } => {
+            Cmd::DrawDummies { pos } => {
                 assert_eq!(pos.len(), 5);
                 assert_eq!(pos[0].x, 0.0);
                 assert_eq!(pos[0].y, 0.0);
@@ -1,8 +1,13 @@
 use std::ffi::CStr;
 
-use arena::Id;
+use arena::{Id, SharedAllocState};
 
-use crate::{Config, alloc::Mem, draw::DrawFrame, input::InputState};
+use crate::{
+    Config,
+    alloc::{Managed, Mem},
+    draw::DrawFrame,
+    input::InputState,
+};
 
 pub type FnUpdateRaw = for<'f> fn(
     alloc: &mut Mem<'f>,
@@ -13,6 +18,12 @@ pub type FnUpdateRaw = for<'f> fn(
 
 pub type FnInitRaw = for<'f> fn(mem: &mut Mem<'f>, rt: &mut RtCtx) -> LogicInitReturn;
 
+pub type FnAllocLayoutRaw = fn() -> u32;
+
+/// The layout features of the arenas, as built into this library. The logic
+/// library exports its own, so that the runtime can check they agree.
+pub const ALLOC_LAYOUT: u32 = arena::LAYOUT_FEATURES;
+
 pub struct Erased;
 
 #[derive(Clone, Copy, Debug)]
@@ -21,8 +32,8 @@ pub struct SendableErasedPtr(pub *mut Erased);
 unsafe impl Send for SendableErasedPtr {}
 
 impl SendableErasedPtr {
-    pub fn wrap<T>(mut ptr: Box<T>) -> Self {
-        SendableErasedPtr(&raw mut *ptr as *mut Erased)
+    pub fn wrap<T>(ptr: Box<T>) -> Self {
+        SendableErasedPtr(Box::into_raw(ptr) as *mut Erased)
     }
 }
 
@@ -41,13 +52,27 @@ pub struct RtCtx {
     pub frame: u64,
     pub rt_kind: RtKind,
     pub load_textures_ptr: for<'f> fn(&mut Self, &[&str], &mut Mem<'f>) -> &'f [Id<Tex>],
+    pub load_palette_ptr: for<'f> fn(&mut Self, &str, &mut Mem<'f>) -> &'f [[u8; 4]],
     pub rt_state: SendableErasedPtr,
+    pub alloc_state: &'static SharedAllocState,
 }
 
 impl RtCtx {
     pub fn load_textures<'f>(&mut self, tex: &[&str], mem: &mut Mem<'f>) -> &'f [Id<Tex>] {
         (self.load_textures_ptr)(self, tex, mem)
     }
+
+    /// Loads a palette file, shared by the textures drawn with it. The colors
+    /// are RGBA, the implicit transparent at index 0 followed by the palette.
+    pub fn load_palette<'f>(&mut self, path: &str, mem: &mut Mem<'f>) -> &'f [[u8; 4]] {
+        (self.load_palette_ptr)(self, path, mem)
+    }
+
+    /// Creates a store for values that persist across frames, referred to by
+    /// IDs. Keep it in the logic state, and free the values explicitly.
+    pub fn managed<T: Copy, I>(&self) -> Managed<'static, T, I> {
+        Managed::new(self.alloc_state)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -68,18 +93,21 @@ pub trait Logic: Send + Sized {
     type S;
 
     fn new() -> Self {
-        unimplemented!()
+        // UNREACHABLE: `init_raw` is the only caller, and the implementations
+        // either define this or override `init_raw`, as `DynLoadedLogic` does
+        unreachable!("UNREACHABLE: implement new, or override init_raw")
     }
 
     #[allow(unused)]
     fn init(&self, mem: &mut Mem<'_>, ctx: &mut RtCtx) -> (Self::S, Config) {
-        unimplemented!()
+        // UNREACHABLE: as with `new`
+        unreachable!("UNREACHABLE: implement init, or override init_raw")
     }
 
     fn init_raw(&self, mem: &mut Mem<'_>, ctx: &mut RtCtx) -> LogicInitReturn {
         let (state, config) = Self::init(&Self::new(), mem, ctx);
         LogicInitReturn {
-            logic_state: &raw mut *Box::new(state) as *mut Erased,
+            logic_state: Box::into_raw(Box::new(state)) as *mut Erased,
             config,
         }
     }
@@ -92,7 +120,9 @@ pub trait Logic: Send + Sized {
         ctx: &mut RtCtx,
         state: &mut Self::S,
     ) -> DrawFrame<'f> {
-        unimplemented!()
+        // UNREACHABLE: `update_raw` is the only caller, and the
+        // implementations either define this or override `update_raw`
+        unreachable!("UNREACHABLE: implement update, or override update_raw")
     }
 
     fn update_raw<'f>(
@@ -129,8 +159,50 @@ macro_rules! export_logic {
             let slf = <$impl as $crate::ffi::Logic>::new();
             <$impl as $crate::ffi::Logic>::init_raw(&slf, mem, rt)
         }
+
+        #[unsafe(no_mangle)]
+        pub extern "Rust" fn logic_alloc_layout() -> u32 {
+            $crate::ffi::ALLOC_LAYOUT
+        }
     };
 }
 
 pub const LOGIC_INIT_SYM: &CStr = c"logic_init";
 pub const LOGIC_UPDATE_SYM: &CStr = c"logic_update";
+pub const LOGIC_ALLOC_LAYOUT_SYM: &CStr = c"logic_alloc_layout";
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null_mut;
+
+    use super::*;
+
+    fn load_textures<'f>(_: &mut RtCtx, _: &[&str], _: &mut Mem<'f>) -> &'f [Id<Tex>] {
+        &[]
+    }
+
+    fn load_palette<'f>(_: &mut RtCtx, _: &str, _: &mut Mem<'f>) -> &'f [[u8; 4]] {
+        &[]
+    }
+
+    #[test]
+    fn test_managed() {
+        let ctx = RtCtx {
+            frame: 0,
+            rt_kind: RtKind::Test,
+            load_textures_ptr: load_textures,
+            load_palette_ptr: load_palette,
+            rt_state: SendableErasedPtr(null_mut()),
+            alloc_state: Box::leak(Box::default()),
+        };
+
+        let mut bullets: Managed<(f32, f32)> = ctx.managed();
+        let mut enemies: Managed<u32> = ctx.managed();
+        let bullet = bullets.alloc((1.0, 2.0));
+        let enemy = enemies.alloc(3);
+        assert_eq!(bullets.get(bullet), Some(&(1.0, 2.0)));
+        assert_eq!(enemies.get(enemy), Some(&3));
+        // Each store gets its own arena ID
+        assert_ne!(bullet.arena_id(), enemy.arena_id());
+    }
+}
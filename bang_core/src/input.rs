@@ -1,3 +1,5 @@
+use std::{mem::swap, time::Duration};
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum KeyState {
@@ -40,6 +42,96 @@ impl KeyState {
     }
 }
 
+/// Maximum number of input events recorded per logic frame. Events beyond
+/// this are left out of the log, but they still affect the aggregated key
+/// states.
+pub const EVENT_LOG_CAP: usize = 32;
+
+/// A single key state change, timestamped relative to the start of the logic
+/// frame it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct InputEvent {
+    pub key: Key,
+    pub state: KeyState,
+    pub offset: Duration,
+}
+
+impl InputEvent {
+    const EMPTY: InputEvent = InputEvent {
+        key: Key::Other,
+        state: KeyState::Up,
+        offset: Duration::ZERO,
+    };
+}
+
+/// Ordered log of the input events that happened during one logic frame.
+///
+/// Most games only need the aggregated per-key states of `InputState`, but
+/// those lose the order of events that happen within a single frame, such as
+/// press-release-press. The log keeps them, up to `EVENT_LOG_CAP` events.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct EventLog {
+    events: [InputEvent; EVENT_LOG_CAP],
+    len: usize,
+    overflowed: bool,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLog {
+    pub const fn new() -> Self {
+        EventLog {
+            events: [InputEvent::EMPTY; EVENT_LOG_CAP],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    pub fn push(&mut self, event: InputEvent) {
+        if self.len < EVENT_LOG_CAP {
+            self.events[self.len] = event;
+            self.len += 1;
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    pub const fn clear(&mut self) {
+        self.len = 0;
+        self.overflowed = false;
+    }
+
+    /// Replaces the contents of this log with the contents of `next`,
+    /// leaving `next` empty.
+    const fn take(&mut self, next: &mut EventLog) {
+        swap(self, next);
+        next.clear();
+    }
+
+    pub fn as_slice(&self) -> &[InputEvent] {
+        &self.events[..self.len]
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if some events of the frame didn't fit in the log.
+    pub const fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 #[repr(C)]
 pub struct InputState {
@@ -48,6 +140,7 @@ pub struct InputState {
     pub down: KeyState,
     pub left: KeyState,
     pub right: KeyState,
+    pub events: EventLog,
 }
 
 impl InputState {
@@ -58,6 +151,7 @@ impl InputState {
             down: KeyState::Up,
             left: KeyState::Up,
             right: KeyState::Up,
+            events: EventLog::new(),
         }
     }
 
@@ -67,6 +161,22 @@ impl InputState {
         self.down.merge(&mut next.down);
         self.left.merge(&mut next.left);
         self.right.merge(&mut next.right);
+        self.events.take(&mut next.events);
+    }
+
+    /// Updates the aggregated state of the key, and records the change in
+    /// the event log. `offset` is the time elapsed since the start of the
+    /// frame.
+    pub fn record(&mut self, key: Key, state: KeyState, offset: Duration) {
+        if key == Key::Other {
+            return;
+        }
+        self.update(key, state);
+        self.events.push(InputEvent { key, state, offset });
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        self.events.as_slice()
     }
 
     pub fn update(&mut self, key: Key, state: KeyState) {
@@ -130,7 +240,9 @@ impl Key {
 
 #[cfg(test)]
 mod tests {
-    use super::{InputState, Key, KeyState};
+    use std::{ops::Not, time::Duration};
+
+    use super::{EVENT_LOG_CAP, InputEvent, InputState, Key, KeyState};
 
     #[test]
     fn test_relax_and_merge() {
@@ -277,6 +389,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_event_log() {
+        let mut state = InputState::new();
+        let mut next = InputState::new();
+        let ms = Duration::from_millis;
+
+        state.record(Key::Space, KeyState::Pressed, ms(1));
+        state.record(Key::Other, KeyState::Pressed, ms(2));
+        state.record(Key::Space, KeyState::Released, ms(3));
+        state.record(Key::Space, KeyState::Pressed, ms(4));
+        next.record(Key::Left, KeyState::Tap, ms(5));
+
+        // The aggregated state loses the order, but the log keeps it
+        assert_eq!(state.space, KeyState::Pressed);
+        assert_eq!(
+            state.events(),
+            &[
+                InputEvent {
+                    key: Key::Space,
+                    state: KeyState::Pressed,
+                    offset: ms(1)
+                },
+                InputEvent {
+                    key: Key::Space,
+                    state: KeyState::Released,
+                    offset: ms(3)
+                },
+                InputEvent {
+                    key: Key::Space,
+                    state: KeyState::Pressed,
+                    offset: ms(4)
+                },
+            ]
+        );
+
+        // Merging moves the events of the next frame in place
+        state.relax_and_merge(&mut next);
+        assert_eq!(
+            state.events(),
+            &[InputEvent {
+                key: Key::Left,
+                state: KeyState::Tap,
+                offset: ms(5)
+            }]
+        );
+        assert!(next.events.is_empty());
+
+        state.relax_and_merge(&mut next);
+        assert!(state.events.is_empty());
+
+        for i in 0..EVENT_LOG_CAP + 1 {
+            state.record(Key::Up, KeyState::Tap, ms(i as u64));
+        }
+        assert_eq!(state.events.len(), EVENT_LOG_CAP);
+        assert!(state.events.overflowed());
+        assert_eq!(state.events().last().map(|e| e.offset), Some(ms(31)));
+
+        state.relax_and_merge(&mut next);
+        assert!(state.events.overflowed().not());
+    }
+
     #[test]
     fn test_convert() {
         for i in 0..256 {
@@ -1,4 +1,5 @@
 pub mod alloc;
+pub mod assets;
 pub mod draw;
 pub mod ffi;
 pub mod game;
@@ -11,4 +12,36 @@ pub struct Config {
     pub resolution: (u32, u32),
     pub logic_fps: u64,
     pub scale: u8,
+    pub frame_mem_budget: MemBudget,
+    pub max_frames_in_flight: FramesInFlight,
+}
+
+/// Limit for the bytes allocated from the frame arena during a single frame,
+/// and what to do when a frame goes over it. The warnings of `Warn` are
+/// reported when the runtime exits, along with the peak memory usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemBudget {
+    Unlimited,
+    Warn(usize),
+    Die(usize),
+}
+
+/// Limit for the frames allocated but not yet retired: the one being built by
+/// the logic, the one waiting to be rendered and the one being rendered. What
+/// to do when the logic would go over it, e.g. because the renderer is
+/// stalled by a minimized window or a debugger:
+///
+/// - `Block` waits for the renderer to catch up
+/// - `DropOldest` takes back the frame the renderer hasn't picked up yet,
+///   and waits if there isn't one
+/// - `Die` ends the app
+///
+/// The renderer holds on to the frame it's rendering until it gets a new
+/// one, so the limit must be at least 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramesInFlight {
+    Unlimited,
+    Block(usize),
+    DropOldest(usize),
+    Die(usize),
 }
@@ -1,13 +1,15 @@
 use std::{
     collections::VecDeque,
+    fmt::{self, Display},
     ops::Not,
-    sync::atomic::{AtomicUsize, Ordering},
-    thread,
-    time::Duration,
+    sync::atomic::Ordering,
 };
 
-use arena::{Arena, SharedAllocState};
-use bang_core::alloc::Mem;
+pub use arena::SharedAllocState;
+use arena::{Arena, MemoryUsage};
+use bang_core::{FramesInFlight, MemBudget, alloc::Mem};
+
+use crate::die_now;
 
 #[derive(Debug)]
 #[repr(C)]
@@ -16,18 +18,22 @@ pub struct AllocRetirer<'l> {
 }
 
 impl<'l> AllocRetirer<'l> {
-    pub fn retire_up_to(&self, seq: usize) {
-        self.shared
-            .retired_seq_up_to
-            .fetch_max(seq, Ordering::Release);
-    }
-
-    pub fn retire_early(&self, seq: usize) {
-        let _ = self.shared.retired_seq_early.compare_exchange(
-            0,
-            seq,
-            Ordering::Release,
-            Ordering::Relaxed,
+    pub fn retire_up_to(&self, seq: u64) {
+        self.shared.retire_up_to(seq);
+    }
+
+    pub fn retire_early(&self, seq: u64) {
+        self.shared.retire_early(seq);
+    }
+
+    /// Panics if the frame has been retired, and its arena might have been
+    /// reset and reused.
+    #[cfg(feature = "debug_alloc")]
+    pub fn assert_not_retired(&self, seq: u64) {
+        let retired_early = self.shared.retired_seq_early.load(Ordering::Acquire);
+        assert!(
+            seq > self.shared.retired_seq() && seq != retired_early,
+            "Frame {seq} is used after it has been retired"
         );
     }
 }
@@ -40,30 +46,102 @@ pub struct AllocCleanup<'l> {
 
 impl<'l> AllocCleanup<'l> {
     pub fn cleanup(&self) {
-        self.shared
-            .retired_seq_up_to
-            .fetch_max(usize::MAX, Ordering::Release);
+        self.shared.retire_up_to(u64::MAX);
+    }
+}
+
+/// The budget warnings kept for the report, at most; the rest are counted
+const MAX_BUDGET_WARNINGS: usize = 16;
+
+/// Frame memory statistics, measured as the frames are retired.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocStats {
+    /// Memory usage of the most recently retired frame
+    pub last_frame: MemoryUsage,
+    /// Most bytes allocated during a single frame
+    pub peak_content_bytes: usize,
+    /// Most memory reserved by a single frame arena, including overhead
+    pub peak_total_bytes: usize,
+    pub free_arenas: usize,
+    pub in_use_arenas: usize,
+    /// Frames that went over a `MemBudget::Warn` budget
+    pub over_budget_frames: usize,
+    /// Times a frame went over a `MemBudget::Warn` budget after frames that
+    /// didn't
+    pub budget_warnings: usize,
+}
+
+/// A frame that went over a `MemBudget::Warn` budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetWarning {
+    pub seq: u64,
+    pub content_bytes: usize,
+    pub budget: usize,
+}
+
+impl Display for BudgetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Frame {} allocated {} bytes, over the budget of {} bytes",
+            self.seq, self.content_bytes, self.budget
+        )
+    }
+}
+
+/// The frame memory stats and budget warnings, reported when the runtime
+/// exits. Printing the warnings as they happen would write over the TUI.
+#[derive(Debug, Clone, Default)]
+pub struct AllocReport {
+    pub stats: AllocStats,
+    /// The first `MAX_BUDGET_WARNINGS` warnings
+    pub warnings: Vec<BudgetWarning>,
+}
+
+impl Display for AllocReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {warning}")?;
+        }
+        let unlisted = self.stats.budget_warnings - self.warnings.len();
+        if unlisted > 0 {
+            writeln!(f, "Warning: went over the budget {unlisted} more times")?;
+        }
+        write!(
+            f,
+            "Frame memory: peak of {} bytes allocated, {} bytes reserved",
+            self.stats.peak_content_bytes, self.stats.peak_total_bytes
+        )
     }
 }
 
 #[derive(Debug)]
 #[repr(C)]
 pub struct AllocManager<'l> {
-    alloc_seq: usize,
     shared: &'l SharedAllocState,
     free_pool: Vec<Arena>,
     in_use: VecDeque<Arena>,
+    budget: MemBudget,
+    /// Whether the last retired frame went over a `MemBudget::Warn` budget
+    over_budget: bool,
+    warnings: Vec<BudgetWarning>,
+    max_in_flight: FramesInFlight,
+    stats: AllocStats,
 }
 
 pub fn make_alloc_tools<'l>(
-    shared: &'l mut SharedAllocState,
+    shared: &'l SharedAllocState,
 ) -> (AllocManager<'l>, AllocRetirer<'l>, AllocCleanup<'l>) {
     (
         AllocManager {
-            alloc_seq: 0,
             shared,
             free_pool: Vec::new(),
             in_use: VecDeque::new(),
+            budget: MemBudget::Unlimited,
+            over_budget: false,
+            warnings: Vec::new(),
+            max_in_flight: FramesInFlight::Unlimited,
+            stats: AllocStats::default(),
         },
         AllocRetirer { shared },
         AllocCleanup { shared },
@@ -71,31 +149,137 @@ pub fn make_alloc_tools<'l>(
 }
 
 impl<'l> AllocManager<'l> {
+    pub fn set_budget(&mut self, budget: MemBudget) {
+        self.budget = budget;
+    }
+
+    pub fn set_max_in_flight(&mut self, max_in_flight: FramesInFlight) {
+        match max_in_flight {
+            FramesInFlight::Block(limit)
+            | FramesInFlight::DropOldest(limit)
+            | FramesInFlight::Die(limit)
+                if limit < 2 =>
+            {
+                die_now!("The limit of frames in flight must be at least 2, got {limit}")
+            }
+            _ => self.max_in_flight = max_in_flight,
+        }
+    }
+
+    fn in_flight_limit(&self) -> usize {
+        match self.max_in_flight {
+            FramesInFlight::Unlimited => usize::MAX,
+            FramesInFlight::Block(limit)
+            | FramesInFlight::DropOldest(limit)
+            | FramesInFlight::Die(limit) => limit,
+        }
+    }
+
+    /// Whether getting a new frame allocation would go over the limit of
+    /// frames in flight, given the frames retired so far.
+    pub fn is_at_limit(&mut self) -> bool {
+        self.process_retired();
+        self.in_use.len() >= self.in_flight_limit()
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        AllocStats {
+            free_arenas: self.free_pool.len(),
+            in_use_arenas: self.in_use.len(),
+            ..self.stats
+        }
+    }
+
+    pub fn report(&self) -> AllocReport {
+        AllocReport {
+            stats: self.stats(),
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    /// Measures the memory usage of the frame before returning its arena to
+    /// the pool. The frame is done with, so nothing borrows the arena.
+    fn retire(&mut self, mut arena: Arena) {
+        let usage = arena.memory_usage();
+        let seq = arena.alloc_seq;
+        let content = usage.content_bytes;
+        self.stats.last_frame = usage;
+        self.stats.peak_content_bytes = self.stats.peak_content_bytes.max(content);
+        self.stats.peak_total_bytes = self.stats.peak_total_bytes.max(usage.total_bytes());
+        match self.budget {
+            MemBudget::Unlimited => (),
+            // Warns when going over the budget, but not again for every frame
+            // that stays over it, to avoid flooding the report
+            MemBudget::Warn(budget) => {
+                let over_budget = content > budget;
+                if over_budget {
+                    self.stats.over_budget_frames += 1;
+                }
+                if over_budget && self.over_budget.not() {
+                    self.stats.budget_warnings += 1;
+                    if self.warnings.len() < MAX_BUDGET_WARNINGS {
+                        self.warnings.push(BudgetWarning {
+                            seq,
+                            content_bytes: content,
+                            budget,
+                        });
+                    }
+                }
+                self.over_budget = over_budget;
+            }
+            MemBudget::Die(budget) if content > budget => {
+                die_now!("Frame {seq} allocated {content} bytes, over the budget of {budget} bytes")
+            }
+            MemBudget::Die(_) => (),
+        }
+        self.free_pool.push(arena);
+    }
+
     pub fn wait_until_cleanup(&mut self) {
-        while self.in_use.is_empty().not() {
-            thread::sleep(Duration::from_millis(1));
+        let shared = self.shared;
+        shared.park_until(|| {
             self.process_retired();
+            self.in_use.is_empty()
+        });
+    }
+
+    /// Waits for the renderer to retire frames until there's room for a new
+    /// one, or dies, depending on the policy. Taking back the unrendered frame
+    /// for `DropOldest` is up to the caller, as it's owned by `DrawSender`.
+    fn wait_for_room(&mut self) {
+        if self.is_at_limit().not() {
+            return;
+        }
+        let limit = self.in_flight_limit();
+        if let FramesInFlight::Die(_) = self.max_in_flight {
+            let in_use: Vec<_> = self.in_use.iter().map(|arena| arena.alloc_seq).collect();
+            let retired = self.shared.retired_seq();
+            die_now!(
+                "Over the limit of {limit} frames in flight: frames {in_use:?} are not retired, and the renderer has retired up to frame {retired}. Is the renderer stalled?"
+            )
         }
+        let shared = self.shared;
+        shared.park_until(|| self.is_at_limit().not());
     }
 
-    pub fn retire_single(&mut self, seq: usize) {
+    pub fn retire_single(&mut self, seq: u64) {
         self.in_use
             .binary_search_by_key(&seq, |alloc| alloc.alloc_seq)
             .map(|early_idx| {
                 let retired = self.in_use.remove(early_idx).expect("UNREACHABLE");
-                self.free_pool.push(retired);
+                self.retire(retired);
             })
             .unwrap_or(());
     }
 
     fn process_retired(&mut self) {
-        let retired_up_to = self.shared.retired_seq_up_to.load(Ordering::SeqCst);
+        let retired_up_to = self.shared.retired_seq();
         let retired_early = self.shared.retired_seq_early.swap(0, Ordering::SeqCst);
         while let Some(alloc) = self.in_use.front()
             && alloc.alloc_seq <= retired_up_to
         {
             let retired = self.in_use.pop_front().expect("UNREACHABLE");
-            self.free_pool.push(retired);
+            self.retire(retired);
         }
         if retired_early > 0 {
             self.retire_single(retired_early);
@@ -103,30 +287,32 @@ impl<'l> AllocManager<'l> {
     }
 
     pub fn get_alloc<'f>(&'f mut self) -> Mem<'f> {
-        self.process_retired();
+        self.wait_for_room();
         let arena_container = self.free_pool.pop().unwrap_or_default();
-        self.alloc_seq += 1;
+        // The sequence is shared with `Managed`, so its frees and reallocations
+        // are retired together with the frame they happened during
+        let seq = self.shared.next_seq();
         self.in_use.push_back(arena_container);
         let arena = self.in_use.back_mut().expect("UNREACHABLE");
-        Mem {
-            alloc_seq: self.alloc_seq,
-            arena: arena.fresh_arena(self.alloc_seq),
-        }
+        Mem::new(arena.fresh_arena(seq))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{thread, time::Duration};
+
     use super::*;
 
     #[test]
     fn test_alloc_manager() {
-        let mut shared = SharedAllocState::default();
-        let (mut manager, retirer, cleanup) = make_alloc_tools(&mut shared);
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, cleanup) = make_alloc_tools(&shared);
 
         let alloc = manager.get_alloc();
         assert_eq!(alloc.alloc_seq, 1);
         assert_eq!(manager.in_use.len(), 1);
+        assert_eq!(shared.current_seq(), 1);
 
         let alloc = manager.get_alloc();
         assert_eq!(alloc.alloc_seq, 2);
@@ -179,4 +365,160 @@ mod tests {
         cleanup.cleanup();
         manager.wait_until_cleanup();
     }
+
+    #[test]
+    fn test_alloc_stats() {
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, _) = make_alloc_tools(&shared);
+        manager.set_budget(MemBudget::Warn(1000));
+
+        let mem = manager.get_alloc();
+        mem.arena.alloc_slice(&[0u32; 100]);
+        let mem = manager.get_alloc();
+        mem.arena.alloc_slice(&[0u32; 1000]);
+        let mem = manager.get_alloc();
+        mem.arena.alloc_slice(&[0u32; 10]);
+        retirer.retire_up_to(3);
+        manager.get_alloc();
+
+        let stats = manager.stats();
+        assert_eq!(stats.last_frame.content_bytes, 10 * size_of::<u32>());
+        assert_eq!(stats.peak_content_bytes, 1000 * size_of::<u32>());
+        assert!(stats.peak_total_bytes >= stats.peak_content_bytes);
+        assert_eq!(stats.free_arenas, 2);
+        assert_eq!(stats.in_use_arenas, 1);
+    }
+
+    #[test]
+    fn test_alloc_budget_warn() {
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, _) = make_alloc_tools(&shared);
+        manager.set_budget(MemBudget::Warn(1000));
+
+        let mut over_budget = Vec::new();
+        for (seq, len) in [(1, 2000), (2, 3000), (3, 100), (4, 2000)] {
+            let mem = manager.get_alloc();
+            mem.arena.alloc_slice(&vec![0u8; len]);
+            retirer.retire_up_to(seq);
+            manager.process_retired();
+            over_budget.push(manager.over_budget);
+        }
+        assert_eq!(over_budget, [true, true, false, true]);
+
+        // Warns on frames 1 and 4, going over the budget
+        let report = manager.report();
+        let warned: Vec<_> = report.warnings.iter().map(|w| w.seq).collect();
+        assert_eq!(warned, [1, 4]);
+        assert_eq!(report.stats.budget_warnings, 2);
+        assert_eq!(report.stats.over_budget_frames, 3);
+        assert_eq!(
+            report.warnings[0],
+            BudgetWarning {
+                seq: 1,
+                content_bytes: 2000,
+                budget: 1000
+            }
+        );
+        assert!(
+            report.to_string().starts_with(
+                "Warning: Frame 1 allocated 2000 bytes, over the budget of 1000 bytes\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_alloc_budget_warn_limit() {
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, _) = make_alloc_tools(&shared);
+        manager.set_budget(MemBudget::Warn(1000));
+
+        // Every other frame goes over the budget
+        for seq in 1..=2 * (MAX_BUDGET_WARNINGS as u64 + 2) {
+            let mem = manager.get_alloc();
+            let len = if seq % 2 == 1 { 2000 } else { 100 };
+            mem.arena.alloc_slice(&vec![0u8; len]);
+            retirer.retire_up_to(seq);
+            manager.process_retired();
+        }
+        let report = manager.report();
+        assert_eq!(report.warnings.len(), MAX_BUDGET_WARNINGS);
+        assert_eq!(report.stats.budget_warnings, MAX_BUDGET_WARNINGS + 2);
+        assert!(
+            report
+                .to_string()
+                .contains("Warning: went over the budget 2 more times\n")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "over the budget")]
+    fn test_alloc_budget_die() {
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, _) = make_alloc_tools(&shared);
+        manager.set_budget(MemBudget::Die(1000));
+
+        let mem = manager.get_alloc();
+        mem.arena.alloc_slice(&[0u8; 2000]);
+        retirer.retire_up_to(1);
+        manager.get_alloc();
+    }
+
+    #[test]
+    fn test_max_in_flight_block() {
+        let shared = SharedAllocState::default();
+        let (mut manager, retirer, _) = make_alloc_tools(&shared);
+        manager.set_max_in_flight(FramesInFlight::Block(2));
+
+        manager.get_alloc();
+        manager.get_alloc();
+        assert!(manager.is_at_limit());
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                retirer.retire_up_to(1);
+            });
+            // Blocks until the frame 1 is retired
+            let alloc = manager.get_alloc();
+            assert_eq!(alloc.alloc_seq, 3);
+        });
+        assert_eq!(manager.in_use.len(), 2);
+        assert_eq!(shared.retired_seq(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Over the limit of 2 frames in flight: frames [1, 2]")]
+    fn test_max_in_flight_die() {
+        let shared = SharedAllocState::default();
+        let (mut manager, _, _) = make_alloc_tools(&shared);
+        manager.set_max_in_flight(FramesInFlight::Die(2));
+
+        manager.get_alloc();
+        manager.get_alloc();
+        manager.get_alloc();
+    }
+
+    #[test]
+    #[should_panic(expected = "must be at least 2")]
+    fn test_max_in_flight_too_low() {
+        let shared = SharedAllocState::default();
+        let (mut manager, _, _) = make_alloc_tools(&shared);
+        manager.set_max_in_flight(FramesInFlight::Block(1));
+    }
+
+    #[test]
+    fn test_wait_until_cleanup() {
+        let shared = SharedAllocState::default();
+        let (mut manager, _, cleanup) = make_alloc_tools(&shared);
+        manager.get_alloc();
+        manager.get_alloc();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                cleanup.cleanup();
+            });
+            manager.wait_until_cleanup();
+        });
+        assert_eq!(manager.in_use.len(), 0);
+        assert_eq!(manager.free_pool.len(), 2);
+    }
 }
@@ -0,0 +1,202 @@
+//! Reading assets by their paths, e.g. `assets/paltex/bubu.paltex`.
+//!
+//! The assets are looked up in order:
+//!
+//! 1. The assets embedded in the executable with `bang_core::include_assets!`
+//!    and registered with `embed`.
+//! 2. The asset pack: `assets.bpak` next to the executable (or in
+//!    `../Resources` of a macOS app bundle), or the pack file at `$BANG_PACK`.
+//! 3. In dev mode, the filesystem, relative to the working directory. Debug
+//!    builds are always in dev mode; release builds when `$BANG_DEV` is set.
+//!    Otherwise a missing asset is an error, so that a release build doesn't
+//!    silently depend on the working directory.
+
+use std::{
+    borrow::Cow,
+    env::{current_exe, var_os},
+    ops::Not,
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use bang_core::{alloc::Mem, assets::EmbeddedAsset, ffi::RtCtx};
+
+use crate::{die, die_now, error::OrDie, pack::Pack};
+
+/// The file name of the asset pack next to the executable
+pub const PACK_FILE: &str = "assets.bpak";
+/// Environment variable for the path of the asset pack, overriding the
+/// default location
+pub const PACK_ENV: &str = "BANG_PACK";
+/// Environment variable that enables dev mode in release builds, reading the
+/// assets missing from the pack from the working directory
+pub const DEV_ENV: &str = "BANG_DEV";
+
+static ASSETS: OnceLock<Assets> = OnceLock::new();
+
+struct Assets {
+    embedded: &'static [EmbeddedAsset],
+    pack: Option<(PathBuf, Pack<Vec<u8>>)>,
+    dev_mode: bool,
+}
+
+impl Assets {
+    /// Opens the asset pack, if there's one.
+    fn open() -> Self {
+        let path = match var_os(PACK_ENV) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => current_exe().ok().and_then(|exe| {
+                let dir = exe.parent()?;
+                [
+                    dir.join(PACK_FILE),
+                    dir.join("../Resources").join(PACK_FILE),
+                ]
+                .into_iter()
+                .find(|path| path.is_file())
+            }),
+        };
+        let pack = path.map(|path| {
+            let bytes = std::fs::read(&path).or_(die!("Failed to read asset pack {:?}", path));
+            let pack = Pack::new(bytes).or_(die!("Failed to open asset pack {:?}", path));
+            (path, pack)
+        });
+        Self {
+            embedded: &[],
+            pack,
+            dev_mode: cfg!(debug_assertions) || var_os(DEV_ENV).is_some(),
+        }
+    }
+
+    fn read(&self, path: &str) -> Cow<'_, [u8]> {
+        if let Some(asset) = self.embedded.iter().find(|asset| asset.path == path) {
+            return Cow::Borrowed(asset.bytes);
+        }
+        if let Some((pack_path, pack)) = &self.pack
+            && let Some(data) = pack.get(path)
+        {
+            return data.or_(die!("Failed to read asset {} from {:?}", path, pack_path));
+        }
+        if self.dev_mode.not() {
+            match &self.pack {
+                Some((pack_path, _)) => die_now!(
+                    "Asset {} isn't in {:?} (set {}=1 to read it from the working directory)",
+                    path,
+                    pack_path,
+                    DEV_ENV
+                ),
+                None => die_now!(
+                    "Asset {} isn't embedded, and there's no {} (set {}=1 to read it from the working directory)",
+                    path,
+                    PACK_FILE,
+                    DEV_ENV
+                ),
+            }
+        }
+        Cow::Owned(std::fs::read(path).or_(die!("Failed to read asset {}", path)))
+    }
+}
+
+/// Registers the assets embedded in the executable, for a self-contained
+/// executable. Call it before starting the runtime.
+pub fn embed(assets: &'static [EmbeddedAsset]) {
+    let mut opened = Assets::open();
+    opened.embedded = assets;
+    if ASSETS.set(opened).is_err() {
+        die_now!("Embed the assets before reading any");
+    }
+}
+
+/// Reads an asset from the embedded ones, the asset pack, or in dev mode,
+/// from the filesystem. The pack is opened on the first read.
+pub fn read(path: &str) -> Cow<'static, [u8]> {
+    ASSETS.get_or_init(Assets::open).read(path)
+}
+
+/// Loads a palette file for `RtCtx::load_palette`, the same for every
+/// runtime.
+pub fn load_palette<'f>(_: &mut RtCtx, path: &str, mem: &mut Mem<'f>) -> &'f [[u8; 4]] {
+    let bytes = read(path);
+    let palette = paltex::decode_palette(&bytes).or_(die!("Failed to decode palette {}", path));
+    mem.from_iter(palette.iter().map(|color| color.to_rgba_u8()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null_mut;
+
+    use arena::Arena;
+    use bang_core::ffi::{RtKind, SendableErasedPtr};
+    use paltex::Color;
+
+    use super::*;
+    use crate::pack::{PackEntry, write_pack};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_palette() {
+        let palette = [[1, 2, 3, 255], [255, 255, 255, 255]].map(Color::from_rgba_u8);
+        let mut encoded = Vec::new();
+        paltex::encode_palette(&palette, &mut encoded);
+        let path = std::env::temp_dir().join("bang_test_load_palette.palette");
+        std::fs::write(&path, encoded).unwrap();
+
+        let mut arenac = Arena::default();
+        let mut mem = Mem::new(arenac.fresh_arena(1));
+        let mut ctx = RtCtx {
+            frame: 0,
+            rt_kind: RtKind::Test,
+            load_textures_ptr: crate::runtime::tests::load_textures,
+            load_palette_ptr: load_palette,
+            rt_state: SendableErasedPtr(null_mut()),
+            alloc_state: Box::leak(Box::default()),
+        };
+        let loaded = ctx.load_palette(path.to_str().unwrap(), &mut mem);
+        assert_eq!(loaded, [[0, 0, 0, 0], [1, 2, 3, 255], [255, 255, 255, 255]]);
+    }
+
+    fn assets(dev_mode: bool) -> Assets {
+        let mut output = Vec::new();
+        write_pack(
+            &[PackEntry {
+                path: "assets/a.txt".to_owned(),
+                data: b"packed".to_vec(),
+                compress: false,
+            }],
+            &mut output,
+        );
+        Assets {
+            embedded: &[],
+            pack: Some((PACK_FILE.into(), Pack::new(output).unwrap())),
+            dev_mode,
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_read_pack_first() {
+        let path = std::env::temp_dir().join("bang_test_read_pack_first.txt");
+        std::fs::write(&path, b"from file").unwrap();
+        let path = path.to_str().unwrap();
+
+        let assets = assets(true);
+        assert_eq!(&*assets.read("assets/a.txt"), b"packed");
+        // Falls back to the filesystem in dev mode
+        assert_eq!(&*assets.read(path), b"from file");
+    }
+
+    #[test]
+    fn test_read_embedded_first() {
+        let mut assets = assets(false);
+        assets.embedded = &[EmbeddedAsset {
+            path: "assets/a.txt",
+            bytes: b"embedded",
+        }];
+        assert_eq!(&*assets.read("assets/a.txt"), b"embedded");
+    }
+
+    #[test]
+    #[should_panic(expected = "Asset assets/b.txt isn't in \"assets.bpak\" (set BANG_DEV=1")]
+    fn test_read_no_fallback() {
+        assets(false).read("assets/b.txt");
+    }
+}
@@ -27,7 +27,7 @@ impl<'l> Default for SharedDrawState<'l> {
 pub struct DrawSender<'l> {
     shared: &'l SharedDrawState<'l>,
     retirer: &'l AllocRetirer<'l>,
-    sent_alloc_seq: usize,
+    sent_alloc_seq: u64,
 }
 
 #[derive(Debug)]
@@ -35,6 +35,9 @@ pub struct DrawReceiver<'l> {
     shared: &'l SharedDrawState<'l>,
     retirer: &'l AllocRetirer<'l>,
     fresh: &'l DrawFrame<'l>,
+    /// The sequence of `fresh` as it was when it was received
+    #[cfg(feature = "debug_alloc")]
+    fresh_seq: u64,
 }
 
 pub fn make_draw_tools<'l>(
@@ -52,6 +55,8 @@ pub fn make_draw_tools<'l>(
         shared,
         retirer,
         fresh: &DRAW_FRAME_DUMMY,
+        #[cfg(feature = "debug_alloc")]
+        fresh_seq: 0,
     };
     (sender, receiver)
 }
@@ -78,6 +83,18 @@ impl<'l> DrawSender<'l> {
             self.retirer.retire_early(prev_alloc_seq);
         }
     }
+
+    /// Takes back the last sent frame if the renderer hasn't picked it up
+    /// yet, and retires it. The renderer keeps showing its current frame.
+    /// Returns whether there was such a frame.
+    pub fn recall_unrendered(&mut self) -> bool {
+        let unrendered = self.shared.fresh.swap(null_mut(), Ordering::Acquire);
+        if unrendered.is_null() {
+            return false;
+        }
+        self.retirer.retire_early(self.sent_alloc_seq);
+        true
+    }
 }
 
 impl<'l> DrawReceiver<'l> {
@@ -87,10 +104,29 @@ impl<'l> DrawReceiver<'l> {
             let retired_seq = self.fresh.alloc_seq;
             self.fresh = unsafe { &mut *freshest };
             self.retirer.retire_up_to(retired_seq);
+            #[cfg(feature = "debug_alloc")]
+            {
+                self.fresh_seq = self.fresh.alloc_seq;
+            }
         }
+        #[cfg(feature = "debug_alloc")]
+        self.assert_fresh_live();
         self.fresh
     }
 
+    /// The frame is allocated from its own arena, so if the arena had been
+    /// reset, its sequence number would read as poison.
+    #[cfg(feature = "debug_alloc")]
+    fn assert_fresh_live(&self) {
+        assert_eq!(
+            self.fresh.alloc_seq, self.fresh_seq,
+            "The frame being rendered has been overwritten"
+        );
+        if self.fresh_seq > 0 {
+            self.retirer.assert_not_retired(self.fresh_seq);
+        }
+    }
+
     pub fn has_fresh(&self) -> bool {
         self.shared.fresh.load(Ordering::Acquire).is_null().not()
     }
@@ -98,7 +134,7 @@ impl<'l> DrawReceiver<'l> {
 
 #[cfg(test)]
 mod tests {
-    use bang_core::draw::Cmd;
+    use bang_core::{alloc::Managed, draw::Cmd};
 
     use super::*;
     use crate::alloc::{SharedAllocState, make_alloc_tools};
@@ -106,8 +142,8 @@ mod tests {
     #[test]
     fn test_draw() {
         let mut shared_draw = SharedDrawState::default();
-        let mut shared_alloc = SharedAllocState::default();
-        let (mut manager, mut retirer, cleanup) = make_alloc_tools(&mut shared_alloc);
+        let shared_alloc = SharedAllocState::default();
+        let (mut manager, mut retirer, cleanup) = make_alloc_tools(&shared_alloc);
         let (mut sender, mut receiver) = make_draw_tools(&mut shared_draw, &mut retirer);
 
         let mut alloc = manager.get_alloc(); // Frame 1
@@ -118,7 +154,7 @@ mod tests {
         let fresh = receiver.get_fresh();
         assert_eq!(fresh.alloc_seq, 1);
         assert_eq!(fresh.cmds.len(), 1);
-        assert!(matches!(fresh.cmds[0], Cmd::DrawSQuads { .. }));
+        assert!(matches!(fresh.cmds[0], Cmd::DrawDummies { .. }));
 
         let fresh = receiver.get_fresh(); // The same as last time
         assert_eq!(fresh.alloc_seq, 1);
@@ -137,4 +173,100 @@ mod tests {
         cleanup.cleanup();
         manager.wait_until_cleanup();
     }
+
+    #[test]
+    fn test_recall_unrendered() {
+        let mut shared_draw = SharedDrawState::default();
+        let shared_alloc = SharedAllocState::default();
+        let (mut manager, mut retirer, cleanup) = make_alloc_tools(&shared_alloc);
+        let (mut sender, mut receiver) = make_draw_tools(&mut shared_draw, &mut retirer);
+
+        let mut alloc = manager.get_alloc(); // Frame 1
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+        assert_eq!(receiver.get_fresh().alloc_seq, 1);
+        assert!(sender.recall_unrendered().not()); // Already picked up
+
+        let mut alloc = manager.get_alloc(); // Frame 2
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+        assert!(sender.recall_unrendered());
+        assert!(receiver.has_fresh().not());
+        assert_eq!(receiver.get_fresh().alloc_seq, 1);
+
+        assert!(manager.is_at_limit().not()); // Processes the retirements
+        assert_eq!(manager.stats().in_use_arenas, 1); // Only frame 1 remains
+
+        cleanup.cleanup();
+        manager.wait_until_cleanup();
+    }
+
+    #[test]
+    fn test_managed_grow_retirement() {
+        let mut shared_draw = SharedDrawState::default();
+        let shared_alloc = SharedAllocState::default();
+        let (mut manager, mut retirer, cleanup) = make_alloc_tools(&shared_alloc);
+        let (mut sender, mut receiver) = make_draw_tools(&mut shared_draw, &mut retirer);
+        let mut managed = Managed::<u32>::new(&shared_alloc);
+        let reader = managed.reader();
+
+        let mut alloc = manager.get_alloc(); // Frame 1
+        let first = managed.alloc(1);
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+        assert_eq!(receiver.get_fresh().alloc_seq, 1);
+
+        let mut alloc = manager.get_alloc(); // Frame 2 outgrows the store
+        while managed.outgrown_stores() == 0 {
+            managed.alloc(2);
+        }
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+
+        let mut alloc = manager.get_alloc(); // Frame 3
+        managed.alloc(3);
+        // The renderer is still on frame 1, and might be reading the old store
+        assert_eq!(managed.outgrown_stores(), 1);
+        // Safety: the renderer holds frame 1, where `first` was allocated
+        assert_eq!(unsafe { reader.get(first) }, Some(1));
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame); // Retire early frame 2
+
+        assert_eq!(receiver.get_fresh().alloc_seq, 3); // Retire frame 1
+        managed.alloc(3);
+        // Frame 2 was never picked up by the renderer, but its retirement
+        // only applies to its arena
+        assert_eq!(managed.outgrown_stores(), 1);
+
+        let mut alloc = manager.get_alloc(); // Frame 4
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+        assert_eq!(receiver.get_fresh().alloc_seq, 4); // Retire frame 3
+        managed.alloc(4);
+        assert_eq!(managed.outgrown_stores(), 0);
+        // Safety: the renderer holds frame 4, and `first` is still live in it
+        assert_eq!(unsafe { reader.get(first) }, Some(1));
+
+        cleanup.cleanup();
+        manager.wait_until_cleanup();
+    }
+
+    #[test]
+    #[cfg(feature = "debug_alloc")]
+    #[should_panic(expected = "Frame 1 is used after it has been retired")]
+    fn test_debug_use_after_retire() {
+        let mut shared_draw = SharedDrawState::default();
+        let shared_alloc = SharedAllocState::default();
+        let (mut manager, mut retirer, _) = make_alloc_tools(&shared_alloc);
+        let (mut sender, mut receiver) = make_draw_tools(&mut shared_draw, &mut retirer);
+
+        let mut alloc = manager.get_alloc(); // Frame 1
+        let mut frame = DrawFrame::debug_dummies(&[(1.0, 2.0)], &mut alloc);
+        sender.send_to_renderer(&mut frame);
+        assert_eq!(receiver.get_fresh().alloc_seq, 1);
+
+        // A bug retiring the frame the renderer is still on
+        shared_alloc.retire_up_to(1);
+        receiver.get_fresh();
+    }
 }
@@ -32,14 +32,18 @@ impl Ender {
         (self.notify_end)(self);
     }
 
-    extern "C" fn sigint_handler(_sig: i32) {
+    extern "C" fn signal_handler(_sig: i32) {
         let should_end = unsafe { &*SHOULD_END.load(Ordering::Acquire) };
         should_end.store(true, Ordering::Release);
     }
 
-    #[cfg(target_os = "macos")]
+    /// Makes SIGINT and SIGTERM end the app gracefully, letting the runtime
+    /// clean up after itself.
+    #[cfg(unix)]
     pub fn install_global_signal_handler(&self) {
+        // Same on Linux and macOS
         pub const SIGINT: i32 = 2;
+        pub const SIGTERM: i32 = 15;
 
         SHOULD_END.store(
             &self.should_end as *const AtomicBool as *mut AtomicBool,
@@ -47,7 +51,8 @@ impl Ender {
         );
 
         unsafe {
-            signal(SIGINT, Ender::sigint_handler);
+            signal(SIGINT, Ender::signal_handler);
+            signal(SIGTERM, Ender::signal_handler);
         }
     }
 }
@@ -76,7 +81,7 @@ mod tests {
         assert!(ender.should_end().not());
         ender.install_global_signal_handler();
 
-        Ender::sigint_handler(2);
+        Ender::signal_handler(2);
 
         assert!(ender.should_end());
         assert!(CALLED.load(Ordering::Acquire).not());
@@ -8,7 +8,7 @@ pub struct InputConsumer<'l> {
     consuming: Box<InputState>,
 }
 
-pub fn make_input_tools(shared: &mut SharedInputState) -> (InputGatherer, InputConsumer) {
+pub fn make_input_tools(shared: &mut SharedInputState) -> (InputGatherer<'_>, InputConsumer<'_>) {
     let shared = &*shared; // Take as unique, but make shared to prevent other references
     (
         InputGatherer { shared },
@@ -25,6 +25,7 @@ impl<'l> InputConsumer<'l> {
             let state = &mut **self.shared.gather.lock().expect("UNREACHABLE");
             *self.consuming = state.current.clone();
             state.current.relax_and_merge(&mut state.next);
+            state.frame_start = state.deadline;
             state.deadline = next_deadline;
         }
         self.consuming.as_ref()
@@ -38,11 +39,13 @@ pub struct SharedInputState {
 
 impl Default for SharedInputState {
     fn default() -> Self {
+        let now = Instant::now();
         Self {
             gather: Mutex::new(Box::new(InnerSharedInputState {
                 current: InputState::new(),
                 next: InputState::new(),
-                deadline: Instant::now(),
+                frame_start: now,
+                deadline: now,
             })),
         }
     }
@@ -52,6 +55,9 @@ impl Default for SharedInputState {
 struct InnerSharedInputState {
     current: InputState,
     next: InputState,
+    /// Start of the frame gathered to `current`; the event log timestamps
+    /// of `current` are relative to this, and those of `next` to `deadline`.
+    frame_start: Instant,
     deadline: Instant,
 }
 
@@ -65,9 +71,11 @@ impl<'l> InputGatherer<'l> {
         let shared_state = &mut **self.shared.gather.lock().expect("UNREACHABLE");
 
         if timestamp > shared_state.deadline {
-            shared_state.next.update(key, state);
+            let offset = timestamp - shared_state.deadline;
+            shared_state.next.record(key, state, offset);
         } else {
-            shared_state.current.update(key, state);
+            let offset = timestamp.saturating_duration_since(shared_state.frame_start);
+            shared_state.current.record(key, state, offset);
         }
     }
 }
@@ -107,4 +115,48 @@ mod tests {
         assert_eq!(gathered.right, KeyState::Up);
         assert_eq!(gathered.space, KeyState::Down);
     }
+
+    #[test]
+    fn test_input_event_offsets() {
+        let mut shared = SharedInputState::default();
+        let (mut gatherer, mut consumer) = make_input_tools(&mut shared);
+        let ms = Duration::from_millis;
+
+        let start = Instant::now();
+        consumer.get_gathered(start);
+        consumer.get_gathered(start + ms(10)); // Frame from `start` to `start + 10ms`
+
+        gatherer.update(Key::Space, KeyState::Pressed, start + ms(2));
+        gatherer.update(Key::Space, KeyState::Released, start + ms(5));
+        gatherer.update(Key::Space, KeyState::Pressed, start + ms(7));
+        gatherer.update(Key::Up, KeyState::Pressed, start + ms(13)); // Belongs to the next frame
+
+        let gathered = consumer.get_gathered(start + ms(20));
+        assert_eq!(gathered.space, KeyState::Pressed);
+        let events: Vec<_> = gathered
+            .events()
+            .iter()
+            .map(|e| (e.key, e.state, e.offset))
+            .collect();
+        assert_eq!(
+            events,
+            &[
+                (Key::Space, KeyState::Pressed, ms(2)),
+                (Key::Space, KeyState::Released, ms(5)),
+                (Key::Space, KeyState::Pressed, ms(7)),
+            ]
+        );
+
+        let gathered = consumer.get_gathered(start + ms(30));
+        assert_eq!(gathered.up, KeyState::Pressed);
+        let events: Vec<_> = gathered
+            .events()
+            .iter()
+            .map(|e| (e.key, e.state, e.offset))
+            .collect();
+        assert_eq!(events, &[(Key::Up, KeyState::Pressed, ms(3))]);
+
+        let gathered = consumer.get_gathered(start + ms(40));
+        assert!(gathered.events().is_empty());
+    }
 }
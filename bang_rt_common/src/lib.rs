@@ -1,9 +1,11 @@
 pub mod alloc;
+pub mod assets;
 pub mod draw;
 pub mod end;
 pub mod error;
 pub mod input;
 pub mod load;
 pub mod logic_loop;
+pub mod pack;
 pub mod runtime;
 pub mod timer;
@@ -7,13 +7,13 @@ use bang_core::{
     alloc::Mem,
     draw::DrawFrame,
     ffi::{
-        Erased, FnInitRaw, FnUpdateRaw, LOGIC_INIT_SYM, LOGIC_UPDATE_SYM, Logic, LogicInitReturn,
-        RtCtx,
+        ALLOC_LAYOUT, Erased, FnAllocLayoutRaw, FnInitRaw, FnUpdateRaw, LOGIC_ALLOC_LAYOUT_SYM,
+        LOGIC_INIT_SYM, LOGIC_UPDATE_SYM, Logic, LogicInitReturn, RtCtx,
     },
     input::InputState,
 };
 
-use crate::{die, error::OrDie};
+use crate::{die, die_now, error::OrDie};
 
 unsafe extern "C" {
     safe fn dlopen(path: *const c_char, mode: c_int) -> Option<NonNull<c_void>>;
@@ -28,14 +28,41 @@ pub fn dyn_load_logic(lib: &CStr) -> DynLoadedLogic {
         .or_(die!("Failed to find symbol: {:?}", LOGIC_UPDATE_SYM));
     let init_ptr = dlsym(lib_ptr, LOGIC_INIT_SYM.as_ptr())
         .or_(die!("Failed to find symbol: {:?}", LOGIC_INIT_SYM));
+    let layout_ptr = dlsym(lib_ptr, LOGIC_ALLOC_LAYOUT_SYM.as_ptr()).or_(die!(
+        "The logic library doesn't export {:?}, so it was built against an older bang_core. Rebuild the logic library.",
+        LOGIC_ALLOC_LAYOUT_SYM
+    ));
     let update = unsafe { std::mem::transmute::<NonNull<c_void>, FnUpdateRaw>(update_ptr) };
     let init = unsafe { std::mem::transmute::<NonNull<c_void>, FnInitRaw>(init_ptr) };
+    let layout = unsafe { std::mem::transmute::<NonNull<c_void>, FnAllocLayoutRaw>(layout_ptr) };
+    check_alloc_layout(layout());
     DynLoadedLogic {
         update_raw_ptr: update,
         init_raw_ptr: init,
     }
 }
 
+/// Dies if the logic library lays out the arenas differently than the
+/// runtime, i.e. it was built with different features. They share the frame
+/// arenas, so a mismatch would corrupt them.
+fn check_alloc_layout(logic_layout: u32) {
+    if logic_layout == ALLOC_LAYOUT {
+        return;
+    }
+    let with = |layout: u32| {
+        if layout & arena::LAYOUT_DEBUG_ALLOC != 0 {
+            "with"
+        } else {
+            "without"
+        }
+    };
+    die_now!(
+        "The logic library was built {} the debug_alloc feature, but the runtime {} it. Build them with the same features, e.g. in the same cargo invocation.",
+        with(logic_layout),
+        with(ALLOC_LAYOUT)
+    );
+}
+
 pub struct DynLoadedLogic {
     update_raw_ptr: FnUpdateRaw,
     init_raw_ptr: FnInitRaw,
@@ -77,13 +104,17 @@ pub mod tests {
             frame: 0,
             rt_kind: RtKind::Test,
             load_textures_ptr: crate::runtime::tests::load_textures,
+            load_palette_ptr: crate::assets::load_palette,
             rt_state: SendableErasedPtr(null_mut()),
+            alloc_state: Box::leak(Box::default()),
         };
         let mut state = Erased;
         TestLogic.update_raw(&mut alloc, &input_state, &mut ctx, &raw mut state);
     }
 
+    // The test dylibs are built with `make test_dylib`, which targets macOS
     #[test]
+    #[cfg(target_os = "macos")]
     #[cfg_attr(miri, ignore)]
     fn test_load() {
         let mut arenac = Arena::default();
@@ -94,12 +125,21 @@ pub mod tests {
             frame: 0,
             rt_kind: RtKind::Test,
             load_textures_ptr: crate::runtime::tests::load_textures,
+            load_palette_ptr: crate::assets::load_palette,
             rt_state: SendableErasedPtr(null_mut()),
+            alloc_state: Box::leak(Box::default()),
         };
         let mut state = Erased;
         dyn_logic.update_raw(&mut alloc, &input_state, &mut ctx, &raw mut state);
     }
 
+    #[test]
+    #[should_panic(expected = "The logic library was built with")]
+    fn test_alloc_layout_mismatch() {
+        check_alloc_layout(ALLOC_LAYOUT);
+        check_alloc_layout(ALLOC_LAYOUT ^ arena::LAYOUT_DEBUG_ALLOC);
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     #[should_panic(expected = "Failed to load library")]
@@ -108,6 +148,7 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(target_os = "macos")]
     #[cfg_attr(miri, ignore)]
     #[should_panic(expected = "Failed to find symbol")]
     fn test_lib_missing_symbol() {
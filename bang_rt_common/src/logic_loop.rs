@@ -1,14 +1,18 @@
 use std::ops::Not;
 
 use bang_core::{
-    Config,
+    Config, FramesInFlight,
     alloc::Mem,
     ffi::{Erased, Logic, RtCtx, SendableErasedPtr},
     input::InputState,
 };
 
 use crate::{
-    alloc::AllocManager, draw::DrawSender, end::Ender, input::InputConsumer, timer::Timer,
+    alloc::{AllocManager, AllocReport},
+    draw::DrawSender,
+    end::Ender,
+    input::InputConsumer,
+    timer::Timer,
 };
 
 pub struct RunArgs<'l, L> {
@@ -36,11 +40,16 @@ fn with_frame_lifetime<'f, L: Logic>(
     rt_ctx.end_frame();
 }
 
-pub fn run<'l>(mut args: RunArgs<'l, impl Logic>) {
+pub fn run<'l>(mut args: RunArgs<'l, impl Logic>) -> AllocReport {
     let mut timer = Timer::new(args.config.logic_fps);
     while args.ender.should_end().not() {
         let next_deadline = timer.wait_until_next();
         let input = args.input_consumer.get_gathered(next_deadline);
+        if let FramesInFlight::DropOldest(_) = args.config.max_frames_in_flight
+            && args.alloc_manager.is_at_limit()
+        {
+            args.sender.recall_unrendered();
+        }
         let mut alloc = args.alloc_manager.get_alloc();
         with_frame_lifetime(
             &args.logic,
@@ -54,4 +63,5 @@ pub fn run<'l>(mut args: RunArgs<'l, impl Logic>) {
     // To ensure that notify_end gets called in case of should_end being set "silently" by a signal handler
     args.ender.soft_quit();
     args.alloc_manager.wait_until_cleanup();
+    args.alloc_manager.report()
 }
@@ -0,0 +1,559 @@
+//! Asset packs: the asset files of a game bundled into a single file, to ship
+//! next to the executable.
+//!
+//! A pack starts with a `PackHeader`, followed by the index: an `EntryHeader`
+//! and the UTF-8 path of each entry, sorted by the path. The stored data of
+//! the entries follows the index. Entries may be compressed with a simple
+//! LZ77 scheme, if it makes them smaller.
+
+use std::{borrow::Cow, fmt::Display};
+
+use bang_core::draw::AsBytes;
+use paltex::{crc32, decode_varint, encode_varint};
+
+/// Magic bytes that start a pack file
+pub const MAGIC: [u8; 4] = *b"BPAK";
+/// The format version written by `write_pack`
+pub const VERSION: u8 = 1;
+
+/// Entry flag: the stored data is compressed
+const FLAG_COMPRESSED: u8 = 0b0000_0001;
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct PackHeader {
+    pub magic: [u8; 4],
+    pub version: u8,
+    pub reserved: [u8; 3],
+    pub entry_count: u32,
+    /// CRC-32 of the index
+    pub index_crc: u32,
+}
+
+unsafe impl AsBytes for PackHeader {}
+
+/// Followed by `path_len` bytes of the path
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct EntryHeader {
+    /// Offset of the stored data from the start of the pack
+    pub offset: u32,
+    pub stored_len: u32,
+    /// Length of the data when uncompressed
+    pub len: u32,
+    /// CRC-32 of the uncompressed data
+    pub crc: u32,
+    pub flags: u8,
+    pub reserved: u8,
+    pub path_len: u16,
+}
+
+unsafe impl AsBytes for EntryHeader {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackError {
+    /// The input doesn't start with the magic bytes.
+    NotPack,
+    UnsupportedVersion(u8),
+    /// The input ends before the header or the index does.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// A checksum doesn't match: the pack is corrupted.
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// An entry path isn't valid UTF-8.
+    InvalidPath,
+    /// The index isn't sorted by path, or has the path twice.
+    UnsortedIndex(String),
+    /// The stored data of an entry extends past the end of the pack.
+    OutOfBounds(String),
+    /// The compressed data of an entry is invalid.
+    InvalidData(String),
+}
+
+impl Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackError::NotPack => write!(f, "not a pack file: no magic bytes"),
+            PackError::UnsupportedVersion(version) => {
+                write!(f, "unsupported pack version {version}")
+            }
+            PackError::Truncated { expected, actual } => {
+                write!(f, "truncated pack: expected {expected} bytes, got {actual}")
+            }
+            PackError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "corrupted pack: checksum is {actual:#010x}, expected {expected:#010x}"
+            ),
+            PackError::InvalidPath => write!(f, "pack entry path isn't valid UTF-8"),
+            PackError::UnsortedIndex(path) => {
+                write!(f, "pack index isn't sorted by path at {path:?}")
+            }
+            PackError::OutOfBounds(path) => {
+                write!(f, "pack entry {path:?} extends past the end of the pack")
+            }
+            PackError::InvalidData(path) => {
+                write!(f, "invalid compressed data in pack entry {path:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// An asset file to write into a pack
+pub struct PackEntry {
+    /// The path the asset is looked up with, e.g. `assets/paltex/bubu.paltex`
+    pub path: String,
+    pub data: Vec<u8>,
+    /// Compresses the data, if it makes it smaller
+    pub compress: bool,
+}
+
+/// An entry in the index of a pack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    offset: usize,
+    pub stored_len: usize,
+    /// Length of the data when uncompressed
+    pub len: usize,
+    crc: u32,
+    pub compressed: bool,
+}
+
+/// A pack read into memory, with its index parsed and validated. The data
+/// of the entries is validated when reading them.
+pub struct Pack<B> {
+    bytes: B,
+    entries: Vec<Entry>,
+}
+
+impl<B: AsRef<[u8]>> Pack<B> {
+    pub fn new(bytes: B) -> Result<Self, PackError> {
+        let input = bytes.as_ref();
+        let mut header = PackHeader::default();
+        let header_end = size_of::<PackHeader>();
+        if input.len() < header_end {
+            return Err(PackError::Truncated {
+                expected: header_end,
+                actual: input.len(),
+            });
+        }
+        header.as_bytes_mut().copy_from_slice(&input[..header_end]);
+
+        if header.magic != MAGIC {
+            return Err(PackError::NotPack);
+        }
+        if header.version != VERSION {
+            return Err(PackError::UnsupportedVersion(header.version));
+        }
+
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut pos = header_end;
+        for _ in 0..header.entry_count {
+            let mut entry = EntryHeader::default();
+            let entry_end = pos + size_of::<EntryHeader>();
+            if input.len() < entry_end {
+                return Err(PackError::Truncated {
+                    expected: entry_end,
+                    actual: input.len(),
+                });
+            }
+            entry.as_bytes_mut().copy_from_slice(&input[pos..entry_end]);
+            let path_end = entry_end + entry.path_len as usize;
+            if input.len() < path_end {
+                return Err(PackError::Truncated {
+                    expected: path_end,
+                    actual: input.len(),
+                });
+            }
+            let path = std::str::from_utf8(&input[entry_end..path_end])
+                .map_err(|_| PackError::InvalidPath)?
+                .to_owned();
+            pos = path_end;
+
+            entries.push(Entry {
+                path,
+                offset: entry.offset as usize,
+                stored_len: entry.stored_len as usize,
+                len: entry.len as usize,
+                crc: entry.crc,
+                compressed: entry.flags & FLAG_COMPRESSED != 0,
+            });
+        }
+
+        let index_crc = crc32(&input[header_end..pos]);
+        if index_crc != header.index_crc {
+            return Err(PackError::ChecksumMismatch {
+                expected: header.index_crc,
+                actual: index_crc,
+            });
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 && entries[i - 1].path >= entry.path {
+                return Err(PackError::UnsortedIndex(entry.path.clone()));
+            }
+            let end = entry.offset.checked_add(entry.stored_len);
+            if entry.offset < pos || end.is_none_or(|end| end > input.len()) {
+                return Err(PackError::OutOfBounds(entry.path.clone()));
+            }
+        }
+
+        Ok(Self { bytes, entries })
+    }
+
+    /// The entries, sorted by path
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Reads the data of the entry with the path, if there's one.
+    pub fn get(&self, path: &str) -> Option<Result<Cow<'_, [u8]>, PackError>> {
+        let idx = self
+            .entries
+            .binary_search_by(|entry| entry.path.as_str().cmp(path))
+            .ok()?;
+        Some(self.read(&self.entries[idx]))
+    }
+
+    /// Reads the data of an entry of this pack, decompressing it if needed.
+    pub fn read(&self, entry: &Entry) -> Result<Cow<'_, [u8]>, PackError> {
+        let stored = &self.bytes.as_ref()[entry.offset..entry.offset + entry.stored_len];
+        let data = if entry.compressed {
+            let data = decompress(stored, entry.len)
+                .ok_or_else(|| PackError::InvalidData(entry.path.clone()))?;
+            Cow::Owned(data)
+        } else {
+            Cow::Borrowed(stored)
+        };
+
+        let crc = crc32(&data);
+        if crc != entry.crc {
+            return Err(PackError::ChecksumMismatch {
+                expected: entry.crc,
+                actual: crc,
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// Writes a pack of the entries.
+pub fn write_pack(entries: &[PackEntry], output: &mut Vec<u8>) {
+    let mut entries: Vec<_> = entries.iter().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for pair in entries.windows(2) {
+        assert!(
+            pair[0].path != pair[1].path,
+            "The path {:?} is in the pack twice",
+            pair[0].path
+        );
+    }
+
+    let stored: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let mut compressed = Vec::new();
+            if entry.compress {
+                compress(&entry.data, &mut compressed);
+            }
+            if entry.compress && compressed.len() < entry.data.len() {
+                (Cow::Owned(compressed), FLAG_COMPRESSED)
+            } else {
+                (Cow::Borrowed(entry.data.as_slice()), 0)
+            }
+        })
+        .collect();
+
+    let index_len: usize = entries
+        .iter()
+        .map(|entry| size_of::<EntryHeader>() + entry.path.len())
+        .sum();
+    let mut offset = size_of::<PackHeader>() + index_len;
+    let mut index = Vec::with_capacity(index_len);
+    for (entry, (data, flags)) in entries.iter().zip(&stored) {
+        let path_len = u16::try_from(entry.path.len()).expect("The path is too long");
+        let header = EntryHeader {
+            offset: u32::try_from(offset).expect("The pack exceeds 4 GiB"),
+            stored_len: data.len() as u32,
+            len: entry.data.len() as u32,
+            crc: crc32(&entry.data),
+            flags: *flags,
+            reserved: 0,
+            path_len,
+        };
+        index.extend_from_slice(header.as_bytes());
+        index.extend_from_slice(entry.path.as_bytes());
+        offset += data.len();
+    }
+
+    let header = PackHeader {
+        magic: MAGIC,
+        version: VERSION,
+        reserved: [0; 3],
+        entry_count: entries.len() as u32,
+        index_crc: crc32(&index),
+    };
+    output.extend_from_slice(header.as_bytes());
+    output.extend_from_slice(&index);
+    for (data, _) in stored {
+        output.extend_from_slice(&data);
+    }
+}
+
+// The compressed data consists of ops, each starting with a byte whose
+// highest bit is the kind of the op, and the rest is its length:
+// - Literals: the length - 1, followed by the bytes.
+// - Match: the length - `MIN_MATCH`, followed by the distance back to copy
+//   the bytes from as a LEB128 varint. Overlapping copies repeat a pattern.
+
+const MATCH: u8 = 0b1000_0000;
+const MAX_LITERALS: usize = 128;
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 127 + MIN_MATCH;
+const HASH_BITS: u32 = 14;
+
+fn push_literals(literals: &[u8], output: &mut Vec<u8>) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+/// Compresses greedily, finding the matches with a hash table of the last
+/// position of each 4-byte sequence.
+fn compress(input: &[u8], output: &mut Vec<u8>) {
+    let hash = |bytes: &[u8]| {
+        let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        (word.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    let mut last_pos = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literals_start = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= input.len() {
+        let candidate = std::mem::replace(&mut last_pos[hash(&input[pos..])], pos);
+        let len = match candidate {
+            usize::MAX => 0,
+            _ => input[candidate..]
+                .iter()
+                .zip(&input[pos..])
+                .take(MAX_MATCH)
+                .take_while(|(a, b)| a == b)
+                .count(),
+        };
+        if len < MIN_MATCH {
+            pos += 1;
+            continue;
+        }
+        push_literals(&input[literals_start..pos], output);
+        output.push(MATCH | (len - MIN_MATCH) as u8);
+        encode_varint(pos - candidate, output);
+        pos += len;
+        literals_start = pos;
+    }
+    push_literals(&input[literals_start..], output);
+}
+
+/// Decompresses data of `len` bytes, or returns `None` if the data is
+/// invalid or of another length.
+fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    // The length comes from the header: capped to what the ops could produce
+    let mut output = Vec::with_capacity(len.min(input.len() * MAX_MATCH));
+    let mut pos = 0;
+    while pos < input.len() {
+        let op = input[pos];
+        pos += 1;
+        if op & MATCH == 0 {
+            let literals = input.get(pos..pos + op as usize + 1)?;
+            pos += literals.len();
+            output.extend_from_slice(literals);
+        } else {
+            let match_len = (op & !MATCH) as usize + MIN_MATCH;
+            let distance = decode_varint(input, &mut pos).ok()?;
+            if distance == 0 || distance > output.len() {
+                return None;
+            }
+            let start = output.len() - distance;
+            for i in start..start + match_len {
+                output.push(output[i]);
+            }
+        }
+        // Bails out early instead of growing without bounds
+        if output.len() > len {
+            return None;
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, data: &[u8], compress: bool) -> PackEntry {
+        PackEntry {
+            path: path.to_owned(),
+            data: data.to_owned(),
+            compress,
+        }
+    }
+
+    fn pseudo_random(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let inputs: [Vec<u8>; 6] = [
+            Vec::new(),
+            b"abc".to_vec(),
+            vec![7; 1000],
+            b"abcdabcdabcdabcdXabcdabcd".repeat(50),
+            pseudo_random(5000),
+            [pseudo_random(300), pseudo_random(300)].concat().repeat(3),
+        ];
+        for input in inputs {
+            let mut compressed = Vec::new();
+            compress(&input, &mut compressed);
+            assert_eq!(decompress(&compressed, input.len()).as_ref(), Some(&input));
+        }
+
+        let mut compressed = Vec::new();
+        compress(&[7; 1000], &mut compressed);
+        assert!(compressed.len() < 30);
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        // Match before the start
+        assert_eq!(decompress(&[MATCH, 1], 4), None);
+        // Literals past the end of the input
+        assert_eq!(decompress(&[3, b'a'], 4), None);
+        // Wrong length
+        assert_eq!(decompress(&[0, b'a'], 2), None);
+        assert_eq!(decompress(&[0, b'a', MATCH, 1], 2), None);
+        assert_eq!(decompress(&[0, b'a'], usize::MAX), None);
+        // Unterminated varint
+        assert_eq!(decompress(&[0, b'a', MATCH, 0x81], 5), None);
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let repetitive = b"bang! ".repeat(100);
+        let random = pseudo_random(100);
+        let entries = [
+            entry("assets/b.txt", &repetitive, true),
+            entry("assets/a.bin", &random, true),
+            entry("assets/c.txt", &repetitive, false),
+            entry("empty", &[], true),
+        ];
+        let mut output = Vec::new();
+        write_pack(&entries, &mut output);
+
+        let pack = Pack::new(output.as_slice()).unwrap();
+        let paths: Vec<_> = pack.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["assets/a.bin", "assets/b.txt", "assets/c.txt", "empty"]
+        );
+        // Compressed only if it helps
+        let compressed: Vec<_> = pack.entries().iter().map(|e| e.compressed).collect();
+        assert_eq!(compressed, [false, true, false, false]);
+
+        assert_eq!(pack.get("assets/a.bin").unwrap().unwrap(), &random[..]);
+        assert_eq!(pack.get("assets/b.txt").unwrap().unwrap(), &repetitive[..]);
+        assert_eq!(pack.get("assets/c.txt").unwrap().unwrap(), &repetitive[..]);
+        assert_eq!(pack.get("empty").unwrap().unwrap(), &[][..]);
+        assert!(pack.get("assets").is_none());
+        assert!(pack.get("assets/d.txt").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The path \"a\" is in the pack twice")]
+    fn test_write_duplicate() {
+        write_pack(
+            &[entry("a", b"1", false), entry("a", b"2", false)],
+            &mut Vec::new(),
+        );
+    }
+
+    #[test]
+    fn test_pack_errors() {
+        let mut output = Vec::new();
+        write_pack(&[entry("a", &b"abcd".repeat(10), true)], &mut output);
+        let header_len = size_of::<PackHeader>();
+        let data_start = header_len + size_of::<EntryHeader>() + 1;
+
+        assert_eq!(
+            Pack::new(&output[..10]).err(),
+            Some(PackError::Truncated {
+                expected: header_len,
+                actual: 10
+            })
+        );
+        assert!(matches!(
+            Pack::new(&output[..data_start - 1]).err(),
+            Some(PackError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Pack::new(&output[..data_start + 1]).err(),
+            Some(PackError::OutOfBounds(path)) if path == "a"
+        ));
+
+        let mut not_pack = output.clone();
+        not_pack[0] = b'X';
+        assert_eq!(Pack::new(not_pack).err(), Some(PackError::NotPack));
+
+        let mut new_version = output.clone();
+        new_version[4] = VERSION + 1;
+        assert_eq!(
+            Pack::new(new_version).err(),
+            Some(PackError::UnsupportedVersion(VERSION + 1))
+        );
+
+        let mut corrupted_index = output.clone();
+        corrupted_index[data_start - 1] = b'b';
+        assert!(matches!(
+            Pack::new(corrupted_index).err(),
+            Some(PackError::ChecksumMismatch { .. })
+        ));
+
+        // Corrupted data is only noticed when reading the entry
+        let mut corrupted_data = output.clone();
+        *corrupted_data.last_mut().unwrap() ^= 1;
+        let pack = Pack::new(corrupted_data).unwrap();
+        assert!(pack.get("a").unwrap().is_err());
+        assert!(Pack::new(output).unwrap().get("a").unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_unsorted_index() {
+        let mut output = Vec::new();
+        write_pack(
+            &[entry("a", b"1", false), entry("b", b"2", false)],
+            &mut output,
+        );
+        // Swaps the paths and fixes the checksum
+        let header_len = size_of::<PackHeader>();
+        let path_a = header_len + size_of::<EntryHeader>();
+        let path_b = path_a + 1 + size_of::<EntryHeader>();
+        output.swap(path_a, path_b);
+        let index_crc = crc32(&output[header_len..path_b + 1]);
+        output[12..16].copy_from_slice(&index_crc.to_le_bytes());
+        assert_eq!(
+            Pack::new(output).err(),
+            Some(PackError::UnsortedIndex("a".to_owned()))
+        );
+    }
+}
@@ -34,7 +34,7 @@ pub trait Runtime {
     ) -> Self::Window<'l>;
     fn run(win: &mut Self::Window<'_>);
     fn notify_end(ender: &Ender);
-    fn new_ctx(&self) -> RtCtx;
+    fn new_ctx(&self, alloc_state: &'static SharedAllocState) -> RtCtx;
 }
 
 pub fn start_dynamic<RT: Runtime>(rt: RT, lib: &CStr) {
@@ -59,11 +59,13 @@ fn downcast(msg: &Box<dyn Any + Send>) -> &str {
 pub fn start_rt<RT: Runtime>(rt: RT, logic: impl Logic) {
     rt.init_rt();
 
-    let mut shared_alloc_state = SharedAllocState::default();
+    // Leaked, because the managed stores referring to it live in the logic
+    // state, which is never dropped either.
+    let shared_alloc_state: &'static SharedAllocState = Box::leak(Box::default());
     let (mut alloc_manager, mut alloc_retirer, alloc_cleanup) =
-        make_alloc_tools(&mut shared_alloc_state);
+        make_alloc_tools(shared_alloc_state);
 
-    let mut rt_ctx = rt.new_ctx();
+    let mut rt_ctx = rt.new_ctx(shared_alloc_state);
     let mut mem = alloc_manager.get_alloc();
     let LogicInitReturn {
         logic_state,
@@ -71,6 +73,9 @@ pub fn start_rt<RT: Runtime>(rt: RT, logic: impl Logic) {
     } = logic.init_raw(&mut mem, &mut rt_ctx);
     let seq = mem.alloc_seq;
     alloc_manager.retire_single(seq);
+    // Init isn't held to the budget, as it's expected to allocate more
+    alloc_manager.set_budget(config.frame_mem_budget);
+    alloc_manager.set_max_in_flight(config.max_frames_in_flight);
 
     let mut shared_input_state = SharedInputState::default();
     let (input_gatherer, input_consumer) = make_input_tools(&mut shared_input_state);
@@ -82,7 +87,9 @@ pub fn start_rt<RT: Runtime>(rt: RT, logic: impl Logic) {
     let ender = &ender;
     let mut logic_err = None;
     let mut rt_err = None;
+    let mut alloc_report = None;
     let moved_logic_err = &mut logic_err;
+    let moved_alloc_report = &mut alloc_report;
 
     let args = RunArgs {
         logic,
@@ -100,6 +107,7 @@ pub fn start_rt<RT: Runtime>(rt: RT, logic: impl Logic) {
             .name("logic_loop".to_owned())
             .spawn_scoped(s, move || {
                 catch_unwind(AssertUnwindSafe(|| logic_loop::run(args)))
+                    .map(|report| *moved_alloc_report = Some(report))
                     .unwrap_or_else(|err| *moved_logic_err = Some(err));
                 ender.soft_quit();
             })
@@ -114,6 +122,11 @@ pub fn start_rt<RT: Runtime>(rt: RT, logic: impl Logic) {
         ender.soft_quit();
     });
 
+    // Reported only now, as the runtime might have been drawing over stderr
+    if let Some(alloc_report) = alloc_report {
+        eprintln!("{alloc_report}");
+    }
+
     match (rt_err, logic_err) {
         (Some(rt_err), Some(logic_err)) => {
             let rt_err = downcast(&rt_err);
@@ -153,10 +166,12 @@ pub(crate) mod tests {
     struct TestRT {
         crash: bool,
         synchro_crash: bool,
+        /// The logic panics before sending any frames
+        logic_crash: bool,
     }
 
     pub fn load_textures<'f>(_: &mut RtCtx, _: &[&str], _: &mut Mem<'f>) -> &'f [Id<Tex>] {
-        unimplemented!()
+        &[]
     }
 
     struct TestWindow<'l> {
@@ -165,6 +180,7 @@ pub(crate) mod tests {
         ender: &'l Ender,
         crash: bool,
         synchro_crash: bool,
+        logic_crash: bool,
     }
 
     impl Runtime for TestRT {
@@ -186,6 +202,7 @@ pub(crate) mod tests {
                 ender,
                 crash: self.crash,
                 synchro_crash: self.synchro_crash,
+                logic_crash: self.logic_crash,
             }
         }
 
@@ -197,33 +214,41 @@ pub(crate) mod tests {
                 while win.ender.should_end().not() {} // Busy wait for the other thread to crash
                 panic!("TestRT crashed synchronously with the logic loop!");
             }
+            if win.logic_crash {
+                while win.ender.should_end().not() {} // Busy wait for the logic to crash
+                return;
+            }
             assert!(win.draw_receiver.has_fresh().not());
             win.input_gatherer
                 .update(Key::Space, KeyState::Pressed, Instant::now());
 
-            while win.draw_receiver.get_fresh().alloc_seq < 1 {
-                if win.ender.should_end() {
-                    return;
-                }
+            // The init frame takes the sequence 1, so the first drawn one is 2
+            while win.draw_receiver.has_fresh().not() {
+                assert!(
+                    win.ender.should_end().not(),
+                    "Ended before the first frame was drawn"
+                );
             }
-
-            let fresh = win.draw_receiver.get_fresh();
-            assert_eq!(fresh.alloc_seq, 1);
+            assert_eq!(win.draw_receiver.get_fresh().alloc_seq, 2);
         }
 
         fn notify_end(_: &Ender) {}
 
-        fn new_ctx(&self) -> RtCtx {
+        fn new_ctx(&self, alloc_state: &'static SharedAllocState) -> RtCtx {
             RtCtx {
                 frame: 0,
                 rt_kind: RtKind::Test,
                 load_textures_ptr: load_textures,
+                load_palette_ptr: crate::assets::load_palette,
                 rt_state: SendableErasedPtr(null_mut()),
+                alloc_state,
             }
         }
     }
 
+    // The test dylibs are built with `make test_dylib`, which targets macOS
     #[test]
+    #[cfg(target_os = "macos")]
     #[cfg_attr(miri, ignore)]
     fn test_runtime_dynamic() {
         let rt = TestRT::default();
@@ -239,7 +264,11 @@ pub(crate) mod tests {
     #[test]
     #[should_panic(expected = "Logic loop panicked")]
     fn test_logic_panic() {
-        let rt = TestRT::default();
+        let rt = TestRT {
+            crash: false,
+            synchro_crash: false,
+            logic_crash: true,
+        };
         start_rt(rt, PanicTestLogic);
     }
 
@@ -249,6 +278,7 @@ pub(crate) mod tests {
         let rt = TestRT {
             crash: true,
             synchro_crash: false,
+            logic_crash: false,
         };
         start_rt(rt, NormalTestLogic);
     }
@@ -259,6 +289,7 @@ pub(crate) mod tests {
         let rt = TestRT {
             crash: false,
             synchro_crash: true,
+            logic_crash: false,
         };
         start_rt(rt, PanicTestLogic);
     }
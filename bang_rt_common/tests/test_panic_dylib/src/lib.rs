@@ -1,5 +1,5 @@
 use bang_core::{
-    Config,
+    Config, FramesInFlight, MemBudget,
     alloc::Mem,
     draw::DrawFrame,
     ffi::{Logic, RtCtx},
@@ -23,6 +23,8 @@ impl Logic for TestLogic {
                 resolution: (320, 200),
                 logic_fps: 60,
                 scale: 1,
+                frame_mem_budget: MemBudget::Unlimited,
+                max_frames_in_flight: FramesInFlight::Unlimited,
             },
         )
     }
@@ -1,5 +1,5 @@
 use bang_core::{
-    Config,
+    Config, FramesInFlight, MemBudget,
     alloc::{Id, Mem},
     draw::{Cmd, DrawFrame, ScreenPos},
     export_logic,
@@ -36,6 +36,8 @@ impl Logic for DemoLogic {
                 resolution: (320, 200),
                 logic_fps: if ctx.rt_kind == RtKind::TUI { 10 } else { 60 },
                 scale: 6,
+                frame_mem_budget: MemBudget::Warn(64 * 1024),
+                max_frames_in_flight: FramesInFlight::Block(3),
             },
         )
     }
@@ -1,8 +1,17 @@
-use bang_rt_common::runtime::start_static;
+use bang_core::{assets::EmbeddedAsset, include_assets};
+use bang_rt_common::{assets::embed, runtime::start_static};
 use bang_rt_macos::MacOSRT;
 use demo_main::DemoLogic;
 
+static ASSETS: &[EmbeddedAsset] = include_assets!(
+    dir = "../..",
+    "assets/paltex/bubu.paltex",
+    "assets/paltex/toge.paltex",
+    "assets/paltex/lima.paltex",
+);
+
 fn main() {
     eprintln!("Running statically");
+    embed(ASSETS);
     start_static(MacOSRT, DemoLogic);
 }
@@ -0,0 +1,575 @@
+use std::{
+    borrow::Borrow,
+    fmt::Debug,
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash},
+    mem::replace,
+};
+
+/// Marks an empty slot in the hash table
+const EMPTY: u32 = u32::MAX;
+const MIN_TABLE_LEN: usize = 8;
+
+fn hash<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    // Not randomly seeded, so the iteration order is deterministic from run
+    // to run. HashDoS isn't a concern for frame-lifetime data.
+    BuildHasherDefault::<DefaultHasher>::default().hash_one(key)
+}
+
+/// Frame-lifetime hash map, allocated with `ArenaGuard::alloc_hash_map`.
+///
+/// The entries are stored densely in a vec allocated from the arena, and
+/// looked up through an open-addressing table of indices with linear probing,
+/// also allocated from the arena. Like the other vecs of the arena, they keep
+/// their capacity from frame to frame, so maps of steady size don't touch the
+/// global allocator.
+///
+/// Iteration order is the insertion order, as long as nothing is removed.
+/// Removing swaps the last entry into the place of the removed one.
+pub struct HashMap<'a, K, V> {
+    entries: &'a mut Vec<(K, V)>,
+    /// Indices to `entries`. The length is zero or a power of two.
+    table: &'a mut Vec<u32>,
+}
+
+impl<'a, K, V> HashMap<'a, K, V> {
+    pub(crate) fn new(entries: &'a mut Vec<(K, V)>, table: &'a mut Vec<u32>) -> Self {
+        debug_assert!(entries.is_empty() && table.is_empty());
+        Self { entries, table }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the entries, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.table.fill(EMPTY);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.entries.iter_mut().map(|(k, v)| (&*k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    fn mask(&self) -> usize {
+        self.table.len() - 1
+    }
+}
+
+impl<'a, K, V> HashMap<'a, K, V>
+where
+    K: Hash + Eq,
+{
+    /// Finds the table slot of the key, or the empty slot where it would be
+    /// inserted. The table must have at least one empty slot.
+    fn find<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mask = self.mask();
+        let mut slot = hash(key) as usize & mask;
+        loop {
+            let idx = self.table[slot];
+            if idx == EMPTY {
+                return Err(slot);
+            }
+            if self.entries[idx as usize].0.borrow() == key {
+                return Ok(slot);
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    fn find_existing<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.table.is_empty() {
+            return None;
+        }
+        self.find(key).ok()
+    }
+
+    /// Keeps the table at most 3/4 full, so the probe sequences stay short
+    /// and there's always an empty slot to end them.
+    fn reserve_one(&mut self) {
+        // Panic: `EMPTY` is reserved, so there can be at most `u32::MAX`
+        // entries. Going over that in a single frame is a bug.
+        assert!(
+            self.entries.len() < EMPTY as usize,
+            "Too many entries in HashMap"
+        );
+        if (self.entries.len() + 1) * 4 <= self.table.len() * 3 {
+            return;
+        }
+        let table_len = (self.table.len() * 2).max(MIN_TABLE_LEN);
+        self.table.clear();
+        self.table.resize(table_len, EMPTY);
+        let mask = self.mask();
+        for (idx, (key, _)) in self.entries.iter().enumerate() {
+            let mut slot = hash(key) as usize & mask;
+            while self.table[slot] != EMPTY {
+                slot = (slot + 1) & mask;
+            }
+            self.table[slot] = idx as u32;
+        }
+    }
+
+    fn push_at(&mut self, slot: usize, key: K, val: V) -> usize {
+        let idx = self.entries.len();
+        self.table[slot] = idx as u32;
+        self.entries.push((key, val));
+        idx
+    }
+
+    /// Inserts the value, returning the earlier value of the key, if any.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V> {
+        self.reserve_one();
+        match self.find(&key) {
+            Ok(slot) => {
+                let idx = self.table[slot] as usize;
+                Some(replace(&mut self.entries[idx].1, val))
+            }
+            Err(slot) => {
+                self.push_at(slot, key, val);
+                None
+            }
+        }
+    }
+
+    /// Returns the value of the key, inserting the result of `default` first
+    /// if there isn't one.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        self.reserve_one();
+        let idx = match self.find(&key) {
+            Ok(slot) => self.table[slot] as usize,
+            Err(slot) => self.push_at(slot, key, default()),
+        };
+        &mut self.entries[idx].1
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.find_existing(key)?;
+        Some(&self.entries[self.table[slot] as usize].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.find_existing(key)?;
+        Some(&mut self.entries[self.table[slot] as usize].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find_existing(key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.find_existing(key)?;
+        let idx = self.table[slot] as usize;
+        self.remove_slot(slot);
+
+        // The last entry is swapped into the place of the removed one,
+        // so its table slot has to be pointed there
+        let last = self.entries.len() - 1;
+        if idx != last {
+            let mask = self.mask();
+            let mut slot = hash(&self.entries[last].0) as usize & mask;
+            while self.table[slot] as usize != last {
+                slot = (slot + 1) & mask;
+            }
+            self.table[slot] = idx as u32;
+        }
+        Some(self.entries.swap_remove(idx).1)
+    }
+
+    /// Empties the slot, shifting the following entries of the probe
+    /// sequence backwards, so that no lookup stops short at the hole.
+    fn remove_slot(&mut self, slot: usize) {
+        let mask = self.mask();
+        let mut hole = slot;
+        let mut next = (hole + 1) & mask;
+        loop {
+            let idx = self.table[next];
+            if idx == EMPTY {
+                break;
+            }
+            let ideal = hash(&self.entries[idx as usize].0) as usize & mask;
+            // The entry can fill the hole if the hole isn't before its ideal
+            // slot, taking the wrap-around into account
+            if next.wrapping_sub(ideal) & mask >= next.wrapping_sub(hole) & mask {
+                self.table[hole] = idx;
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.table[hole] = EMPTY;
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for HashMap<'_, K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Frame-lifetime hash set, allocated with `ArenaGuard::alloc_hash_set`.
+/// Stored like `HashMap`.
+pub struct HashSet<'a, T> {
+    map: HashMap<'a, T, ()>,
+}
+
+impl<'a, T> HashSet<'a, T> {
+    pub(crate) fn new(map: HashMap<'a, T, ()>) -> Self {
+        Self { map }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Removes all the values, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.map.keys()
+    }
+}
+
+impl<'a, T> HashSet<'a, T>
+where
+    T: Hash + Eq,
+{
+    /// Inserts the value, returning whether it wasn't in the set already.
+    pub fn insert(&mut self, val: T) -> bool {
+        let len = self.map.len();
+        self.map.get_or_insert_with(val, || ());
+        self.map.len() > len
+    }
+
+    pub fn contains<Q>(&self, val: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(val)
+    }
+
+    /// Removes the value, returning whether it was in the set.
+    pub fn remove<Q>(&mut self, val: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(val).is_some()
+    }
+}
+
+impl<T: Debug> Debug for HashSet<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Frame-lifetime map that keeps its entries sorted by key in a single vec,
+/// allocated with `ArenaGuard::alloc_sorted_map`.
+///
+/// Lookups are binary searches, and insertions and removals shift the
+/// following entries, so it's best suited for small maps, and for when
+/// sorted iteration is needed.
+pub struct SortedMap<'a, K, V> {
+    entries: &'a mut Vec<(K, V)>,
+}
+
+impl<'a, K, V> SortedMap<'a, K, V> {
+    pub(crate) fn new(entries: &'a mut Vec<(K, V)>) -> Self {
+        debug_assert!(entries.is_empty());
+        Self { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the entries, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Iterates over the entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    /// Iterates mutably over the entries in key order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.entries.iter_mut().map(|(k, v)| (&*k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    /// The entry with the smallest key
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.entries.first().map(|(k, v)| (k, v))
+    }
+
+    /// The entry with the largest key
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.entries.last().map(|(k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V> SortedMap<'a, K, V>
+where
+    K: Ord,
+{
+    fn find<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.entries.binary_search_by(|(k, _)| k.borrow().cmp(key))
+    }
+
+    /// Inserts the value, returning the earlier value of the key, if any.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V> {
+        match self.find(&key) {
+            Ok(idx) => Some(replace(&mut self.entries[idx].1, val)),
+            Err(idx) => {
+                self.entries.insert(idx, (key, val));
+                None
+            }
+        }
+    }
+
+    /// Returns the value of the key, inserting the result of `default` first
+    /// if there isn't one.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        let idx = match self.find(&key) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.entries.insert(idx, (key, default()));
+                idx
+            }
+        };
+        &mut self.entries[idx].1
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let idx = self.find(key).ok()?;
+        Some(&self.entries[idx].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let idx = self.find(key).ok()?;
+        Some(&mut self.entries[idx].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).is_ok()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let idx = self.find(key).ok()?;
+        Some(self.entries.remove(idx).1)
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for SortedMap<'_, K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, ops::Not};
+
+    use crate::Arena;
+
+    #[test]
+    fn test_hash_map() {
+        let mut alloc = Arena::default();
+        let arena = alloc.fresh_arena(1);
+        let map = arena.alloc_hash_map::<u32, u32>();
+        assert!(map.is_empty());
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.remove(&1), None);
+
+        assert_eq!(map.insert(1, 10), None);
+        assert_eq!(map.insert(2, 20), None);
+        assert_eq!(map.insert(1, 11), Some(10));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&1), Some(&11));
+        *map.get_mut(&2).expect("inserted") += 1;
+        *map.get_or_insert_with(3, || 30) += 1;
+        *map.get_or_insert_with(3, || 30) += 1;
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            [(&1, &11), (&2, &21), (&3, &32)]
+        );
+
+        // Removing swaps the last entry in the place of the removed one
+        assert_eq!(map.remove(&1), Some(11));
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&3, &2]);
+        assert!(map.contains_key(&3));
+        assert!(map.contains_key(&1).not());
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get(&3), None);
+    }
+
+    /// Compares to `BTreeMap` with pseudo-random operations, to exercise the
+    /// probing, growing and the backward shifts on removal.
+    #[test]
+    fn test_hash_map_random_ops() {
+        const OPS: u32 = if cfg!(miri) { 500 } else { 20_000 };
+        let mut alloc = Arena::default();
+        let arena = alloc.fresh_arena(1);
+        let map = arena.alloc_hash_map::<u32, u32>();
+        let mut reference = BTreeMap::new();
+
+        let mut rng = 12345u32;
+        for i in 0..OPS {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            let key = rng % 500;
+            if rng & 0x300 == 0 {
+                assert_eq!(map.remove(&key), reference.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, i), reference.insert(key, i));
+            }
+        }
+        assert_eq!(map.len(), reference.len());
+        for (key, val) in &reference {
+            assert_eq!(map.get(key), Some(val));
+        }
+        let mut entries: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        assert!(entries.into_iter().eq(reference.into_iter()));
+    }
+
+    #[test]
+    fn test_hash_map_capacity_reuse() {
+        let mut alloc = Arena::default();
+        let arena = alloc.fresh_arena(1);
+        let map = arena.alloc_hash_map::<u64, u64>();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let (entries_cap, table_cap) = (map.entries.capacity(), map.table.capacity());
+
+        let arena = alloc.fresh_arena(2);
+        let map = arena.alloc_hash_map::<u64, u64>();
+        assert!(map.is_empty());
+        assert_eq!(map.entries.capacity(), entries_cap);
+        assert_eq!(map.table.capacity(), table_cap);
+        map.insert(1, 1);
+        assert_eq!(map.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_hash_set() {
+        let mut alloc = Arena::default();
+        let arena = alloc.fresh_arena(1);
+        let set = arena.alloc_hash_set::<&str>();
+        assert!(set.insert("a"));
+        assert!(set.insert("b"));
+        assert!(set.insert("a").not());
+        assert_eq!(set.len(), 2);
+        assert!(set.contains("a"));
+        assert!(set.remove("a"));
+        assert!(set.remove("a").not());
+        assert_eq!(set.iter().collect::<Vec<_>>(), [&"b"]);
+        assert_eq!(format!("{set:?}"), r#"{"b"}"#);
+    }
+
+    #[test]
+    fn test_sorted_map() {
+        let mut alloc = Arena::default();
+        let arena = alloc.fresh_arena(1);
+        let map = arena.alloc_sorted_map::<i32, char>();
+        assert_eq!(map.first(), None);
+        assert_eq!(map.insert(3, 'c'), None);
+        assert_eq!(map.insert(1, 'a'), None);
+        assert_eq!(map.insert(2, 'x'), None);
+        assert_eq!(map.insert(2, 'b'), Some('x'));
+        *map.get_or_insert_with(0, || '0') = '_';
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&0, &1, &2, &3]);
+        assert_eq!(map.values().collect::<String>(), "_abc");
+        assert_eq!(map.first(), Some((&0, &'_')));
+        assert_eq!(map.last(), Some((&3, &'c')));
+        assert_eq!(map.remove(&0), Some('_'));
+        assert_eq!(map.remove(&0), None);
+        assert_eq!(map.get(&1), Some(&'a'));
+        assert!(map.contains_key(&2));
+        assert_eq!(format!("{map:?}"), "{1: 'a', 2: 'b', 3: 'c'}");
+    }
+}
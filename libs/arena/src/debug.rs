@@ -0,0 +1,37 @@
+//! Allocation debugging aids, enabled with the `debug_alloc` feature.
+//!
+//! - The memory of the previous allocation sequence is poisoned on reset, so
+//!   that use-after-reset reads garbage that stands out instead of plausible
+//!   stale values.
+//! - Each value allocation is preceded by a canary, which is checked on
+//!   reset, so that out-of-bounds writes over the preceding value are caught.
+//!   Vec contents get no canaries: their buffers are allocated, grown and
+//!   freed by `Vec` itself with the exact layout of its capacity, so there's
+//!   no room around them that the arena owns. Growing also moves the buffer.
+//!   They are only poisoned.
+
+/// Byte pattern written over the memory of retired allocation sequences
+pub const POISON: u8 = 0xDD;
+/// Byte pattern of the canaries placed before value allocations
+pub const CANARY: u8 = 0xCA;
+
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes, and not aliased.
+pub unsafe fn poison(ptr: *mut u8, len: usize) {
+    unsafe { ptr.write_bytes(POISON, len) };
+}
+
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes, and not aliased.
+pub unsafe fn write_canary(ptr: *mut u8, len: usize) {
+    unsafe { ptr.write_bytes(CANARY, len) };
+}
+
+/// # Safety
+///
+/// `ptr` must be valid for reads of `len` bytes, and not mutably aliased.
+pub unsafe fn is_canary_intact(ptr: *const u8, len: usize) -> bool {
+    (0..len).all(|i| unsafe { ptr.add(i).read() } == CANARY)
+}
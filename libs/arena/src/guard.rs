@@ -1,23 +1,25 @@
 use std::{
     fmt::Debug,
     marker::PhantomData,
-    mem::transmute,
+    mem::{needs_drop, take, transmute},
     ops::{Deref, DerefMut},
-    ptr::copy,
+    ptr::{copy, drop_in_place, slice_from_raw_parts_mut},
     slice,
 };
 
 use crate::{
     ErasedMax, MemoryUsage, check_alignment_static, check_drop_static,
     check_string_vec_layout_static,
+    collections::{HashMap, HashSet, SortedMap},
     val::{self, ByAlign},
     vec,
 };
 
 /// Short-lived arena implementation
 ///
-/// Supports objects with aligments up to 16 bytes. This is the maximum
-/// natural alignment for all supported platforms.
+/// Supports objects with aligments up to 64 bytes. 16 bytes is the maximum
+/// natural alignment for all supported platforms, but wide SIMD types and
+/// cache-line padded structs may be over-aligned up to 64 bytes.
 ///
 /// # Safety
 ///
@@ -28,11 +30,12 @@ use crate::{
 ///
 /// Specifically, any references returned by the allocation methods
 /// (`alloc_val`, `alloc_vec`, `alloc_slice`, `alloc_iter`, `alloc_val_ignore_drop`,
-/// `alloc_vec_ignore_drop`, `alloc_slice_ignore_drop`, `alloc_iter_ignore_drop`)
-/// are not safe to use after the `reset` method is called. Reset splits the
-/// use of this arena into monotonically increasing "allocation sequences",
-/// indicated by the sequence number in `alloc_seq` field. Only the references
-/// allocated on the current allocation sequence are safe to use.
+/// `alloc_vec_ignore_drop`, `alloc_slice_ignore_drop`, `alloc_iter_ignore_drop`,
+/// `alloc_hash_map`, `alloc_hash_set`, `alloc_sorted_map`) are not safe to use
+/// after the `reset` method is called. Reset splits the use of this arena into
+/// monotonically increasing "allocation sequences", indicated by the sequence
+/// number in `alloc_seq` field. Only the references allocated on the current
+/// allocation sequence are safe to use.
 ///
 /// Similarly, mutable vector references borrowed out of this arena are not
 /// safe to use after the `memory_usage` method is called. However, mutable
@@ -44,26 +47,99 @@ use crate::{
 /// The allocation methods (`alloc_val`, `alloc_vec`, `alloc_slice`,
 /// `alloc_iter`, `alloc_val_ignore_drop`, `alloc_vec_ignore_drop`,
 /// `alloc_slice_ignore_drop`, `alloc_iter_ignore_drop`) panic if attempting to
-/// allocate a type with alignment that is not 1, 2, 4, 8, or 16. Attempting
-/// to do so is API misuse and on caller's responsibility.
+/// allocate a type with alignment that is not 1, 2, 4, 8, 16, 32 or 64.
+/// Attempting to do so is API misuse and on caller's responsibility.
+///
+/// # Drop
+///
+/// The values allocated with `alloc_val_with_drop` and `alloc_vec_with_drop`
+/// are dropped when the arena is reset or dropped, in reverse allocation
+/// order. The other allocation methods never run destructors.
 #[derive(Debug)]
 pub struct ArenaGuard<'a> {
-    pub alloc_seq: usize,
+    pub alloc_seq: u64,
     vec_align_1: vec::ByAlign,
     vec_align_2: vec::ByAlign,
     vec_align_4: vec::ByAlign,
     vec_align_8: vec::ByAlign,
     vec_align_16: vec::ByAlign,
+    vec_align_32: vec::ByAlign,
+    vec_align_64: vec::ByAlign,
     val_align_1: val::ByAlign,
     val_align_2: val::ByAlign,
     val_align_4: val::ByAlign,
     val_align_8: val::ByAlign,
     val_align_16: val::ByAlign,
+    val_align_32: val::ByAlign,
+    val_align_64: val::ByAlign,
+    drops: DropRegistry,
     _lifetime: PhantomData<&'a mut ErasedMax>,
 }
 
+#[derive(Debug)]
+struct DropEntry {
+    ptr: *mut u8,
+    drop_fn: unsafe fn(*mut u8),
+}
+
+/// Destructors to run for the values allocated during the current
+/// allocation sequence.
+///
+/// The arena might be reset on another thread than the one that allocated
+/// the values: the init frame, for one, is allocated on the main thread and
+/// reset on the logic thread. That's why the values must be `Send`.
+#[derive(Debug, Default)]
+struct DropRegistry {
+    entries: Vec<DropEntry>,
+}
+
+// Safety: the entries only point to values that are `Send`, so they can be
+// dropped on whichever thread the arena is reset on.
+unsafe impl Send for DropRegistry {}
+
+unsafe fn drop_val<T>(ptr: *mut u8) {
+    // Safety: registered only for pointers to live values of type `T`
+    unsafe { drop_in_place(ptr as *mut T) };
+}
+
+unsafe fn drop_vec_contents<T>(ptr: *mut u8) {
+    // Safety: registered only for pointers to vecs of type `Vec<T>`. The vec
+    // itself stays in the arena for reuse, so only the contents are dropped.
+    unsafe {
+        let vec = &mut *(ptr as *mut Vec<T>);
+        let len = vec.len();
+        vec.set_len(0);
+        drop_in_place(slice_from_raw_parts_mut(vec.as_mut_ptr(), len));
+    }
+}
+
+impl DropRegistry {
+    fn register(&mut self, ptr: *mut u8, drop_fn: unsafe fn(*mut u8)) {
+        self.entries.push(DropEntry { ptr, drop_fn });
+    }
+
+    /// Runs the registered destructors in reverse allocation order.
+    ///
+    /// # Safety
+    ///
+    /// The values the entries point to must not be accessed anymore.
+    unsafe fn run(&mut self) {
+        // Taken out first, so that if a destructor panics, the rest are
+        // leaked instead of being dropped twice
+        let entries = take(&mut self.entries);
+        for entry in entries.into_iter().rev() {
+            // Safety: the entry points to a live value of the type matching
+            // `drop_fn`, and is removed from the registry, so it's run once.
+            unsafe { (entry.drop_fn)(entry.ptr) };
+        }
+    }
+}
+
 impl<'a> Drop for ArenaGuard<'a> {
     fn drop(&mut self) {
+        // Safety: the allocated values can't outlive the arena
+        unsafe { self.drops.run() };
+
         // We need to manually drop the vecs to avoid memory leaks.
         // The vecs are not automatically dropped because they are stored as
         // type-erased vecs in `MaybeUninit<_>`. The destructors of the contents
@@ -71,14 +147,7 @@ impl<'a> Drop for ArenaGuard<'a> {
         // library API contract, and considered safe in Rust. The default
         // allocating methods statically check that the allocated types are not
         // `Drop` to warn the user of any suprises.
-        let aligns = [
-            (1, &mut self.vec_align_1),
-            (2, &mut self.vec_align_2),
-            (4, &mut self.vec_align_4),
-            (8, &mut self.vec_align_8),
-            (16, &mut self.vec_align_16),
-        ];
-        for (align, by_align) in aligns {
+        for (align, by_align) in self.vec_aligns_mut() {
             by_align.drop(align)
         }
     }
@@ -93,15 +162,50 @@ impl<'a> ArenaGuard<'a> {
             vec_align_4: vec::ByAlign::new(),
             vec_align_8: vec::ByAlign::new(),
             vec_align_16: vec::ByAlign::new(),
-            val_align_1: val::ByAlign::new(),
-            val_align_2: val::ByAlign::new(),
-            val_align_4: val::ByAlign::new(),
-            val_align_8: val::ByAlign::new(),
-            val_align_16: val::ByAlign::new(),
+            vec_align_32: vec::ByAlign::new(),
+            vec_align_64: vec::ByAlign::new(),
+            val_align_1: val::ByAlign::new(1),
+            val_align_2: val::ByAlign::new(2),
+            val_align_4: val::ByAlign::new(4),
+            val_align_8: val::ByAlign::new(8),
+            val_align_16: val::ByAlign::new(16),
+            val_align_32: val::ByAlign::new(32),
+            val_align_64: val::ByAlign::new(64),
+            drops: DropRegistry::default(),
             _lifetime: PhantomData,
         }
     }
 
+    fn vec_aligns_mut(&mut self) -> [(usize, &mut vec::ByAlign); 7] {
+        [
+            (1, &mut self.vec_align_1),
+            (2, &mut self.vec_align_2),
+            (4, &mut self.vec_align_4),
+            (8, &mut self.vec_align_8),
+            (16, &mut self.vec_align_16),
+            (32, &mut self.vec_align_32),
+            (64, &mut self.vec_align_64),
+        ]
+    }
+
+    /// Panics if a value allocated during the current sequence has been
+    /// written out of bounds. Also checked on reset.
+    #[cfg(feature = "debug_alloc")]
+    pub fn check_canaries(&self) {
+        let val_aligns = [
+            &self.val_align_1,
+            &self.val_align_2,
+            &self.val_align_4,
+            &self.val_align_8,
+            &self.val_align_16,
+            &self.val_align_32,
+            &self.val_align_64,
+        ];
+        for by_align in val_aligns {
+            by_align.check_canaries();
+        }
+    }
+
     pub(crate) unsafe fn memory_usage(&self) -> MemoryUsage {
         let mut usage = MemoryUsage::default();
         usage.overhead_bytes += size_of::<ArenaGuard>();
@@ -127,6 +231,8 @@ impl<'a> ArenaGuard<'a> {
             (4, &self.vec_align_4),
             (8, &self.vec_align_8),
             (16, &self.vec_align_16),
+            (32, &self.vec_align_32),
+            (64, &self.vec_align_64),
         ];
 
         for (align, by_align) in vec_aligns {
@@ -145,6 +251,8 @@ impl<'a> ArenaGuard<'a> {
             &self.val_align_4,
             &self.val_align_8,
             &self.val_align_16,
+            &self.val_align_32,
+            &self.val_align_64,
         ];
 
         for by_align in val_aligns {
@@ -159,6 +267,8 @@ impl<'a> ArenaGuard<'a> {
             4 => &mut self.vec_align_4,
             8 => &mut self.vec_align_8,
             16 => &mut self.vec_align_16,
+            32 => &mut self.vec_align_32,
+            64 => &mut self.vec_align_64,
             // UNREACHABLE: always checked with `check_alignment_static`
             _ => panic!("UNREACHABLE: Unsupported alignment"),
         }
@@ -171,6 +281,8 @@ impl<'a> ArenaGuard<'a> {
             4 => &mut self.val_align_4,
             8 => &mut self.val_align_8,
             16 => &mut self.val_align_16,
+            32 => &mut self.val_align_32,
+            64 => &mut self.val_align_64,
             // UNREACHABLE: always checked with `check_alignment_static`
             _ => panic!("UNREACHABLE: Unsupported alignment"),
         }
@@ -221,6 +333,52 @@ impl<'a> ArenaGuard<'a> {
         self.alloc_iter_ignore_drop(iter)
     }
 
+    /// Allocates a value that is dropped when the arena is reset.
+    ///
+    /// The value must be `'static`, as it's dropped after the references
+    /// borrowed out of the arena are dead, and `Send`, as the arena might be
+    /// reset on another thread.
+    pub fn alloc_val_with_drop<T: Send + 'static>(&mut self, val: T) -> &'a mut T {
+        let val = self.alloc_val_ignore_drop(val);
+        if needs_drop::<T>() {
+            self.drops.register(val as *mut T as *mut u8, drop_val::<T>);
+        }
+        val
+    }
+
+    /// Allocates a vec whose contents are dropped when the arena is reset.
+    ///
+    /// The contents must be `Send + 'static`, like with `alloc_val_with_drop`.
+    pub fn alloc_vec_with_drop<T: Send + 'static>(&mut self) -> &'a mut Vec<T> {
+        let vec = self.alloc_vec_ignore_drop();
+        if needs_drop::<T>() {
+            self.drops
+                .register(vec as *mut Vec<T> as *mut u8, drop_vec_contents::<T>);
+        }
+        vec
+    }
+
+    /// Allocates an empty hash map, backed by vecs allocated from the arena.
+    pub fn alloc_hash_map<K, V>(&mut self) -> &'a mut HashMap<'a, K, V> {
+        check_drop_static::<(K, V)>();
+        let map = HashMap::new(self.alloc_vec_ignore_drop(), self.alloc_vec_ignore_drop());
+        self.alloc_val_ignore_drop(map)
+    }
+
+    /// Allocates an empty hash set, backed by vecs allocated from the arena.
+    pub fn alloc_hash_set<T>(&mut self) -> &'a mut HashSet<'a, T> {
+        check_drop_static::<T>();
+        let map = HashMap::new(self.alloc_vec_ignore_drop(), self.alloc_vec_ignore_drop());
+        self.alloc_val_ignore_drop(HashSet::new(map))
+    }
+
+    /// Allocates an empty sorted map, backed by a vec allocated from the arena.
+    pub fn alloc_sorted_map<K, V>(&mut self) -> &'a mut SortedMap<'a, K, V> {
+        check_drop_static::<(K, V)>();
+        let map = SortedMap::new(self.alloc_vec_ignore_drop());
+        self.alloc_val_ignore_drop(map)
+    }
+
     pub fn alloc_sink<'s, T>(&'s mut self) -> Sink<'s, 'a, T> {
         check_drop_static::<T>();
         self.alloc_sink_ignore_drop()
@@ -379,14 +537,24 @@ impl<'a> ArenaGuard<'a> {
         }
     }
 
-    pub(crate) unsafe fn reset(&mut self, seq: usize) {
+    pub(crate) unsafe fn reset(&mut self, seq: u64) {
         debug_assert!(seq > self.alloc_seq);
+        // Safety: the values allocated during the previous sequence are not
+        // accessed anymore. This runs before any vecs are reused.
+        unsafe { self.drops.run() };
+        #[cfg(feature = "debug_alloc")]
+        for (align, by_align) in self.vec_aligns_mut() {
+            // Safety: the vecs of the previous sequence are not accessed anymore
+            unsafe { by_align.poison(align) };
+        }
         self.alloc_seq = seq;
         self.val_align_1.reset();
         self.val_align_2.reset();
         self.val_align_4.reset();
         self.val_align_8.reset();
         self.val_align_16.reset();
+        self.val_align_32.reset();
+        self.val_align_64.reset();
     }
 }
 
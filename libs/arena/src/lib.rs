@@ -1,9 +1,17 @@
 use std::{
     fmt::Debug,
     mem::{needs_drop, transmute},
-    sync::atomic::{AtomicU16, AtomicU64, Ordering},
+    ops::Not,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU16, AtomicU64, Ordering},
+    },
+    thread::{self, Thread},
 };
 
+mod collections;
+#[cfg(feature = "debug_alloc")]
+mod debug;
 mod guard;
 //mod managed;
 mod managed_sync;
@@ -12,9 +20,20 @@ mod tests;
 mod val;
 mod vec;
 
+pub use collections::{HashMap, HashSet, SortedMap};
 pub use guard::{ArenaGuard, Sink};
 //pub use managed::{Id, Managed};
-pub use managed_sync::{Id, Managed};
+pub use managed_sync::{Id, Managed, ManagedReader};
+
+/// The features that change the memory layout of the arenas, as flags. Code
+/// sharing the arenas across a dynamic library boundary must agree on them.
+pub const LAYOUT_FEATURES: u32 = if cfg!(feature = "debug_alloc") {
+    LAYOUT_DEBUG_ALLOC
+} else {
+    0
+};
+/// The flag of the `debug_alloc` feature in `LAYOUT_FEATURES`
+pub const LAYOUT_DEBUG_ALLOC: u32 = 0b1;
 
 // Placeholder types for type erasure
 //
@@ -58,7 +77,7 @@ impl MemoryUsage {
 
 #[derive(Debug)]
 pub struct Arena {
-    pub alloc_seq: usize,
+    pub alloc_seq: u64,
     guard: ArenaGuard<'static>,
 }
 
@@ -88,20 +107,25 @@ impl Arena {
         unsafe { transmute(&mut self.guard) }
     }
 
-    pub fn fresh_arena<'a>(&'a mut self, seq: usize) -> &'a mut ArenaGuard<'a> {
+    pub fn fresh_arena<'a>(&'a mut self, seq: u64) -> &'a mut ArenaGuard<'a> {
         self.alloc_seq = seq;
         unsafe { self.guard.reset(seq) };
         self.guard_with_short_lifetime()
     }
 }
 
+/// Allocation state shared between the frame allocator, the managed stores
+/// and the renderer. The allocation sequence kept here is the single source
+/// of truth for which frame is being built and which frames are done with.
 #[derive(Debug)]
 #[repr(C)]
 pub struct SharedAllocState {
     pub managed_arena_seq: AtomicU16,
-    pub alloc_seq: AtomicU64,
+    alloc_seq: AtomicU64,
     pub retired_seq_up_to: AtomicU64,
     pub retired_seq_early: AtomicU64,
+    /// The thread waiting in `park_until` for frames to be retired
+    waiter: Mutex<Option<Thread>>,
 }
 
 impl Default for SharedAllocState {
@@ -111,14 +135,68 @@ impl Default for SharedAllocState {
             alloc_seq: AtomicU64::new(0),
             retired_seq_up_to: AtomicU64::new(0),
             retired_seq_early: AtomicU64::new(0),
+            waiter: Mutex::new(None),
         }
     }
 }
 
 impl SharedAllocState {
-    fn current_seq(&self) -> u64 {
+    /// Starts a new allocation sequence, i.e. a new frame, and returns its
+    /// sequence number. The first sequence is 1.
+    pub fn next_seq(&self) -> u64 {
+        self.alloc_seq.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// The sequence number of the frame currently being built
+    pub fn current_seq(&self) -> u64 {
         self.alloc_seq.load(Ordering::Acquire)
     }
+
+    /// Marks the frames up to `seq` done with. Frames can't be un-retired,
+    /// so retiring an older frame than already retired is a no-op.
+    pub fn retire_up_to(&self, seq: u64) {
+        self.retired_seq_up_to.fetch_max(seq, Ordering::Release);
+        self.notify_waiter();
+    }
+
+    /// Marks a single frame done with, out of order. Only one frame can be
+    /// pending early retirement at a time; if there already is one, this is
+    /// a no-op, and the frame is retired by `retire_up_to` later.
+    pub fn retire_early(&self, seq: u64) {
+        let _ =
+            self.retired_seq_early
+                .compare_exchange(0, seq, Ordering::Release, Ordering::Relaxed);
+        self.notify_waiter();
+    }
+
+    /// The sequence number of the newest frame up to which all the frames
+    /// are done with
+    pub fn retired_seq(&self) -> u64 {
+        self.retired_seq_up_to.load(Ordering::Acquire)
+    }
+
+    /// Blocks the current thread until `done` returns `true`. `done` is
+    /// checked again each time frames are retired.
+    pub fn park_until(&self, mut done: impl FnMut() -> bool) {
+        // Registering before checking ensures that a retirement happening
+        // after the check unparks us, and one before it is seen by the check.
+        self.set_waiter(Some(thread::current()));
+        while done().not() {
+            thread::park();
+        }
+        self.set_waiter(None);
+    }
+
+    fn set_waiter(&self, waiter: Option<Thread>) {
+        // The lock is never held over anything that could panic
+        *self.waiter.lock().unwrap_or_else(PoisonError::into_inner) = waiter;
+    }
+
+    fn notify_waiter(&self) {
+        if let Some(waiter) = &*self.waiter.lock().unwrap_or_else(PoisonError::into_inner) {
+            waiter.unpark();
+        }
+    }
 }
 
 const fn check_alignment_static<T>() {
@@ -127,7 +205,7 @@ const fn check_alignment_static<T>() {
         let align = align_of::<T>();
         assert!(align.is_power_of_two(), "Align must be power of two!");
         assert!(align >= 1, "Align must be at least 1");
-        assert!(align <= 16, "Align must be at most 16");
+        assert!(align <= 64, "Align must be at most 64");
     };
 }
 
@@ -1,29 +1,154 @@
 use std::{
     any::type_name,
+    cell::UnsafeCell,
     collections::VecDeque,
     fmt::Debug,
     hash::Hash,
     marker::PhantomData,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicPtr, Ordering},
+    mem::{MaybeUninit, take},
+    ops::{Deref, Not},
+    ptr::NonNull,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicPtr, AtomicU32, Ordering},
+    },
 };
 
 use crate::{Erased, SharedAllocState, check_alignment_static, check_drop_static};
 
+const MAX_IDX: usize = 0xFFFFFF;
+const GEN_MASK: u32 = 0xFFFFFF;
+/// Generations of live values are even and those of freed values are odd.
+/// Slots that have never been used count as freed, so that allocating to
+/// them wraps the generation around to 0.
+const GEN_UNUSED: u32 = GEN_MASK;
+const INITIAL_CAPACITY: usize = 16;
+
+/// Fixed-capacity storage for the values. When it fills up, the values are
+/// copied to a new store with double the capacity, and the old one is kept
+/// around until no reader can be accessing it anymore.
 struct Store<T> {
+    /// `alloc_seq` at the time this store replaced the previous one
     alloc_seq: u64,
-    store: Vec<MaybeUninit<T>>,
-    generations: Vec<u32>,
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    generations: Box<[AtomicU32]>,
 }
 
-pub struct Managed<'l, T, I = Erased> {
+impl<T> Store<T>
+where
+    T: Copy,
+{
+    fn new(capacity: usize, alloc_seq: u64) -> Self {
+        Self {
+            alloc_seq,
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            generations: (0..capacity).map(|_| AtomicU32::new(GEN_UNUSED)).collect(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn gener(&self, idx: usize) -> u32 {
+        self.generations[idx].load(Ordering::Acquire)
+    }
+
+    /// # Safety
+    /// The slot must contain a live value, and there must be no concurrent
+    /// writes to it.
+    unsafe fn read(&self, idx: usize) -> T {
+        unsafe { (*self.slots[idx].get()).assume_init() }
+    }
+}
+
+/// Owning pointer to a `Store`. Unlike `Box`, it doesn't assert unique access
+/// when moved around, so the readers' pointers to the store stay valid.
+struct OwnedStore<T>(NonNull<Store<T>>);
+
+impl<T> OwnedStore<T> {
+    fn new(store: Store<T>) -> Self {
+        Self(NonNull::from(Box::leak(Box::new(store))))
+    }
+}
+
+impl<T> Deref for OwnedStore<T> {
+    type Target = Store<T>;
+
+    fn deref(&self) -> &Store<T> {
+        // Safety: the pointer comes from a leaked `Box` and is freed only on drop
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T> Drop for OwnedStore<T> {
+    fn drop(&mut self) {
+        // Safety: the pointer comes from a leaked `Box` and this is the only owner
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+/// The part of `Managed` that the readers have access to.
+struct Shared<T> {
     arena_id: u16,
-    stores: VecDeque<Store<T>>,
-    current_store: AtomicPtr<Store<T>>,
-    shared: &'l SharedAllocState,
+    current: AtomicPtr<Store<T>>,
+    /// When `Managed` is dropped, its stores are moved here, as the readers
+    /// might still be accessing them.
+    graveyard: Mutex<Vec<OwnedStore<T>>>,
+}
+
+// Safety: the stores are only written to by `Managed`, which has a unique
+// owner, and only read by readers. Reads and writes are synchronized with
+// the generation counters, and with `SharedAllocState` for reuse of slots.
+// The values are read on other threads while `Managed` hands out references
+// to them, so they must be `Sync` too: with interior mutability, a value
+// could be written while it's being read.
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+/// Storage for long-lived values of type `T`, referred to by `Id`s.
+///
+/// The values are allocated, freed and mutated by a single owner, usually the
+/// logic thread, but can be read concurrently by any number of
+/// `ManagedReader`s, for example from the render thread, without locking.
+///
+/// Because the readers might be looking at values referred to by frames that
+/// are still being rendered, freed slots aren't reused, and stores that have
+/// been outgrown aren't freed, until the frames that could refer to them have
+/// been retired through `SharedAllocState`. Without readers, they are reused
+/// and freed immediately.
+pub struct Managed<'l, T, I = Erased> {
+    shared: Arc<Shared<T>>,
+    /// The current store is at the front, followed by the ones it replaced.
+    stores: VecDeque<OwnedStore<T>>,
+    alloc_state: &'l SharedAllocState,
+    /// Number of slots that have been used at least once
+    slots_used: usize,
+    /// Number of live values
+    live: usize,
     free_list: Vec<u32>,
     reap_list: Vec<u32>,
-    _basetype_marker: PhantomData<T>,
+    /// Freed slots the readers might still be reading, with the `alloc_seq`
+    /// current when they were freed.
+    quarantine: VecDeque<(u64, u32)>,
+    _iface_marker: PhantomData<I>,
+}
+
+// Safety: `Managed` is the only one with write access to the stores. The
+// readers share the values with it, see `Shared`.
+unsafe impl<T: Send + Sync, I> Send for Managed<'_, T, I> {}
+
+/// Read-only handle to the values of a `Managed`, that can be sent to other
+/// threads.
+///
+/// The readers must only look up `Id`s from frames that haven't been retired
+/// yet, i.e. during rendering; that's what keeps the memory and the values
+/// they see valid. The compiler can't check that, so `ManagedReader::get` is
+/// unsafe.
+pub struct ManagedReader<T, I = Erased> {
+    shared: Arc<Shared<T>>,
     _iface_marker: PhantomData<I>,
 }
 
@@ -49,7 +174,7 @@ impl<T> PartialEq for Id<T> {
 impl<T> Eq for Id<T> {}
 impl<T> PartialOrd for Id<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.id.cmp(&other.id))
+        Some(self.cmp(other))
     }
 }
 impl<T> Ord for Id<T> {
@@ -76,8 +201,8 @@ impl<T> Debug for Id<T> {
 
 impl<T> Id<T> {
     fn new(arena_id: u16, idx: usize, generation: u32) -> Self {
-        debug_assert!(idx <= 0xFFFFFF);
-        debug_assert!(generation <= 0xFFFFFF);
+        debug_assert!(idx <= MAX_IDX);
+        debug_assert!(generation <= GEN_MASK);
         let id = (arena_id as u64) << 48 | (idx as u64) << 24 | generation as u64;
         Self {
             id,
@@ -106,97 +231,156 @@ impl<'l, T, I> Managed<'l, T, I>
 where
     T: Copy,
 {
-    fn new(shared: &'l SharedAllocState) -> Self {
+    pub fn new(alloc_state: &'l SharedAllocState) -> Self {
         check_drop_static::<T>();
         check_alignment_static::<T>();
 
-        let arena_id = shared.managed_arena_seq.fetch_add(1, Ordering::Relaxed);
-        let alloc_seq = shared.alloc_seq.load(Ordering::Acquire);
-        let mut stores = VecDeque::new();
-        stores.push_front(Store {
-            alloc_seq,
-            store: Vec::new(),
-            generations: Vec::new(),
+        let arena_id = alloc_state
+            .managed_arena_seq
+            .fetch_add(1, Ordering::Relaxed);
+        let store = OwnedStore::new(Store::new(INITIAL_CAPACITY, alloc_state.current_seq()));
+        let shared = Arc::new(Shared {
+            arena_id,
+            current: AtomicPtr::new(store.0.as_ptr()),
+            graveyard: Mutex::new(Vec::new()),
         });
-        // UNREACHABLE: `self.stores` was just initialized with one `Vec`
-        let current_store = AtomicPtr::new(stores.front_mut().expect("UNREACHABLE: never empty"));
         Self {
-            arena_id,
-            stores,
-            current_store,
             shared,
+            stores: VecDeque::from([store]),
+            alloc_state,
+            slots_used: 0,
+            live: 0,
             free_list: Vec::new(),
             reap_list: Vec::new(),
+            quarantine: VecDeque::new(),
             _iface_marker: PhantomData,
-            _basetype_marker: PhantomData,
         }
     }
 
-    fn current_store(&mut self) -> &mut Store<T> {
-        // Safety: TODO
-        unsafe { &mut *self.current_store.load(Ordering::Acquire) }
+    /// Returns a handle for reading the values from other threads.
+    pub fn reader(&self) -> ManagedReader<T, I> {
+        ManagedReader {
+            shared: self.shared.clone(),
+            _iface_marker: PhantomData,
+        }
     }
 
-    fn grow(&mut self) -> &mut Store<T> {
-        let full_store = self.current_store();
-        let mut store = Vec::with_capacity(full_store.store.capacity() * 2);
-        let mut generations = Vec::with_capacity(store.len());
-        store.extend(full_store.store.iter().cloned());
-        generations.extend(full_store.generations.iter().cloned());
-        let new_store = Store {
-            alloc_seq: self.shared.current_seq(),
-            store,
-            generations,
-        };
-        self.stores.push_front(new_store);
-        // UNREACHABLE: we just added an item, so `self.stores` is never empty
-        self.stores.front_mut().expect("UNREACHABLE: never empty")
+    fn current_store(&self) -> &Store<T> {
+        // UNREACHABLE: `self.stores` is initialized with one store, and
+        // the current one is never removed
+        self.stores.front().expect("UNREACHABLE: never empty")
     }
 
-    pub fn alloc(&mut self, val: T) -> Id<T> {
-        let arena_id = self.arena_id;
-        if self.free_list.is_empty() {
-            let current_store = self.current_store();
-            let next_idx = current_store.store.len();
-            assert!(next_idx < 0xFFFFFF);
-            let current_store = if next_idx < current_store.store.capacity() {
-                current_store
-            } else {
-                self.grow()
-            };
-            current_store.store.push(MaybeUninit::new(val));
-            current_store.generations.push(0);
-            Id::new(arena_id, next_idx, 0)
+    fn has_readers(&self) -> bool {
+        // Only `Managed` can create the first reader, so if there are none,
+        // none can appear while we hold `&self`.
+        Arc::strong_count(&self.shared) > 1
+    }
+
+    /// Frames up to this sequence number are done with, so readers won't be
+    /// accessing slots freed or stores replaced up to this point.
+    fn retired_seq(&self) -> u64 {
+        if self.has_readers() {
+            self.alloc_state.retired_seq()
         } else {
-            // UNREACHABLE: self.free_list was checked not to be empty in the
-            // if condition that led to this else branch
-            let idx = self
-                .free_list
-                .pop()
-                .expect("UNREACHABLE: checked not to be empty") as usize;
-            let current_store = self.current_store();
-            current_store.store[idx] = MaybeUninit::new(val);
-            let gener = &mut current_store.generations[idx];
-            *gener += 1;
-            Id::new(arena_id, idx, *gener)
+            u64::MAX
         }
     }
 
+    fn collect_retired(&mut self) {
+        let retired_seq = self.retired_seq();
+        // Each store can be freed when the frames up to the point it was
+        // replaced by the next newer store are retired.
+        while self.stores.len() > 1 && self.stores[self.stores.len() - 2].alloc_seq <= retired_seq {
+            self.stores.pop_back();
+        }
+        while let Some(&(seq, idx)) = self.quarantine.front()
+            && seq <= retired_seq
+        {
+            self.quarantine.pop_front();
+            self.free_list.push(idx);
+        }
+    }
+
+    fn grow(&mut self) {
+        let full_store = self.current_store();
+        let capacity = (full_store.capacity() * 2).min(MAX_IDX + 1);
+        let store = Store::new(capacity, self.alloc_state.current_seq());
+        for idx in 0..self.slots_used {
+            // Safety: `Managed` is the only writer, and we have `&mut self`
+            unsafe { *store.slots[idx].get() = *full_store.slots[idx].get() };
+            let gener = full_store.generations[idx].load(Ordering::Relaxed);
+            store.generations[idx].store(gener, Ordering::Relaxed);
+        }
+        let store = OwnedStore::new(store);
+        // Release: the readers see the copied values when they see the new store
+        self.shared
+            .current
+            .store(store.0.as_ptr(), Ordering::Release);
+        self.stores.push_front(store);
+        self.collect_retired();
+    }
+
+    pub fn alloc(&mut self, val: T) -> Id<T> {
+        self.collect_retired();
+        let idx = match self.free_list.pop() {
+            Some(idx) => idx as usize,
+            None => {
+                assert!(self.slots_used <= MAX_IDX, "Too many values in Managed");
+                if self.slots_used == self.current_store().capacity() {
+                    self.grow();
+                }
+                self.slots_used += 1;
+                self.slots_used - 1
+            }
+        };
+        self.live += 1;
+        let arena_id = self.shared.arena_id;
+        let store = self.current_store();
+        // Safety: the slot is either unused or freed, and quarantined until
+        // no reader could be accessing it anymore. `Managed` is the only
+        // writer, and we have `&mut self`.
+        unsafe { *store.slots[idx].get() = MaybeUninit::new(val) };
+        let gener = (store.gener(idx) + 1) & GEN_MASK;
+        // Release: the readers see the value when they see the generation
+        store.generations[idx].store(gener, Ordering::Release);
+        Id::new(arena_id, idx, gener)
+    }
+
     pub fn alloc_upcast(&mut self, val: T) -> Id<I> {
         Self::upcast(self.alloc(val))
     }
 
     pub fn reap_deferred_now(&mut self) {
+        self.drain_reaped(|_, _| ());
+    }
+
+    /// Frees the values marked with `defer_free` like `reap_deferred_now`,
+    /// passing each of them to `on_reap` first. Useful for cleaning up
+    /// resources the values refer to.
+    pub fn drain_reaped(&mut self, mut on_reap: impl FnMut(Id<T>, T)) {
         self.reap_list.sort_unstable();
         self.reap_list.dedup();
-        for idx in self.reap_list.drain(..) {
-            // Safety: Only valid indices of currently live values are inserted to
-            // `reap_list`. Other methods don't insert anything. The list is drained
-            // only by `reap_deferred_now`, and possible duplicates are deduplicated,
-            // so drop and freeing procedure is called exactly once per value.
-            unsafe { self.stores[idx as usize].assume_init_drop() };
-            self.generations[idx as usize] += 1;
-            self.free_list.push(idx);
+        let has_readers = self.has_readers();
+        let seq = self.alloc_state.current_seq();
+        let arena_id = self.shared.arena_id;
+        let store = self.current_store();
+        for &idx in &self.reap_list {
+            let idx = idx as usize;
+            let gener = store.gener(idx);
+            // Safety: only valid indices of currently live values are inserted
+            // to `reap_list`, so the value is initialized.
+            on_reap(Id::new(arena_id, idx, gener), unsafe { store.read(idx) });
+            // Possible duplicates are deduplicated, so this makes the
+            // generation odd, marking the slot freed.
+            store.generations[idx].store((gener + 1) & GEN_MASK, Ordering::Release);
+        }
+        self.live -= self.reap_list.len();
+        if has_readers {
+            self.quarantine
+                .extend(self.reap_list.drain(..).map(|idx| (seq, idx)));
+        } else {
+            self.free_list.append(&mut self.reap_list);
         }
     }
 
@@ -213,9 +397,9 @@ where
     pub fn defer_free(&mut self, id: Id<T>) -> bool {
         let (arena_id, idx, gener) = id.parts();
         // Panic: documented in the docstring. Caller's responsibility.
-        assert!(arena_id == self.arena_id);
-        assert!(idx < self.generations.len());
-        if gener < self.generations[idx] {
+        assert!(arena_id == self.shared.arena_id);
+        assert!(idx < self.slots_used);
+        if gener != self.current_store().gener(idx) {
             eprintln!("Warning: trying to free already freed entity {id:?}");
             false
         } else {
@@ -237,12 +421,13 @@ where
     pub fn get(&self, id: Id<T>) -> Option<&T> {
         let (arena_id, idx, gener) = id.parts();
         // Panic: documented in the docstring. Caller's responsibility.
-        assert!(arena_id == self.arena_id);
-        assert!(idx < self.generations.len());
-        if gener == self.generations[idx] {
+        assert!(arena_id == self.shared.arena_id);
+        assert!(idx < self.slots_used);
+        let store = self.current_store();
+        if gener == store.gener(idx) {
             // Safety: if the generation matches, the value is guaranteed to be
-            // in an initialized and valid state.
-            Some(unsafe { self.stores[idx].assume_init_ref() })
+            // in an initialized and valid state. Only `&mut self` methods write.
+            Some(unsafe { (*store.slots[idx].get()).assume_init_ref() })
         } else {
             None
         }
@@ -258,26 +443,106 @@ where
     /// this case, but cross-using ID's between two separate arenas is a bug
     /// and may panic. This is considered API misuse and is on caller's
     /// responsibility.
+    ///
+    /// It also panics if there are readers, as they might be reading the
+    /// value at the same time.
     pub fn get_mut(&mut self, id: Id<T>) -> Option<&mut T> {
         let (arena_id, idx, gener) = id.parts();
         // Panic: documented in the docstring. Caller's responsibility.
-        assert!(arena_id == self.arena_id);
-        assert!(idx < self.generations.len());
-        if gener == self.generations[idx] {
+        assert!(arena_id == self.shared.arena_id);
+        assert!(idx < self.slots_used);
+        assert!(
+            self.has_readers().not(),
+            "Can't mutate values of Managed with readers"
+        );
+        let store = self.current_store();
+        if gener == store.gener(idx) {
             // Safety: if the generation matches, the value is guaranteed to be
-            // in an initialized and valid state.
-            Some(unsafe { self.stores[idx].assume_init_mut() })
+            // in an initialized and valid state. There are no readers, and
+            // `&mut self` guarantees unique access otherwise.
+            Some(unsafe { (*store.slots[idx].get()).assume_init_mut() })
         } else {
             None
         }
     }
 
+    /// Number of outgrown stores kept alive because the readers might still
+    /// be accessing them. They are freed on allocation once the frames up to
+    /// the one that outgrew them are retired.
+    pub fn outgrown_stores(&self) -> usize {
+        self.stores.len() - 1
+    }
+
+    /// Number of live values. Values marked with `defer_free` count as live
+    /// until they are reaped.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Iterates over the live values and their IDs, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> {
+        let arena_id = self.shared.arena_id;
+        let store = self.current_store();
+        (0..self.slots_used).filter_map(move |idx| {
+            let gener = store.gener(idx);
+            (gener & 1 == 0).then(|| {
+                // Safety: the generation is even, so the value is live
+                let val = unsafe { (*store.slots[idx].get()).assume_init_ref() };
+                (Id::new(arena_id, idx, gener), val)
+            })
+        })
+    }
+
+    /// Iterates mutably over the live values and their IDs, in index order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are readers, like `get_mut`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Id<T>, &mut T)> {
+        assert!(
+            self.has_readers().not(),
+            "Can't mutate values of Managed with readers"
+        );
+        let arena_id = self.shared.arena_id;
+        let store = self.current_store();
+        (0..self.slots_used).filter_map(move |idx| {
+            let gener = store.gener(idx);
+            (gener & 1 == 0).then(|| {
+                // Safety: the generation is even, so the value is live. Each
+                // index is visited once, so the references don't alias, and
+                // `&mut self` guarantees unique access otherwise.
+                let val = unsafe { (*store.slots[idx].get()).assume_init_mut() };
+                (Id::new(arena_id, idx, gener), val)
+            })
+        })
+    }
+
+    /// Marks the values for which `keep` returns `false` to be freed next time
+    /// `reap_deferred_now` or `drain_reaped` is called.
+    pub fn retain(&mut self, mut keep: impl FnMut(Id<T>, &T) -> bool) {
+        let arena_id = self.shared.arena_id;
+        // Borrowing `self.stores` directly keeps `self.reap_list` available
+        let store = self.stores.front().expect("UNREACHABLE: never empty");
+        for idx in 0..self.slots_used {
+            let gener = store.gener(idx);
+            if gener & 1 != 0 {
+                continue;
+            }
+            // Safety: the generation is even, so the value is live
+            let val = unsafe { (*store.slots[idx].get()).assume_init_ref() };
+            if keep(Id::new(arena_id, idx, gener), val).not() {
+                self.reap_list.push(idx as u32);
+            }
+        }
+    }
+
     /// Checks if given index contains a valid, living object.
     pub fn is_idx_live(&self, idx: usize) -> bool {
-        self.generations
-            .get(idx)
-            .map(|gener| gener & 1 == 0)
-            .unwrap_or(false)
+        idx < self.slots_used && self.current_store().gener(idx) & 1 == 0
     }
 
     pub fn upcast(id: Id<T>) -> Id<I> {
@@ -295,14 +560,70 @@ where
     }
 }
 
-impl<'l, T, I> Drop for Managed<'l, T, I> {
+impl<T, I> Drop for Managed<'_, T, I> {
     fn drop(&mut self) {
-        for idx in 0..self.stores.len() {
-            if self.is_idx_live(idx) {
-                // Safety: `is_idx_live` examines the generation, and guarantees
-                // the value is in an initialized and valid state.
-                unsafe { self.stores[idx].assume_init_drop() };
-            }
+        // The values don't need to be dropped (see `check_drop_static`), but
+        // the readers might still be accessing the stores, so they are kept
+        // around until the last reader is dropped too.
+        let stores = take(&mut self.stores);
+        self.shared
+            .graveyard
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(stores);
+    }
+}
+
+impl<T, I> Clone for ManagedReader<T, I> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            _iface_marker: PhantomData,
+        }
+    }
+}
+
+impl<T, I> ManagedReader<T, I>
+where
+    T: Copy,
+{
+    /// Retrieves a copy of the value associated with the given ID.
+    ///
+    /// A value freed after the frame being read was built is still returned,
+    /// as the frame refers to it. Returns `None` if the value has been freed
+    /// and its slot reused.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the ID points to an invalid index.
+    /// If you use an ID retrieved from this arena, you will never encounter
+    /// this case, but cross-using ID's between two separate arenas is a bug
+    /// and may panic. This is considered API misuse and is on caller's
+    /// responsibility.
+    ///
+    /// # Safety
+    ///
+    /// `id` must come from a frame that hasn't been retired yet, and the frame
+    /// must not be retired before this function returns. Retiring the frame
+    /// lets `Managed` free the store it was read from and reuse the slots it
+    /// freed, while this function still reads them.
+    pub unsafe fn get(&self, id: Id<T>) -> Option<T> {
+        let (arena_id, idx, gener) = id.parts();
+        // Panic: documented in the docstring. Caller's responsibility.
+        assert!(arena_id == self.shared.arena_id);
+        // Safety: the store is freed only after it has been replaced, and the
+        // frames up to replacing it are retired. The caller guarantees the
+        // frame of the ID isn't retired, so the store is still alive.
+        let store = unsafe { &*self.shared.current.load(Ordering::Acquire) };
+        assert!(idx < store.capacity());
+        let current = store.gener(idx);
+        if current == gener || current == (gener + 1) & GEN_MASK {
+            // Safety: the value is initialized, as it was live when the frame
+            // was built. `Managed` doesn't write to the slot before it's freed
+            // and the frames up to freeing it are retired.
+            Some(unsafe { store.read(idx) })
+        } else {
+            None
         }
     }
 }
@@ -313,43 +634,54 @@ mod tests {
     use std::{
         cmp::Ordering,
         hash::{DefaultHasher, Hasher},
+        sync::mpsc,
+        thread,
     };
 
+    /// Reads a value the way the renderer does.
+    fn read<T: Copy>(reader: &ManagedReader<T>, id: Id<T>) -> Option<T> {
+        // Safety: the tests retire the frames only after reading the IDs
+        // allocated in them.
+        unsafe { reader.get(id) }
+    }
+
     #[test]
     fn test_managed() {
-        let mut managed = Managed::<_, Erased>::new(1);
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
 
-        assert_eq!(managed.is_idx_live(0), false);
-        assert_eq!(managed.is_idx_live(1), false);
-        assert_eq!(managed.is_idx_live(2), false);
+        assert!(managed.is_idx_live(0).not());
+        assert!(managed.is_idx_live(1).not());
+        assert!(managed.is_idx_live(2).not());
         let id = managed.alloc(5);
-        assert_eq!(managed.is_idx_live(id.idx()), true);
+        assert!(managed.is_idx_live(id.idx()));
         assert_eq!(managed.get(id), Some(&5));
         assert_eq!(managed.get_mut(id), Some(&mut 5));
-        assert_eq!(managed.defer_free(id), true);
+        assert!(managed.defer_free(id));
         assert_eq!(managed.get(id), Some(&5));
-        assert_eq!(managed.is_idx_live(id.idx()), true);
+        assert!(managed.is_idx_live(id.idx()));
         managed.reap_deferred_now();
-        assert_eq!(managed.is_idx_live(id.idx()), false);
-        assert_eq!(managed.defer_free(id), false);
+        assert!(managed.is_idx_live(id.idx()).not());
+        assert!(managed.defer_free(id).not());
         assert_eq!(managed.get(id), None);
         assert_eq!(managed.get_mut(id), None);
         let id2 = managed.alloc(6);
         let id3 = managed.alloc(7);
-        assert_eq!(managed.is_idx_live(id.idx()), true); // Because the slot is being reused
-        assert_eq!(managed.is_idx_live(id2.idx()), true);
-        assert_eq!(managed.is_idx_live(id3.idx()), true);
+        assert!(managed.is_idx_live(id.idx())); // Because the slot is being reused
+        assert!(managed.is_idx_live(id2.idx()));
+        assert!(managed.is_idx_live(id3.idx()));
         assert_eq!(managed.get(id2), Some(&6));
         assert_ne!(id, id2);
-        assert_eq!(managed.is_idx_live(0), true);
-        assert_eq!(managed.is_idx_live(1), true);
-        assert_eq!(managed.is_idx_live(2), false);
+        assert!(managed.is_idx_live(0));
+        assert!(managed.is_idx_live(1));
+        assert!(managed.is_idx_live(2).not());
     }
 
     #[test]
     fn test_id() {
         struct Dummy;
         let id: Id<Dummy> = Id::new(1, 3, 5);
+        #[allow(clippy::clone_on_copy)]
         let _id2 = id.clone();
         let _id3 = id; // using Copy trait
         assert!(id.eq(&_id2)); // Using Eq trait
@@ -362,22 +694,208 @@ mod tests {
         assert_eq!(state.finish(), state2.finish());
         assert_eq!(
             format!("{id:?}"),
-            "Id(idx=3, gen=5, type=arena::managed::tests::test_id::Dummy)"
+            "Id(arena_id=1, idx=3, gen=5, type=arena::managed_sync::tests::test_id::Dummy)"
         );
     }
 
     #[test]
-    fn managed_drop_on_free() {
-        let mut managed = Managed::<_, Erased>::new(1);
-        let id = managed.alloc(String::from("Hello, World!"));
-        managed.defer_free(id);
+    #[cfg_attr(miri, ignore)]
+    fn test_generation_wraps() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let mut id = managed.alloc(0);
+        for i in 1..=(GEN_MASK / 2 + 1) {
+            managed.defer_free(id);
+            managed.reap_deferred_now();
+            id = managed.alloc(i);
+        }
+        assert_eq!(id.idx(), 0);
+        assert_eq!(id.gener(), 0);
+        assert_eq!(managed.get(id), Some(&(GEN_MASK / 2 + 1)));
+    }
+
+    #[test]
+    fn test_grow() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let ids: Vec<_> = (0..100).map(|i| managed.alloc(i)).collect();
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!(managed.get(id), Some(&i));
+        }
+        // Without readers, the outgrown stores are freed right away
+        assert_eq!(managed.stores.len(), 1);
+    }
+
+    #[test]
+    fn test_reader_retirement() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let reader = managed.reader();
+        assert_eq!(alloc_state.next_seq(), 1);
+
+        let ids: Vec<_> = (0..INITIAL_CAPACITY + 1)
+            .map(|i| managed.alloc(i))
+            .collect();
+        assert_eq!(managed.stores.len(), 2);
+        assert_eq!(read(&reader, ids[0]), Some(0));
+        assert_eq!(read(&reader, ids[INITIAL_CAPACITY]), Some(INITIAL_CAPACITY));
+
+        // The freed slot isn't reused until the frame freeing it is retired,
+        // so the frames referring to it can still read it
+        managed.defer_free(ids[0]);
         managed.reap_deferred_now();
+        assert_eq!(managed.get(ids[0]), None);
+        assert_eq!(read(&reader, ids[0]), Some(0));
+        assert_eq!(alloc_state.next_seq(), 2);
+        let id = managed.alloc(100);
+        assert_ne!(id.idx(), ids[0].idx());
+
+        alloc_state.retire_up_to(1);
+        let id = managed.alloc(101);
+        assert_eq!(id.idx(), ids[0].idx());
+        assert_eq!(read(&reader, id), Some(101));
+        assert_eq!(read(&reader, ids[0]), None);
+        assert_eq!(managed.stores.len(), 1);
     }
 
     #[test]
-    fn managed_drop_on_container_drop() {
-        let mut managed = Managed::<_, Erased>::new(1);
-        let _ = managed.alloc(String::from("Hello, World!"));
-        drop(managed)
+    fn test_grown_store_retirement() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let reader = managed.reader();
+        alloc_state.next_seq(); // Frame 1
+        let ids: Vec<_> = (0..INITIAL_CAPACITY).map(|i| managed.alloc(i)).collect();
+
+        let seq = alloc_state.next_seq(); // Frame 2 outgrows the store
+        managed.alloc(INITIAL_CAPACITY);
+        assert_eq!(managed.outgrown_stores(), 1);
+
+        // The renderer might still be reading the old store while rendering
+        // frame 1, or while picking up frame 2
+        alloc_state.retire_up_to(seq - 1);
+        alloc_state.next_seq();
+        managed.alloc(0);
+        assert_eq!(managed.outgrown_stores(), 1);
+        assert_eq!(read(&reader, ids[0]), Some(0));
+
+        alloc_state.retire_up_to(seq);
+        managed.alloc(0);
+        assert_eq!(managed.outgrown_stores(), 0);
+        assert_eq!(read(&reader, ids[0]), Some(0));
+    }
+
+    #[test]
+    fn test_reader_outlives_managed() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let reader = managed.reader();
+        let id = managed.alloc(5);
+        drop(managed);
+        assert_eq!(read(&reader, id), Some(5));
+    }
+
+    #[test]
+    fn test_iter() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        assert!(managed.is_empty());
+        let ids: Vec<_> = (0..5).map(|i| managed.alloc(i)).collect();
+        managed.defer_free(ids[1]);
+        managed.defer_free(ids[3]);
+        assert_eq!(managed.len(), 5);
+        managed.reap_deferred_now();
+        assert_eq!(managed.len(), 3);
+
+        let live: Vec<_> = managed.iter().map(|(id, &v)| (id, v)).collect();
+        assert_eq!(live, [(ids[0], 0), (ids[2], 2), (ids[4], 4)]);
+
+        for (_, v) in managed.iter_mut() {
+            *v *= 10;
+        }
+        assert_eq!(managed.get(ids[2]), Some(&20));
+        assert_eq!(managed.get(ids[4]), Some(&40));
+    }
+
+    #[test]
+    fn test_retain_drain_reaped() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let ids: Vec<_> = (0..6).map(|i| managed.alloc(i)).collect();
+        managed.retain(|_, &v| v % 2 == 0);
+        // Frees are deferred
+        assert_eq!(managed.len(), 6);
+        assert_eq!(managed.get(ids[1]), Some(&1));
+
+        let mut reaped = Vec::new();
+        managed.drain_reaped(|id, v| reaped.push((id, v)));
+        assert_eq!(reaped, [(ids[1], 1), (ids[3], 3), (ids[5], 5)]);
+        assert_eq!(managed.len(), 3);
+        assert_eq!(managed.get(ids[1]), None);
+        assert!(managed.iter().all(|(_, &v)| v % 2 == 0));
+
+        // Already reaped values aren't reaped again
+        managed.drain_reaped(|_, _| panic!("Nothing to reap"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_get_mut_with_readers() {
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<_, Erased>::new(&alloc_state);
+        let _reader = managed.reader();
+        let id = managed.alloc(5);
+        managed.get_mut(id);
+    }
+
+    /// Simulates the logic and render threads: the logic allocates and frees
+    /// values each frame and sends the IDs to the renderer, which reads them
+    /// and then retires the frame.
+    #[test]
+    fn test_concurrent_reader() {
+        const FRAMES: u64 = if cfg!(miri) { 20 } else { 1000 };
+        let alloc_state = SharedAllocState::default();
+        let mut managed = Managed::<(u64, u64), Erased>::new(&alloc_state);
+        let reader = managed.reader();
+        let (sender, receiver) = mpsc::sync_channel::<(u64, Vec<Id<(u64, u64)>>)>(2);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for (seq, ids) in receiver {
+                    for id in ids {
+                        let (_, val) = read(&reader, id).expect("live in this frame");
+                        assert_eq!(val, id.idx() as u64);
+                    }
+                    alloc_state.retire_up_to(seq);
+                }
+            });
+
+            let mut live = VecDeque::new();
+            for _ in 0..FRAMES {
+                let seq = alloc_state.next_seq();
+                for _ in 0..3 {
+                    let id = managed.alloc((seq, 0));
+                    *managed.get_mut_unchecked(id) = (seq, id.idx() as u64);
+                    live.push_back(id);
+                }
+                while live.len() > 20 {
+                    let id = live.pop_front().expect("not empty");
+                    managed.defer_free(id);
+                }
+                managed.reap_deferred_now();
+                sender
+                    .send((seq, live.iter().copied().collect()))
+                    .expect("reader alive");
+            }
+            drop(sender);
+        });
+    }
+
+    impl<T: Copy, I> Managed<'_, T, I> {
+        /// For setting up values in tests with readers around, before the
+        /// values are shown to the readers.
+        fn get_mut_unchecked(&mut self, id: Id<T>) -> &mut T {
+            let store = self.current_store();
+            unsafe { (*store.slots[id.idx()].get()).assume_init_mut() }
+        }
     }
 }
@@ -1,5 +1,5 @@
 use super::*;
-use std::{mem::transmute_copy, ptr::addr_eq};
+use std::mem::transmute_copy;
 
 #[test]
 fn test_no_allocation() {
@@ -156,6 +156,40 @@ fn test_different_val_alignments() {
     assert_eq!(usage.content_bytes, expected_content);
 }
 
+#[test]
+fn test_over_aligned() {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(align(32))]
+    struct Simd8([f32; 8]);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(align(64))]
+    struct CacheLine(u64);
+
+    let mut alloc = Arena::default();
+    for seq in 1..=3 {
+        let arena = alloc.fresh_arena(seq);
+        // Odd sizes and many values, to misalign and grow the chunks
+        let _ = arena.alloc_val(1u8);
+        for i in 0..20 {
+            let simd = arena.alloc_val(Simd8([i as f32; 8]));
+            assert!(simd.0.as_ptr().addr().is_multiple_of(32));
+            assert_eq!(simd.0[7], i as f32);
+            let line = arena.alloc_val(CacheLine(i));
+            assert!((&raw const *line).addr().is_multiple_of(64));
+            assert_eq!(line.0, i);
+        }
+        let simds = arena.alloc_slice(&[Simd8([1.0; 8]); 5]);
+        assert!(simds.as_ptr().addr().is_multiple_of(32));
+        assert_eq!(simds[4], Simd8([1.0; 8]));
+
+        let vec = arena.alloc_vec::<CacheLine>();
+        vec.extend((0..10).map(CacheLine));
+        assert!(vec.as_ptr().addr().is_multiple_of(64));
+        assert_eq!(vec[9], CacheLine(9));
+    }
+}
+
 #[test]
 fn test_vec_reset() {
     let mut alloc = Arena::default();
@@ -198,8 +232,8 @@ fn test_large_vec_allocation() {
         vec.push(i);
     }
 
-    for i in 0..1000 {
-        assert_eq!(vec[i], i as u32);
+    for i in 0..1000u32 {
+        assert_eq!(vec[i as usize], i);
     }
 
     let usage = alloc.memory_usage();
@@ -291,7 +325,11 @@ fn test_zero_sized_val() {
     let empty1 = arena.alloc_val(());
     let empty2 = arena.alloc_val(());
 
-    assert!(addr_eq(empty1, empty2));
+    // With `debug_alloc`, each value is preceded by an 8-byte canary
+    let canary_len = if cfg!(feature = "debug_alloc") { 8 } else { 0 };
+    let empty1_addr = (empty1 as *const ()).addr();
+    let empty2_addr = (empty2 as *const ()).addr();
+    assert_eq!(empty2_addr - empty1_addr, canary_len);
 }
 
 #[test]
@@ -319,7 +357,7 @@ fn test_val_memory_usage() {
     let mut empty_usage = MemoryUsage::default();
     arena.val_memory_usage(&mut empty_usage);
     assert_eq!(empty_usage.content_bytes, 0);
-    assert_eq!(empty_usage.capacity_bytes, 80);
+    assert_eq!(empty_usage.capacity_bytes, 7 * 16);
 
     // Allocate small values
     let _val1 = arena.alloc_val(1u8);
@@ -334,7 +372,8 @@ fn test_val_memory_usage() {
     // Calculate exact content bytes for small allocations
     let expected_small_content = size_of::<u8>() + size_of::<u16>() + size_of::<u32>();
     assert_eq!(small_alloc_usage.content_bytes, expected_small_content);
-    assert!(small_alloc_usage.total_bytes() == empty_usage.total_bytes());
+    // No new memory; with `debug_alloc`, only the canaries add overhead
+    assert_eq!(small_alloc_usage.capacity_bytes, empty_usage.capacity_bytes);
 
     // Allocate a large array
     let _large_val = arena.alloc_val([42u64; 64]); // 512 bytes
@@ -370,9 +409,9 @@ fn test_val_memory_usage() {
     let expected_content2 = size_of::<u32>();
     assert_eq!(usage2.content_bytes, expected_content2);
 
-    // Total memory should be the same after reset and reallocation
+    // Capacity should be the same after reset and reallocation
     // (since we're reusing memory)
-    assert!(usage2.total_bytes() == usage1.total_bytes());
+    assert_eq!(usage2.capacity_bytes, usage1.capacity_bytes);
 }
 
 #[test]
@@ -429,7 +468,7 @@ fn test_vec_memory_usage() {
     let arena = alloc.fresh_arena(1);
 
     assert_eq!(initial_usage.content_bytes, 0);
-    assert_eq!(initial_usage.capacity_bytes, 5 * 4 * 4);
+    assert_eq!(initial_usage.capacity_bytes, 7 * 4 * 4);
 
     let vec1 = arena.alloc_vec::<u8>();
     vec1.extend_from_slice(&[1, 2, 3, 4, 5]);
@@ -578,6 +617,66 @@ fn test_alloc_ignoring_drop() {
     let _ = arena.alloc_slice_ignore_drop(&[String::from("Hello, World 3!")]);
 }
 
+#[test]
+fn test_alloc_with_drop() {
+    use std::sync::{Arc, Mutex};
+
+    struct Noisy(u32, Arc<Mutex<Vec<u32>>>);
+    impl Drop for Noisy {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let mut alloc = Arena::default();
+    let arena = alloc.fresh_arena(1);
+    let _ = arena.alloc_val_with_drop(Noisy(1, dropped.clone()));
+    let vec = arena.alloc_vec_with_drop();
+    vec.push(Noisy(2, dropped.clone()));
+    vec.push(Noisy(3, dropped.clone()));
+    let _ = arena.alloc_val_with_drop(Noisy(4, dropped.clone()));
+    let arc = Arc::new(5);
+    let _ = arena.alloc_val_with_drop(arc.clone());
+    assert_eq!(Arc::strong_count(&arc), 2);
+    assert!(dropped.lock().unwrap().is_empty());
+
+    // Dropped on reset, in reverse allocation order
+    let arena = alloc.fresh_arena(2);
+    assert_eq!(*dropped.lock().unwrap(), [4, 2, 3, 1]);
+    assert_eq!(Arc::strong_count(&arc), 1);
+
+    // The vecs are reused without dropping the contents again
+    let vec = arena.alloc_vec_with_drop();
+    assert!(vec.is_empty());
+    vec.push(Noisy(6, dropped.clone()));
+    let _ = alloc.fresh_arena(3);
+    assert_eq!(*dropped.lock().unwrap(), [4, 2, 3, 1, 6]);
+
+    let arena = alloc.fresh_arena(4);
+    let _ = arena.alloc_val_with_drop(Noisy(7, dropped.clone()));
+    drop(alloc);
+    assert_eq!(*dropped.lock().unwrap(), [4, 2, 3, 1, 6, 7]);
+}
+
+#[test]
+fn test_alloc_with_drop_other_thread() {
+    use std::sync::{Arc, Mutex};
+
+    let mut alloc = Arena::default();
+    let arena = alloc.fresh_arena(1);
+    let val = Arc::new(Mutex::new(1));
+    let _ = arena.alloc_val_with_drop(val.clone());
+    // Dropped on the thread that resets the arena, like the init frame that
+    // is allocated on the main thread and reset on the logic thread
+    std::thread::spawn(move || {
+        let _ = alloc.fresh_arena(2);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(Arc::strong_count(&val), 1);
+}
+
 #[test]
 fn test_alloc_slice() {
     let mut alloc = Arena::default();
@@ -599,7 +698,7 @@ fn test_alloc_slice() {
     assert_eq!(slice5, &[4.0, 5.0, 6.0]);
 
     let usage = alloc.memory_usage();
-    let expected_bytes = 8 * size_of::<u32>() + 3 * size_of::<f32>() + 3 * size_of::<u64>();
+    let expected_bytes = size_of::<[u32; 8]>() + size_of::<[f32; 3]>() + size_of::<[u64; 3]>();
     assert_eq!(usage.content_bytes, expected_bytes);
 }
 
@@ -667,7 +766,7 @@ fn test_from_faulty_iter() {
 
     let mut usage_before = MemoryUsage::default();
     arena.val_memory_usage(&mut usage_before);
-    assert_eq!(usage_before.content_bytes, 1 * size_of::<u32>());
+    assert_eq!(usage_before.content_bytes, size_of::<u32>());
 
     let b = arena.alloc_iter(too_short);
     assert_eq!(b, &[4, 5, 6]);
@@ -737,6 +836,18 @@ fn test_sink() {
     assert_eq!(slice, &expected)
 }
 
+#[test]
+fn test_sink_over_partly_filled_chunk() {
+    let mut arena = ArenaGuard::new();
+    let _ = arena.alloc_val(123u32);
+    let mut sink = arena.alloc_sink();
+    // The chunk isn't filled to the end when the second item doesn't fit
+    sink.push([1u32, 2]);
+    sink.push([3, 4]);
+    sink.push([5, 6]);
+    assert_eq!(sink.into_slice(), &[[1, 2], [3, 4], [5, 6]]);
+}
+
 #[test]
 fn test_string_vec_layout_comp() {
     assert_eq!(size_of::<String>(), size_of::<Vec<u8>>());
@@ -747,7 +858,7 @@ fn test_string_vec_layout_comp() {
         let mut string = String::with_capacity(cap);
         let mut vec = Vec::with_capacity(cap);
         string.push_str(&lorem[..len]);
-        vec.extend_from_slice(lorem[..len].as_bytes());
+        vec.extend_from_slice(&lorem.as_bytes()[..len]);
 
         let string_bytes: [usize; 3] = unsafe { transmute_copy(&string) };
         let vec_bytes: [usize; 3] = unsafe { transmute_copy(&vec) };
@@ -769,7 +880,7 @@ fn test_string_vec_layout_comp() {
         let mut string = String::with_capacity(cap);
         let mut vec = Vec::with_capacity(cap);
         string.push_str(&lorem[..len]);
-        vec.extend_from_slice(lorem[..len].as_bytes());
+        vec.extend_from_slice(&lorem.as_bytes()[..len]);
         let tx_string = unsafe { transmute::<&mut Vec<u8>, &mut String>(&mut vec) };
         let tx_vec = unsafe { transmute::<&mut String, &mut Vec<u8>>(&mut string) };
 
@@ -792,6 +903,49 @@ fn test_string_vec_layout_comp() {
     }
 }
 
+#[test]
+#[cfg(feature = "debug_alloc")]
+fn test_debug_poison_vec() {
+    let mut alloc = Arena::default();
+    let arena = alloc.fresh_arena(1);
+    let vec = arena.alloc_vec::<u8>();
+    vec.extend_from_slice(&[1, 2, 3]);
+
+    let arena = alloc.fresh_arena(2);
+    let vec = arena.alloc_vec::<u8>();
+    // Safety: the poisoned bytes are initialized
+    let spare: Vec<u8> = unsafe { transmute(vec.spare_capacity_mut().to_vec()) };
+    assert!(spare.len() >= 3);
+    assert!(spare.iter().all(|&b| b == 0xDD));
+}
+
+#[test]
+#[cfg(feature = "debug_alloc")]
+fn test_debug_canaries_intact() {
+    let mut alloc = Arena::default();
+    let arena = alloc.fresh_arena(1);
+    for i in 0..100u64 {
+        *arena.alloc_val(i) += 1;
+        arena.alloc_slice(&[i as u8; 3])[2] = 0;
+    }
+    arena.check_canaries();
+    let _ = alloc.fresh_arena(2);
+}
+
+#[test]
+#[cfg(feature = "debug_alloc")]
+#[cfg_attr(miri, ignore)] // Ignore Miri because this deliberately writes out of bounds
+#[should_panic(expected = "Arena canary overwritten: a value of alignment 4")]
+fn test_debug_canary_overwritten() {
+    let mut alloc = Arena::default();
+    let arena = alloc.fresh_arena(1);
+    let val = arena.alloc_val(1u32) as *mut u32;
+    arena.alloc_val(2u32);
+    // Safety: it isn't; this simulates a buggy write past the end of `val`
+    unsafe { val.add(1).write(0) };
+    let _ = alloc.fresh_arena(2);
+}
+
 // Expect: error[E0499]: cannot borrow `alloc` as mutable more than once at a time
 /// Test: getting memory usage invalidates the arena
 /// ```compile_fail
@@ -842,6 +996,43 @@ pub struct _CompileFailAllocationsCantOutliveArena;
 /// ```
 pub struct _CompileFailNewArenaInvalidatesOldArena;
 
+// Expect: error[E0499]: cannot borrow `alloc` as mutable more than once at a time
+/// Test: getting a new arena makes the maps of the old one unusable
+/// ```compile_fail
+/// let mut alloc = arena::Arena::default();
+/// let arena = alloc.fresh_arena(1);
+///
+/// let map = arena.alloc_hash_map::<u32, u32>();
+/// map.insert(1, 42);
+///
+/// let arena = alloc.fresh_arena(2);
+/// map.insert(2, 43);
+/// ```
+pub struct _CompileFailNewArenaInvalidatesOldHashMap;
+
+// Expect: error[E0597]: `alloc` does not live long enough
+/// Test: allocated maps can't outlive the allocator
+/// ```compile_fail
+/// let outlived_map = {
+///     let mut alloc = arena::Arena::default();
+///     let arena = alloc.fresh_arena(1);
+///
+///     let map = arena.alloc_sorted_map::<u32, u32>();
+///     map.insert(1, 42);
+///     map
+/// };
+/// ```
+pub struct _CompileFailMapsCantOutliveArena;
+
+// Expect: evaluation of `arena::ArenaGuard::<'_>::check_drop_static::<std::string::String>::{constant#0}` failed
+/// Test: attempt allocing a set of values with Drop implementation
+/// ```compile_fail
+/// let mut alloc = arena::Arena::default();
+/// let arena = alloc.fresh_arena(1);
+/// let _ = arena.alloc_hash_set::<String>();
+/// ```
+pub struct _CompileFailForbidDropOnHashSet;
+
 // Expect: evaluation of `arena::ArenaGuard::<'_>::check_drop_static::<std::string::String>::{constant#0}` failed
 /// Test: attempt allocing a value with Drop implementation
 /// ```compile_fail
@@ -861,22 +1052,60 @@ pub struct _CompileFailForbidDropOnVal;
 /// ```
 pub struct _CompileFailForbidDropOnVec;
 
-// Expect: error[E0080]: evaluation of `arena::ArenaGuard::<'_>::check_alignment_static::<main::_doctest_main_libs_arena_src_tests_rs_819_0::TestAlign32>::{constant#0}` failed
+// Expect: error[E0597]: `s` does not live long enough
+/// Test: attempt allocing a non-'static value with Drop, that would be
+/// dropped after the borrowed value
+/// ```compile_fail
+/// let mut alloc = arena::Arena::default();
+/// {
+///     let s = String::from("Hello, World!");
+///     let arena = alloc.fresh_arena(1);
+///     arena.alloc_val_with_drop(vec![&s]);
+/// }
+/// let _ = alloc.fresh_arena(2);
+/// ```
+pub struct _CompileFailDropNonStatic;
+
+// Expect: error[E0277]: `Rc<i32>` cannot be sent between threads safely
+/// Test: attempt allocing a non-`Send` value with Drop, that would be dropped
+/// on whichever thread resets the arena
+/// ```compile_fail
+/// let mut alloc = arena::Arena::default();
+/// let arena = alloc.fresh_arena(1);
+/// arena.alloc_val_with_drop(std::rc::Rc::new(1));
+/// ```
+pub struct _CompileFailDropNonSend;
+
+// Expect: error[E0277]: `Cell<u32>` cannot be shared between threads safely
+/// Test: attempt reading values that aren't `Sync` from another thread, while
+/// the owner hands out references to them. `Cell<u32>` itself isn't `Copy`, so
+/// this wraps its marker in one that is.
+/// ```compile_fail
+/// #[derive(Clone, Copy)]
+/// struct NotSync(std::marker::PhantomData<std::cell::Cell<u32>>);
+/// let alloc_state = arena::SharedAllocState::default();
+/// let managed = arena::Managed::<NotSync>::new(&alloc_state);
+/// let reader = managed.reader();
+/// std::thread::spawn(move || drop(reader));
+/// ```
+pub struct _CompileFailManagedNonSync;
+
+// Expect: error[E0080]: evaluation of `arena::ArenaGuard::<'_>::check_alignment_static::<main::_doctest_main_libs_arena_src_tests_rs_819_0::TestAlign128>::{constant#0}` failed
 /// ```compile_fail
-/// #[repr(align(32))]
-/// struct TestAlign32;
+/// #[repr(align(128))]
+/// struct TestAlign128;
 /// let mut alloc = arena::Arena::default();
 /// let arena = alloc.fresh_arena(1);
-/// let _ = arena.alloc_val(TestAlign32);
+/// let _ = arena.alloc_val(TestAlign128);
 /// ```
 pub struct _CompileFailUnsupportedValAlign;
 
-// Expect: error[E0080]: evaluation of `arena::ArenaGuard::<'_>::check_alignment_static::<main::_doctest_main_libs_arena_src_tests_rs_819_0::TestAlign32>::{constant#0}` failed
+// Expect: error[E0080]: evaluation of `arena::ArenaGuard::<'_>::check_alignment_static::<main::_doctest_main_libs_arena_src_tests_rs_819_0::TestAlign128>::{constant#0}` failed
 /// ```compile_fail
-/// #[repr(align(32))]
-/// struct TestAlign32;
+/// #[repr(align(128))]
+/// struct TestAlign128;
 /// let mut alloc = arena::Arena::default();
 /// let arena = alloc.fresh_arena(1);
-/// let _ = arena.alloc_vec::<TestAlign32>();
+/// let _ = arena.alloc_vec::<TestAlign128>();
 /// ```
 pub struct _CompileFailUnsupportedVecAlign;
@@ -1,22 +1,42 @@
 use std::{cmp::max, mem::MaybeUninit};
 
+#[cfg(feature = "debug_alloc")]
+use crate::debug;
 use crate::{Erased, ErasedMax, MemoryUsage};
 
-#[derive(Debug)]
-struct ValChunk(Box<[MaybeUninit<ErasedMax>]>);
+const UNIT: usize = size_of::<MaybeUninit<ErasedMax>>();
 
-impl Default for ValChunk {
-    fn default() -> Self {
-        Self::new(size_of::<MaybeUninit<ErasedMax>>())
-    }
+/// A chunk of memory for values. The chunk is made of maximally aligned
+/// units, so it's aligned for all values up to that alignment. For values
+/// with greater alignment, the chunk has extra units of padding, and the
+/// usable part starts from the first suitably aligned address, at `offset`.
+#[derive(Debug)]
+struct ValChunk {
+    buf: Box<[MaybeUninit<ErasedMax>]>,
+    offset: usize,
+    padding_bytes: usize,
 }
 
 impl ValChunk {
-    fn new(cap_bytes: usize) -> Self {
-        let capacity = cap_bytes.div_ceil(size_of::<MaybeUninit<ErasedMax>>());
+    fn new(cap_bytes: usize, align: usize) -> Self {
+        let capacity = cap_bytes.div_ceil(UNIT);
         let mut vec = Vec::with_capacity(capacity);
         vec.resize_with(capacity, MaybeUninit::uninit);
-        ValChunk(vec.into_boxed_slice())
+        Self::from_vec(vec, align)
+    }
+
+    fn from_vec(mut vec: Vec<MaybeUninit<ErasedMax>>, align: usize) -> Self {
+        let padding = align.saturating_sub(UNIT).div_ceil(UNIT);
+        vec.resize_with(vec.len() + padding, MaybeUninit::uninit);
+        let buf = vec.into_boxed_slice();
+        let offset = (buf.as_ptr() as *const u8).align_offset(align);
+        let padding_bytes = padding * UNIT;
+        debug_assert!(offset <= padding_bytes);
+        ValChunk {
+            buf,
+            offset,
+            padding_bytes,
+        }
     }
 
     fn as_ptr(&self) -> *const [MaybeUninit<ErasedMax>] {
@@ -29,11 +49,12 @@ impl ValChunk {
         //
         // See https://github.com/rust-lang/miri/issues/4317
         let chunk_ptr = &raw const *self as *mut Self;
-        unsafe { &raw mut *(*chunk_ptr).0 }
+        unsafe { &raw mut *(*chunk_ptr).buf }
     }
 
+    /// Usable capacity, not counting the padding
     fn cap_bytes(&self) -> usize {
-        self.as_ptr().len() * size_of::<MaybeUninit<ErasedMax>>()
+        self.as_ptr().len() * UNIT - self.padding_bytes
     }
 
     /// Returns a mutable reference to the vector at the given index.
@@ -49,7 +70,7 @@ impl ValChunk {
     unsafe fn get_mut(&mut self, byte_offset: usize) -> *mut Erased {
         let buf_start_ptr = self.as_ptr() as *mut MaybeUninit<Erased>;
         // Safety: caller's responsibility as documented
-        let obj_ptr = unsafe { buf_start_ptr.byte_add(byte_offset) };
+        let obj_ptr = unsafe { buf_start_ptr.byte_add(self.offset + byte_offset) };
         obj_ptr as *mut Erased
     }
 
@@ -66,45 +87,73 @@ impl ValChunk {
     unsafe fn get_const(&self, byte_offset: usize) -> *const Erased {
         let buf_start_ptr = self.as_ptr() as *const MaybeUninit<Erased>;
         // Safety: caller's responsibility as documented
-        let obj_ptr = unsafe { buf_start_ptr.byte_add(byte_offset) };
+        let obj_ptr = unsafe { buf_start_ptr.byte_add(self.offset + byte_offset) };
         obj_ptr as *const Erased
     }
 
-    fn new_from_chunks(chunks: &mut Vec<ValChunk>, prev_content_bytes: usize) -> ValChunk {
+    fn new_from_chunks(
+        chunks: &mut Vec<ValChunk>,
+        prev_content_bytes: usize,
+        align: usize,
+    ) -> ValChunk {
         let mut new = Vec::with_capacity(prev_content_bytes * 2);
         for chunk in chunks.drain(..) {
-            new.extend(chunk.0.into_iter());
+            new.extend(chunk.buf);
         }
         new.resize_with(new.capacity(), MaybeUninit::uninit);
-        ValChunk(new.into_boxed_slice())
+        ValChunk::from_vec(new, align)
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct ByAlign {
+    align: usize,
     last: usize,
     last_used_bytes: usize,
     chunks: Vec<ValChunk>,
     total_content_bytes: usize,
+    /// Chunk indices, byte offsets and lengths of the canaries of this
+    /// sequence
+    #[cfg(feature = "debug_alloc")]
+    canaries: Vec<(usize, usize, usize)>,
+    #[cfg(feature = "debug_alloc")]
+    canary_bytes: usize,
 }
 
 impl ByAlign {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(align: usize) -> Self {
         ByAlign {
+            align,
             last: 0,
             last_used_bytes: 0,
-            chunks: vec![ValChunk::default()],
+            chunks: vec![ValChunk::new(UNIT, align)],
             total_content_bytes: 0,
+            #[cfg(feature = "debug_alloc")]
+            canaries: Vec::new(),
+            #[cfg(feature = "debug_alloc")]
+            canary_bytes: 0,
         }
     }
 
     pub(crate) fn reset(&mut self) {
+        #[cfg(feature = "debug_alloc")]
+        {
+            self.check_canaries();
+            self.canaries.clear();
+            self.canary_bytes = 0;
+            for chunk in &mut self.chunks {
+                let cap_bytes = chunk.cap_bytes();
+                // Safety: the usable part of the chunk is in bounds, and the
+                // values of the previous sequence are not accessed anymore
+                unsafe { debug::poison(chunk.get_mut(0) as *mut u8, cap_bytes) };
+            }
+        }
         self.last_used_bytes = 0;
         let prev_content_bytes = self.total_content_bytes;
         self.total_content_bytes = 0;
         if self.last > 0 {
             self.last = 0;
-            let chunk = ValChunk::new_from_chunks(&mut self.chunks, prev_content_bytes);
+            let chunk = ValChunk::new_from_chunks(&mut self.chunks, prev_content_bytes, self.align);
             self.chunks.push(chunk);
         }
     }
@@ -114,23 +163,29 @@ impl ByAlign {
     }
 
     fn grow(&mut self, byte_size: usize) {
+        // The unused tail of the chunk guards the last value in it
+        #[cfg(feature = "debug_alloc")]
+        self.write_canary(self.chunks[self.last].cap_bytes() - self.last_used_bytes);
         self.total_content_bytes += self.last_used_bytes;
 
         let last_cap_bytes = self.chunks[self.last].cap_bytes();
         // Ensure new capacity is at least double the size we need
         let new_cap_bytes = max(last_cap_bytes * 2, byte_size * 2);
-        self.chunks.push(ValChunk::new(new_cap_bytes));
+        self.chunks.push(ValChunk::new(new_cap_bytes, self.align));
         self.last += 1;
         self.last_used_bytes = 0;
     }
 
-    fn move_over(&mut self, bytes: usize) {
+    /// Moves the last `bytes` of the previous chunk, which had `prev_used_bytes`
+    /// in use, to the start of the current one.
+    fn move_over(&mut self, prev_used_bytes: usize, bytes: usize) {
         let prev_chunk = &mut self.chunks[self.last - 1];
         let src_range_start = prev_chunk
             .as_ptr()
-            .wrapping_byte_add(prev_chunk.cap_bytes() - bytes)
+            .wrapping_byte_add(prev_chunk.offset + prev_used_bytes - bytes)
             as *const Erased;
-        let dest_range_start = self.chunks[self.last].0.as_mut_ptr() as *mut Erased;
+        // Safety: the new chunk has room for `bytes`, ensured by `grow`
+        let dest_range_start = unsafe { self.chunks[self.last].get_mut(0) };
         unsafe {
             src_range_start.copy_to_nonoverlapping(dest_range_start, bytes);
         }
@@ -148,9 +203,11 @@ impl ByAlign {
     /// - No aliasing: If you create references from returned `*mut ErasedMin`,
     ///   you must ensure that they are no longer live after calling `reset`.
     pub(crate) unsafe fn allocate_val(&mut self, byte_size: usize) -> *mut Erased {
-        if self.capacity_over(byte_size) {
-            self.grow(byte_size);
+        if self.capacity_over(self.canary_len() + byte_size) {
+            self.grow(self.canary_len() + byte_size);
         }
+        #[cfg(feature = "debug_alloc")]
+        self.write_canary(self.canary_len());
 
         // Safety:
         // - No aliasing: We increment last_used_bytes immediately after getting
@@ -190,8 +247,9 @@ impl ByAlign {
         debug_assert!(len <= self.chunks[self.last].cap_bytes());
 
         if self.capacity_over(byte_size) {
+            let prev_used_bytes = self.last_used_bytes;
             self.grow(byte_size * len);
-            self.move_over(byte_size * len);
+            self.move_over(prev_used_bytes, byte_size * len);
         }
 
         // Safety:
@@ -207,6 +265,49 @@ impl ByAlign {
         ptr
     }
 
+    /// With `debug_alloc`, each value is preceded by a canary of at least 8
+    /// bytes. It's a multiple of the alignment, so the values stay aligned.
+    fn canary_len(&self) -> usize {
+        if cfg!(feature = "debug_alloc") {
+            self.align.max(8)
+        } else {
+            0
+        }
+    }
+
+    /// Writes a canary of `len` bytes at the current position.
+    #[cfg(feature = "debug_alloc")]
+    fn write_canary(&mut self, len: usize) {
+        // Safety: the room for the canary is ensured by the caller, and the
+        // memory past `last_used_bytes` is not handed out yet
+        unsafe {
+            let ptr = self.chunks[self.last].get_mut(self.last_used_bytes) as *mut u8;
+            debug::write_canary(ptr, len);
+        }
+        self.canaries.push((self.last, self.last_used_bytes, len));
+        self.canary_bytes += len;
+        self.last_used_bytes += len;
+    }
+
+    /// Panics if a value has been written out of bounds, over the canary
+    /// following it.
+    #[cfg(feature = "debug_alloc")]
+    pub(crate) fn check_canaries(&self) {
+        for &(chunk_idx, offset, len) in &self.canaries {
+            // Safety: the canaries are in bounds, and no references to them
+            // are ever handed out
+            let intact = unsafe {
+                let ptr = self.chunks[chunk_idx].get_const(offset) as *const u8;
+                debug::is_canary_intact(ptr, len)
+            };
+            assert!(
+                intact,
+                "Arena canary overwritten: a value of alignment {} was written out of bounds",
+                self.align
+            );
+        }
+    }
+
     pub(crate) fn current_const(&self) -> *const Erased {
         unsafe { self.chunks[self.last].get_const(self.last_used_bytes) }
     }
@@ -234,7 +335,7 @@ impl ByAlign {
         // and `by` is less than the allocation size
         #[cfg(debug_assertions)]
         {
-            let start_addr = self.chunks[self.last].0.as_ptr().addr();
+            let start_addr = self.current_chunk_start().addr();
             let end_addr = start_addr + self.last_used_bytes;
             debug_assert!(start_addr <= last_alloc_ptr.addr());
             debug_assert!(last_alloc_ptr.addr() <= end_addr - by);
@@ -242,16 +343,31 @@ impl ByAlign {
         self.last_used_bytes -= by;
     }
 
+    #[cfg(debug_assertions)]
+    fn current_chunk_start(&self) -> *const Erased {
+        // Safety: the offset 0 is always in bounds
+        unsafe { self.chunks[self.last].get_const(0) }
+    }
+
     fn cap_bytes(&self) -> usize {
         self.chunks.iter().map(|c| c.cap_bytes()).sum()
     }
 
+    fn canary_bytes(&self) -> usize {
+        #[cfg(feature = "debug_alloc")]
+        return self.canary_bytes;
+        #[cfg(not(feature = "debug_alloc"))]
+        0
+    }
+
+    /// Canaries are counted as overhead, not content
     fn content_bytes(&self) -> usize {
-        self.total_content_bytes + self.last_used_bytes
+        self.total_content_bytes + self.last_used_bytes - self.canary_bytes()
     }
 
     pub(crate) fn memory_usage(&self, usage: &mut MemoryUsage) {
         usage.overhead_bytes += self.chunks.capacity() * size_of::<ValChunk>();
+        usage.overhead_bytes += self.canary_bytes();
         usage.capacity_bytes += self.cap_bytes();
         usage.content_bytes += self.content_bytes();
     }
@@ -260,11 +376,11 @@ impl ByAlign {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::ptr::addr_eq;
+    use std::{ops::Not, ptr::addr_eq};
 
     #[test]
     fn test_val_basic() {
-        let mut by_align = ByAlign::new();
+        let mut by_align = ByAlign::new(1);
 
         let helper = |by_align: &mut ByAlign, val: u32| {
             let val_ptr = unsafe { by_align.allocate_val(size_of::<u32>()) } as *mut u32;
@@ -286,67 +402,96 @@ mod test {
 
         let (val2, val2_ptr) = helper(&mut by_align, 44);
         assert_eq!(*val2, 44);
-        assert!(addr_eq(val0_ptr, val2_ptr));
+        // The sequence starts over from the start of the first chunk
+        let start = unsafe { by_align.chunks[0].get_const(by_align.canary_len()) };
+        assert!(addr_eq(start, val2_ptr));
+        // With `debug_alloc`, the values and their canaries didn't fit in
+        // the first chunk, so the chunks were merged into a new one
+        if cfg!(feature = "debug_alloc").not() {
+            assert!(addr_eq(val0_ptr, val2_ptr));
+        }
     }
 
-    fn alloc(by_align: &mut ByAlign, size: usize) {
-        unsafe { by_align.allocate_val(size) };
+    /// Allocates a value that takes `bytes` of the chunk, counting its canary
+    fn alloc(by_align: &mut ByAlign, bytes: usize) {
+        unsafe { by_align.allocate_val(bytes - by_align.canary_len()) };
     }
 
     #[test]
     fn test_val_growth_patterns() {
-        let mut align = ByAlign::new();
+        let mut align = ByAlign::new(1);
         let align = &mut align;
+        // With `debug_alloc`, each allocation has a canary that isn't content
+        let c = align.canary_len();
         assert_eq!(align.cap_bytes(), 16); // Preallocated
         assert_eq!(align.content_bytes(), 0); // No content yet
-        for _ in 0..16 {
-            alloc(align, 1); // Allocating 16 x 1 (u8)
+        for _ in 0..2 {
+            alloc(align, 8); // Allocating 2 x 8 ([u8; 8])
         }
         assert_eq!(align.cap_bytes(), 16); // Full
-        assert_eq!(align.content_bytes(), 16); // 16 bytes of content
-        alloc(align, 1); // Overflowing by 1
+        assert_eq!(align.content_bytes(), 16 - 2 * c); // 16 bytes taken
+        alloc(align, 8); // Overflowing by 8
         assert_eq!(align.cap_bytes(), 48); // Grew by 32 (16 + 32 = 48)
-        assert_eq!(align.content_bytes(), 17); // 16 + 1 = 17 bytes of content
-        alloc(align, 31); // Allocating [u8; 31], Filled to the brim
+        assert_eq!(align.content_bytes(), 24 - 3 * c); // 16 + 8 = 24 bytes taken
+        alloc(align, 24); // Filled to the brim
         assert_eq!(align.cap_bytes(), 48); // Full again
-        assert_eq!(align.content_bytes(), 48); // 17 + 31 = 48 bytes of content
-        alloc(align, 1); // Overflowing by 1, Only 1/64 filled
+        assert_eq!(align.content_bytes(), 48 - 4 * c); // 24 + 24 = 48 bytes taken
+        alloc(align, 8); // Overflowing by 8, Only 8/64 filled
         assert_eq!(align.cap_bytes(), 112); // Grew by 64 (48 + 64 = 112)
-        assert_eq!(align.content_bytes(), 49); // 48 + 1 = 49 bytes of content
-        alloc(align, 64); // Overflowing by 1, 64/128 filled
+        assert_eq!(align.content_bytes(), 56 - 5 * c); // 48 + 8 = 56 bytes taken
+        alloc(align, 64); // Overflowing by 8, 64/128 filled
         assert_eq!(align.cap_bytes(), 240); // Grew by 128 (112 + 128 = 240)
-        assert_eq!(align.content_bytes(), 113); // 49 + 64 = 113 bytes of content
+        assert_eq!(align.content_bytes(), 120 - 6 * c); // 56 + 64 = 120 bytes taken
         alloc(align, 130); // Overflowing by under next increment (256), but...
         // over the current capacity: 2*130 = 260, rounded up to 272 (nearest multiple of 16)
         assert_eq!(align.cap_bytes(), 512); // Grew by 272 (240 + round(2*130) = 512)
-        assert_eq!(align.content_bytes(), 243); // 113 + 130 = 243 bytes of content
+        assert_eq!(align.content_bytes(), 250 - 7 * c); // 120 + 130 = 250 bytes taken
         alloc(align, 550); // Overflowing by over next increment (544)
         assert_eq!(align.cap_bytes(), 1616); // Grew by 1104 (rounded up) (512 + round(2*550) = 1616)
-        assert_eq!(align.content_bytes(), 793); // 243 + 550 = 793 bytes of content
+        assert_eq!(align.content_bytes(), 800 - 8 * c); // 250 + 550 = 800 bytes taken
         alloc(align, 554); // Filled to the brim
         assert_eq!(align.cap_bytes(), 1616); // Full again
-        assert_eq!(align.content_bytes(), 1347); // 793 + 554 = 1347 bytes of content
-        alloc(align, 1);
+        assert_eq!(align.content_bytes(), 1354 - 9 * c); // 800 + 554 = 1354 bytes taken
+        alloc(align, 8);
         assert_eq!(align.cap_bytes(), 3824); // Grew by 2208 (1616 + 2*1104 = 3824)
-        assert_eq!(align.content_bytes(), 1348); // 1347 + 1 = 1348 bytes of content
+        assert_eq!(align.content_bytes(), 1362 - 10 * c); // 1354 + 8 = 1362 bytes taken
     }
 
     #[test]
     fn test_val_reset() {
-        let mut align = ByAlign::new();
+        let mut align = ByAlign::new(1);
         let align = &mut align;
+        let c = align.canary_len();
         assert_eq!(align.cap_bytes(), 16); // Preallocated
         assert_eq!(align.content_bytes(), 0); // No content yet
-        alloc(align, 100); // [u16; 50]
-        assert_eq!(align.cap_bytes(), 224); // Grew by 208 (16 + round(2*100) = 216)
-        assert_eq!(align.content_bytes(), 100); // 100 bytes of content
+        alloc(align, 100); // [u16; 50], with its canary
+        assert_eq!(align.cap_bytes(), 224); // Grew by 208 (16 + round(2*100) = 224)
+        assert_eq!(align.content_bytes(), 100 - c); // 100 bytes taken
 
         align.reset();
 
-        assert_eq!(align.cap_bytes(), 224); // The other than the bigger slice are dropped
+        // The chunks are merged into one, at least as big as the two
+        assert_eq!(align.chunks.len(), 1);
+        let merged_cap_bytes = align.cap_bytes();
+        assert!(merged_cap_bytes >= 224);
         assert_eq!(align.content_bytes(), 0); // Content reset to 0
         alloc(align, 220); // Would overflow a 16-byte chunk
-        assert_eq!(align.cap_bytes(), 224); // But it fits this!
-        assert_eq!(align.content_bytes(), 220); // 220 bytes of content
+        assert_eq!(align.cap_bytes(), merged_cap_bytes); // But it fits this!
+        assert_eq!(align.chunks.len(), 1);
+        assert_eq!(align.content_bytes(), 220 - c); // 220 bytes taken
+    }
+
+    #[test]
+    #[cfg(feature = "debug_alloc")]
+    fn test_val_poison() {
+        let mut by_align = ByAlign::new(4);
+        let ptr = unsafe { by_align.allocate_val(size_of::<u32>()) } as *mut u32;
+        unsafe { ptr.write(42) };
+
+        by_align.reset();
+
+        // Reads the memory before writing to it
+        let ptr = unsafe { by_align.allocate_val(size_of::<u32>()) } as *mut u32;
+        assert_eq!(unsafe { ptr.read() }, 0xDDDDDDDD);
     }
 }
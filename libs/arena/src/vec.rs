@@ -3,6 +3,8 @@ use std::{
     mem::MaybeUninit,
 };
 
+#[cfg(feature = "debug_alloc")]
+use crate::debug;
 use crate::{ErasedMax as Erased, MemoryUsage};
 
 #[derive(Debug)]
@@ -67,7 +69,7 @@ impl VecChunk {
         // and another x2 for free space
         let mut new = Vec::with_capacity(last_cap * 4);
         for chunk in chunks.drain(..) {
-            new.extend(chunk.0.into_iter());
+            new.extend(chunk.0);
         }
         new.resize_with(new.capacity(), || MaybeUninit::new(Vec::new()));
         VecChunk(new.into_boxed_slice())
@@ -76,7 +78,7 @@ impl VecChunk {
 
 #[derive(Debug)]
 struct BySize {
-    seq: usize,
+    seq: u64,
     last: usize,
     last_used_count: usize,
     chunks: Vec<VecChunk>,
@@ -111,7 +113,7 @@ impl BySize {
         self.last_used_count = 0;
     }
 
-    fn get_new(&mut self, seq: usize) -> &mut Vec<Erased> {
+    fn get_new(&mut self, seq: u64) -> &mut Vec<Erased> {
         if self.chunks.is_empty() {
             self.chunks.push(VecChunk::default());
         }
@@ -149,13 +151,36 @@ impl ByAlign {
         ByAlign { sizes: Vec::new() }
     }
 
-    pub fn allocate_vec(&mut self, n_size: usize, seq: usize) -> &mut Vec<Erased> {
+    pub fn allocate_vec(&mut self, n_size: usize, seq: u64) -> &mut Vec<Erased> {
         if n_size >= self.sizes.len() {
             self.sizes.resize_with(n_size + 1, BySize::new);
         }
         self.sizes[n_size].get_new(seq)
     }
 
+    /// Poisons the contents of all the vecs, including their spare capacity.
+    ///
+    /// # Safety
+    ///
+    /// No aliasing: there must be no live references to the vecs or their
+    /// contents, i.e. the allocation sequence they belong to must be over.
+    #[cfg(feature = "debug_alloc")]
+    pub(crate) unsafe fn poison(&mut self, align: usize) {
+        for (n_size, by_size) in self.sizes.iter_mut().enumerate() {
+            let element_size = n_size * align;
+            for chunk in &mut by_size.chunks {
+                for idx in 0..chunk.cap() {
+                    // Safety: in bounds, and not aliased (caller's responsibility)
+                    let erased = unsafe { chunk.get(idx) };
+                    let ptr = erased.as_mut_ptr() as *mut u8;
+                    // Safety: the buffer of the vec is valid for its capacity
+                    // in the elements of its original type
+                    unsafe { debug::poison(ptr, erased.capacity() * element_size) };
+                }
+            }
+        }
+    }
+
     pub(crate) fn drop(&mut self, align: usize) {
         for (n, by_size) in self.sizes.drain(..).enumerate() {
             let element_size = n * align;
@@ -165,7 +190,7 @@ impl ByAlign {
                     let erased = unsafe { slot.assume_init_mut() };
                     if erased.capacity() > 0 {
                         let alloc_size = element_size * erased.capacity();
-                        // UNREACHABLE: The align sizes 1, 2, 4, 8, 16 are always valid
+                        // UNREACHABLE: The align sizes 1, 2, 4, 8, 16, 32, 64 are always valid
                         // and alloc_size is calculated from the actually allocated size
                         // so the layout is always valid
                         let layout = Layout::from_size_align(alloc_size, align)
@@ -193,7 +218,7 @@ impl ByAlign {
     /// held. (Shared references (`&Vec<T>`) and mutable slice references to
     /// the contents of the vecs (`&mut [T]`) are OK.) Breaking this rule is
     /// Undefined Behavior.
-    pub(crate) unsafe fn memory_usage(&self, align: usize, seq: usize, usage: &mut MemoryUsage) {
+    pub(crate) unsafe fn memory_usage(&self, align: usize, seq: u64, usage: &mut MemoryUsage) {
         usage.overhead_bytes += self.sizes.capacity() * size_of::<BySize>();
         for (n_size, by_size) in self.sizes.iter().enumerate() {
             let element_size = n_size * align;
@@ -0,0 +1,54 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paltex::{Color, DecodeError, RowDecoder};
+
+// Fixed buffers, as a runtime decoding into staging memory would have
+const PALETTE_LEN: usize = 256;
+const DATA_LEN: usize = 1 << 16;
+
+fuzz_target!(|data: &[u8]| {
+    let mut palette = [Color::TRANSPARENT; PALETTE_LEN];
+    let mut buf = vec![0; DATA_LEN];
+
+    let info = paltex::decode_info(data);
+    let result = paltex::decode_into(data, &mut palette, &mut buf);
+
+    let Ok(info) = info else {
+        assert_eq!(result.err(), info.err());
+        return;
+    };
+    if info.data_len() > DATA_LEN {
+        assert!(matches!(result, Err(DecodeError::BufferTooSmall { .. })));
+        return;
+    }
+    // Decoded only when it fits the fixed buffers, to keep the memory bounded
+    let expected = paltex::decode_anim(data);
+    match &expected {
+        Ok(anim) => {
+            assert_eq!(result, Ok(info));
+            assert_eq!(palette[..info.palette_len], anim.palette);
+            assert_eq!(buf[..info.data_len()], anim.data);
+        }
+        Err(error) => assert_eq!(result.as_ref().err(), Some(error)),
+    }
+
+    // The rows end up the same as the whole, up to the first error
+    let mut rows = Vec::new();
+    let mut row_error = None;
+    if let Ok(mut decoder) = RowDecoder::new(data, &mut palette, &mut buf) {
+        while let Some(row) = decoder.next_row() {
+            match row {
+                Ok(row) => rows.extend_from_slice(row),
+                Err(error) => row_error = Some(error),
+            }
+        }
+    }
+    match expected {
+        Ok(anim) => {
+            assert_eq!(row_error, None);
+            assert_eq!(rows, anim.data);
+        }
+        Err(_) => assert!(rows.len() < info.data_len() || row_error.is_some()),
+    }
+});
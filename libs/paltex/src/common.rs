@@ -1,16 +1,322 @@
-use std::fmt::{Debug, Display};
+use std::{fmt::Display, ops::Not};
 
 use bang_core::draw::AsBytes;
 
-#[derive(Debug)]
-pub struct InvalidInput;
+/// Magic bytes that start a paltex file of version 2 or later. Version 1
+/// files have no container and start straight with the `Header`.
+pub const MAGIC: [u8; 4] = *b"PTEX";
+/// The format version written by the encoder
+pub const VERSION: u8 = 2;
+/// Maximum number of colors in the palette, not counting the implicit transparent.
+/// Only the extended ops fit this many; see `IndexWidth::Wide`.
+pub const MAX_PAL_LEN: u8 = OpSet::Extended.max_pal_len();
+/// Maximum number of pixels in all the frames of a paltex. A single extended
+/// op can claim to fill billions of pixels, so the decoder rejects larger
+/// sizes before allocating.
+pub const MAX_DATA_LEN: usize = 1 << 26;
 
-impl Display for InvalidInput {
+/// Magic bytes that start a palette file: a standalone palette shared by
+/// paltex files, with no image data
+pub const PALETTE_MAGIC: [u8; 4] = *b"PPAL";
+/// The palette file format version written by the encoder
+pub const PALETTE_VERSION: u8 = 1;
+
+/// Container flag: the encoded pixel data uses `OpSet::Runs(IndexWidth::Wide)`,
+/// for palettes of up to 254 colors
+pub const FLAG_WIDE_INDICES: u8 = 0b0000_0001;
+/// Container flag: the encoded pixel data uses `OpSet::Extended`
+pub const FLAG_EXTENDED_OPS: u8 = 0b0000_0010;
+/// Container flag: the paltex is an animation, and the palette is followed
+/// by an `AnimHeader` and the frame durations
+pub const FLAG_ANIMATION: u8 = 0b0000_0100;
+#[cfg(any(feature = "decoding", test))]
+const OP_SET_FLAGS: u8 = FLAG_WIDE_INDICES | FLAG_EXTENDED_OPS;
+#[cfg(any(feature = "decoding", test))]
+pub(crate) const KNOWN_FLAGS: u8 = OP_SET_FLAGS | FLAG_ANIMATION;
+
+/// The ops the encoded pixel data consists of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpSet {
+    /// Horizontal and vertical runs of bounded length, each filling the
+    /// first pixels still unfilled. The only op set of version 1.
+    Runs(IndexWidth),
+    /// Runs of any length, row copies, pattern repeats and literals, filling
+    /// the pixels in order. 8-bit indices.
+    Extended,
+}
+
+impl OpSet {
+    pub const fn max_pal_len(self) -> u8 {
+        match self {
+            OpSet::Runs(index_width) => index_width.max_pal_len(),
+            OpSet::Extended => 255,
+        }
+    }
+
+    #[cfg(any(feature = "decoding", test))]
+    pub(crate) const fn from_flags(flags: u8) -> Result<Self, DecodeError> {
+        match flags & OP_SET_FLAGS {
+            0 => Ok(OpSet::Runs(IndexWidth::Narrow)),
+            FLAG_WIDE_INDICES => Ok(OpSet::Runs(IndexWidth::Wide)),
+            FLAG_EXTENDED_OPS => Ok(OpSet::Extended),
+            _ => Err(DecodeError::UnsupportedFlags(flags)),
+        }
+    }
+
+    #[cfg(any(feature = "encoding", test))]
+    pub(crate) const fn flags(self) -> u8 {
+        match self {
+            OpSet::Runs(IndexWidth::Narrow) => 0,
+            OpSet::Runs(IndexWidth::Wide) => FLAG_WIDE_INDICES,
+            OpSet::Extended => FLAG_EXTENDED_OPS,
+        }
+    }
+}
+
+/// Encoding of `OpSet::Extended`: each op starts with a byte whose two
+/// highest bits are the kind of the op and the rest is its length in pixels.
+/// Operands, if any, follow.
+pub(crate) mod ext {
+    /// Operand: the color index
+    pub const RUN: u8 = 0b00 << 6;
+    /// Copies the pixels from the row above
+    pub const COPY_ROW: u8 = 0b01 << 6;
+    /// Operand: distance as a varint. Copies the pixels from `distance`
+    /// pixels before, repeating a pattern if the length exceeds the distance.
+    pub const REPEAT: u8 = 0b10 << 6;
+    /// Operand: the color index of each pixel
+    pub const LITERAL: u8 = 0b11 << 6;
+    #[cfg(any(feature = "decoding", test))]
+    pub const KIND_MASK: u8 = 0b1100_0000;
+    pub const LEN_MASK: u8 = 0b0011_1111;
+    /// Lengths up to this are stored in the op byte, as `len - 1`. Longer
+    /// ones store `LEN_MASK` in the op byte, and `len - LEN_INLINE_MAX - 1`
+    /// as a varint operand before the other operands.
+    pub const LEN_INLINE_MAX: usize = LEN_MASK as usize;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ExtOp {
+        Run {
+            len: usize,
+            color_idx: u8,
+        },
+        CopyRow {
+            len: usize,
+        },
+        Repeat {
+            len: usize,
+            distance: usize,
+        },
+        /// The indices follow the op
+        Literal {
+            len: usize,
+        },
+    }
+}
+
+/// Width of the color indices in the encoded pixel data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexWidth {
+    /// 4-bit indices, packed into one-byte ops with runs of up to 8 pixels.
+    /// Palettes of up to 15 colors.
+    Narrow,
+    /// 8-bit indices, in two-byte ops with runs of up to 128 pixels.
+    /// Palettes of up to 254 colors, one less than the indices fit: a run
+    /// may fill pixels anywhere ahead, so the decoder marks the pixels that
+    /// aren't filled yet with index 255, in the caller's buffer. Palettes of
+    /// 255 colors need `OpSet::Extended`, which fills the pixels in order.
+    Wide,
+}
+
+impl IndexWidth {
+    pub const fn max_pal_len(self) -> u8 {
+        match self {
+            IndexWidth::Narrow => 15,
+            IndexWidth::Wide => 254,
+        }
+    }
+
+    /// The most compact index width that fits a palette of `pal_len` colors
+    pub const fn fitting(pal_len: usize) -> Option<Self> {
+        if pal_len <= IndexWidth::Narrow.max_pal_len() as usize {
+            Some(IndexWidth::Narrow)
+        } else if pal_len <= IndexWidth::Wide.max_pal_len() as usize {
+            Some(IndexWidth::Wide)
+        } else {
+            None
+        }
+    }
+
+    /// Size of one op in the encoded pixel data
+    #[cfg(any(feature = "decoding", test))]
+    pub(crate) const fn op_len(self) -> usize {
+        match self {
+            IndexWidth::Narrow => 1,
+            IndexWidth::Wide => 2,
+        }
+    }
+
+    /// Maximum run length of one op
+    pub(crate) const fn run_max(self) -> usize {
+        match self {
+            IndexWidth::Narrow => 0b0000_0111 + 1,
+            IndexWidth::Wide => 0b0111_1111 + 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input doesn't start with the paltex magic bytes, and doesn't look
+    /// like a version 1 paltex either: likely not a paltex file at all.
+    NotPaltex,
+    /// The input doesn't start with the palette file magic bytes.
+    NotPalette,
+    UnsupportedVersion(u8),
+    UnsupportedFlags(u8),
+    /// The input ends before the length declared by its headers.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// The input continues after the length declared by its headers.
+    TrailingBytes(usize),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    ZeroSize {
+        width: u16,
+        height: u16,
+    },
+    PaletteTooLong {
+        pal_len: u8,
+        op_set: OpSet,
+    },
+    ColorOutOfRange {
+        color_idx: u8,
+        pal_len: u8,
+    },
+    /// The frames have more pixels than `MAX_DATA_LEN`.
+    TooLarge {
+        data_len: usize,
+    },
+    /// The encoded data ends before all the pixels are filled.
+    IncompleteData {
+        missing_pixels: usize,
+    },
+    /// The encoded data continues after all the pixels are filled.
+    ExcessData {
+        excess_bytes: usize,
+    },
+    /// The encoded data ends in the middle of an op.
+    PartialOp,
+    /// An op refers to pixels before the start of the image, or fills pixels
+    /// past the end of it.
+    InvalidOp {
+        offset: usize,
+    },
+    /// The paltex is an animation: decode it with `decode_anim`.
+    Animation,
+    NoFrames,
+    InvalidLoopStart {
+        loop_start: u16,
+        frame_count: u16,
+    },
+    /// A caller-provided buffer can't fit the palette or the pixel data.
+    BufferTooSmall {
+        needed: usize,
+        actual: usize,
+    },
+}
+
+impl Display for DecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+        match self {
+            DecodeError::NotPaltex => write!(
+                f,
+                "not a paltex file: no magic bytes, and doesn't look like a version 1 paltex either"
+            ),
+            DecodeError::NotPalette => write!(f, "not a palette file: no magic bytes"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported paltex version {version}")
+            }
+            DecodeError::UnsupportedFlags(flags) => {
+                write!(f, "unsupported paltex flags {flags:#010b}")
+            }
+            DecodeError::Truncated { expected, actual } => write!(
+                f,
+                "truncated paltex: expected {expected} bytes, got {actual}"
+            ),
+            DecodeError::TrailingBytes(n) => {
+                write!(f, "{n} unexpected bytes after the end of the paltex")
+            }
+            DecodeError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "corrupted paltex: checksum is {actual:#010x}, expected {expected:#010x}"
+            ),
+            DecodeError::ZeroSize { width, height } => {
+                write!(f, "invalid paltex size {width}x{height}")
+            }
+            DecodeError::PaletteTooLong { pal_len, op_set } => write!(
+                f,
+                "paltex palette has {pal_len} colors, the maximum with {op_set:?} ops is {}",
+                op_set.max_pal_len()
+            ),
+            DecodeError::ColorOutOfRange { color_idx, pal_len } => write!(
+                f,
+                "paltex color index {color_idx} is out of range of the palette of {pal_len} colors"
+            ),
+            DecodeError::TooLarge { data_len } => {
+                write!(f, "{data_len} pixels is over the maximum of {MAX_DATA_LEN}")
+            }
+            DecodeError::IncompleteData { missing_pixels } => write!(
+                f,
+                "paltex data ends before the image is filled ({missing_pixels} pixels missing)"
+            ),
+            DecodeError::ExcessData { excess_bytes } => write!(
+                f,
+                "paltex data continues after the image is filled ({excess_bytes} excess bytes)"
+            ),
+            DecodeError::PartialOp => write!(f, "paltex data ends in the middle of an op"),
+            DecodeError::InvalidOp { offset } => {
+                write!(f, "invalid paltex op at byte {offset} of the data")
+            }
+            DecodeError::Animation => write!(f, "the paltex is an animation"),
+            DecodeError::NoFrames => write!(f, "paltex animation has no frames"),
+            DecodeError::InvalidLoopStart {
+                loop_start,
+                frame_count,
+            } => write!(
+                f,
+                "paltex animation loops back to frame {loop_start}, but has only {frame_count} frames"
+            ),
+            DecodeError::BufferTooSmall { needed, actual } => write!(
+                f,
+                "buffer of {actual} elements is too small for the paltex, which needs {needed}"
+            ),
+        }
     }
 }
 
+impl std::error::Error for DecodeError {}
+
+/// Precedes the `Header` in version 2 and later.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Container {
+    pub magic: [u8; 4],
+    pub version: u8,
+    pub flags: u8,
+    pub reserved: u16,
+    /// Length of the encoded pixel data that follows the header and the palette
+    pub data_len: u32,
+    /// CRC-32 of the header, the palette and the encoded pixel data
+    pub crc: u32,
+}
+
+unsafe impl AsBytes for Container {}
+
 #[derive(Debug, Default)]
 #[repr(C)]
 pub struct Header {
@@ -22,6 +328,106 @@ pub struct Header {
 
 unsafe impl AsBytes for Header {}
 
+/// Follows the palette in animations, and is followed by the duration of
+/// each frame as an `u16`. The frames are encoded one after another, as if
+/// they were a single image of `frame_count` times the height.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct AnimHeader {
+    pub frame_count: u16,
+    /// `NO_LOOP` if the animation stops at the last frame
+    pub loop_start: u16,
+}
+
+unsafe impl AsBytes for AnimHeader {}
+
+pub const NO_LOOP: u16 = u16::MAX;
+
+/// Starts a palette file, and is followed by `pal_len` colors. The implicit
+/// transparent isn't stored, as in paltex files.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct PaletteHeader {
+    pub magic: [u8; 4],
+    pub version: u8,
+    pub pal_len: u8,
+    pub reserved: u16,
+    /// CRC-32 of the colors
+    pub crc: u32,
+}
+
+unsafe impl AsBytes for PaletteHeader {}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE), as used by zlib and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A LEB128 varint that can't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarintError {
+    /// The input ends in the middle of the varint.
+    Truncated,
+    /// The value doesn't fit in 32 bits.
+    Overflow,
+}
+
+/// Appends `value` as a LEB128 varint.
+pub fn encode_varint(mut value: usize, output: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0b0111_1111) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0b1000_0000);
+    }
+}
+
+/// Decodes a LEB128 varint of at most 32 bits at `pos`, leaving `pos` after
+/// it.
+pub fn decode_varint(input: &[u8], pos: &mut usize) -> Result<usize, VarintError> {
+    let mut value: u32 = 0;
+    for shift in (0..32).step_by(7) {
+        let &byte = input.get(*pos).ok_or(VarintError::Truncated)?;
+        *pos += 1;
+        let bits = (byte & 0b0111_1111) as u32;
+        if (bits << shift) >> shift != bits {
+            return Err(VarintError::Overflow);
+        }
+        value |= bits << shift;
+        if byte & 0b1000_0000 == 0 {
+            return Ok(value as usize);
+        }
+    }
+    Err(VarintError::Overflow)
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C, align(4))]
 pub struct Color {
@@ -64,6 +470,7 @@ impl Color {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PalTex {
     pub width: u32,
     pub height: u32,
@@ -72,7 +479,8 @@ pub struct PalTex {
 }
 
 impl PalTex {
-    pub fn from_encoded(bytes: &[u8]) -> Result<Self, InvalidInput> {
+    #[cfg(any(feature = "decoding", test))]
+    pub fn from_encoded(bytes: &[u8]) -> Result<Self, DecodeError> {
         crate::decode(bytes)
     }
 
@@ -108,3 +516,140 @@ impl PalTex {
         }
     }
 }
+
+/// Frames of the same size that share a palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalTexAnim {
+    /// Width of a frame
+    pub width: u32,
+    /// Height of a frame
+    pub height: u32,
+    pub palette: Vec<Color>,
+    /// The frames, one after another
+    pub data: Vec<u8>,
+    /// Duration of each frame in logic ticks
+    pub durations: Vec<u16>,
+    /// The frame to loop back to after the last one. If `None`, the
+    /// animation stops at the last frame.
+    pub loop_start: Option<u16>,
+}
+
+impl PalTexAnim {
+    #[cfg(any(feature = "decoding", test))]
+    pub fn from_encoded(bytes: &[u8]) -> Result<Self, DecodeError> {
+        crate::decode_anim(bytes)
+    }
+
+    /// Builds an animation from frames that share the palette of the first one.
+    pub fn from_frames(frames: &[PalTex], durations: Vec<u16>, loop_start: Option<u16>) -> Self {
+        assert!(frames.is_empty().not(), "An animation needs frames");
+        assert_eq!(frames.len(), durations.len(), "One duration per frame");
+        let first = &frames[0];
+        for frame in frames {
+            assert!(
+                frame.width == first.width && frame.height == first.height,
+                "The frames must be of the same size"
+            );
+            assert!(
+                frame.palette == first.palette,
+                "The frames must share the palette"
+            );
+        }
+        Self {
+            width: first.width,
+            height: first.height,
+            palette: first.palette.clone(),
+            data: frames.iter().flat_map(|f| &f.data).copied().collect(),
+            durations,
+            loop_start,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.durations.len()
+    }
+
+    fn frame_len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn frame(&self, idx: usize) -> &[u8] {
+        let frame_len = self.frame_len();
+        &self.data[idx * frame_len..(idx + 1) * frame_len]
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.frame_len())
+    }
+
+    /// A still image of the given frame
+    pub fn to_paltex(&self, idx: usize) -> PalTex {
+        PalTex {
+            width: self.width,
+            height: self.height,
+            palette: self.palette.clone(),
+            data: self.frame(idx).to_owned(),
+        }
+    }
+
+    /// The frame shown `tick` logic ticks after the start of the animation
+    pub fn frame_at(&self, tick: u64) -> usize {
+        let find = |durations: &[u16], mut tick: u64| {
+            for (idx, &duration) in durations.iter().enumerate() {
+                if tick < duration as u64 {
+                    return Some(idx);
+                }
+                tick -= duration as u64;
+            }
+            None
+        };
+        let last = self.frame_count().saturating_sub(1);
+
+        let total: u64 = self.durations.iter().map(|&d| d as u64).sum();
+        if tick < total {
+            // UNREACHABLE: the tick is within the total duration
+            return find(&self.durations, tick).expect("UNREACHABLE: within duration");
+        }
+        let Some(loop_start) = self.loop_start else {
+            return last;
+        };
+        let loop_start = loop_start as usize;
+        let looped = &self.durations[loop_start..];
+        let loop_total: u64 = looped.iter().map(|&d| d as u64).sum();
+        if loop_total == 0 {
+            return last;
+        }
+        // UNREACHABLE: the tick is within the loop duration
+        loop_start
+            + find(looped, (tick - total) % loop_total).expect("UNREACHABLE: within loop duration")
+    }
+}
+
+/// The size and the format of an encoded paltex, known before decoding the
+/// pixel data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PalTexInfo {
+    /// The format version of the file
+    pub version: u8,
+    /// Width of a frame
+    pub width: u32,
+    /// Height of a frame
+    pub height: u32,
+    /// Length of the decoded palette, including the implicit transparent
+    pub palette_len: usize,
+    /// 1 for still images
+    pub frame_count: usize,
+    pub is_animation: bool,
+    pub op_set: OpSet,
+}
+
+impl PalTexInfo {
+    pub fn frame_len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Length of the decoded pixel data of all the frames
+    pub fn data_len(&self) -> usize {
+        self.frame_len() * self.frame_count
+    }
+}
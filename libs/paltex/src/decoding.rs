@@ -1,42 +1,85 @@
+use std::ops::{Not, Range};
+
 use bang_core::draw::AsBytes;
 
-use crate::common::{Color, Header, InvalidInput, PalTex};
+use crate::common::{
+    AnimHeader, Color, Container, DecodeError, FLAG_ANIMATION, Header, IndexWidth, KNOWN_FLAGS,
+    MAGIC, MAX_DATA_LEN, NO_LOOP, OpSet, PALETTE_MAGIC, PALETTE_VERSION, PalTex, PalTexAnim,
+    PalTexInfo, PaletteHeader, VERSION, VarintError, crc32, decode_varint,
+    ext::{self, ExtOp},
+};
+
+pub(crate) fn decode_container(input: &[u8]) -> Result<(Container, OpSet, &[u8]), DecodeError> {
+    let mut container = Container::default();
+    let container_end = size_of::<Container>();
+
+    if input.len() < container_end {
+        return Err(DecodeError::Truncated {
+            expected: container_end,
+            actual: input.len(),
+        });
+    }
+
+    container
+        .as_bytes_mut()
+        .copy_from_slice(&input[..container_end]);
+
+    if container.magic != MAGIC {
+        return Err(DecodeError::NotPaltex);
+    }
+    if container.version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(container.version));
+    }
+    if container.flags & !KNOWN_FLAGS != 0 {
+        return Err(DecodeError::UnsupportedFlags(container.flags));
+    }
+    let op_set = OpSet::from_flags(container.flags)?;
+
+    Ok((container, op_set, &input[container_end..]))
+}
 
-pub(crate) fn decode_header(input: &[u8]) -> Result<Header, InvalidInput> {
+pub(crate) fn read_header(input: &[u8]) -> Result<Header, DecodeError> {
     let mut header = Header::default();
     let header_end = size_of::<Header>();
 
     if input.len() < header_end {
-        return Err(InvalidInput);
+        return Err(DecodeError::Truncated {
+            expected: header_end,
+            actual: input.len(),
+        });
     }
 
     header.as_bytes_mut().copy_from_slice(&input[..header_end]);
 
-    header.pal_len = u8::min(header.pal_len, 15);
+    Ok(header)
+}
+
+pub(crate) fn validate_header(header: &Header, op_set: OpSet) -> Result<(), DecodeError> {
+    if header.pal_len > op_set.max_pal_len() {
+        return Err(DecodeError::PaletteTooLong {
+            pal_len: header.pal_len,
+            op_set,
+        });
+    }
     if header.width == 0 || header.height == 0 {
-        return Err(InvalidInput);
+        return Err(DecodeError::ZeroSize {
+            width: header.width,
+            height: header.height,
+        });
     }
 
-    Ok(header)
+    Ok(())
 }
 
-pub(crate) fn decode_palette<'a>(
-    header: &Header,
-    input: &'a [u8],
-) -> Result<(Vec<Color>, &'a [u8]), InvalidInput> {
-    let mut palette_vec = vec![Color::TRANSPARENT; header.pal_len as usize + 1];
-    let palette = &mut palette_vec[1..];
-    let header_end = size_of::<Header>();
-    let palette_end = header_end + size_of_val(palette);
-    if input.len() < palette_end {
-        return Err(InvalidInput);
-    }
-    palette
-        .as_bytes_mut()
-        .copy_from_slice(&input[header_end..palette_end]);
-    let img_data = &input[palette_end..];
+pub(crate) fn decode_header(input: &[u8], op_set: OpSet) -> Result<Header, DecodeError> {
+    let header = read_header(input)?;
+    validate_header(&header, op_set)?;
+    Ok(header)
+}
 
-    Ok((palette_vec, img_data))
+/// Length of the header and the palette
+fn headers_len(header: &Header) -> usize {
+    size_of::<Header>() + header.pal_len as usize * size_of::<Color>()
 }
 
 pub(crate) fn decode_op(op: u8) -> (u8, u8, u8) {
@@ -47,13 +90,23 @@ pub(crate) fn decode_op(op: u8) -> (u8, u8, u8) {
     (color_idx, run_dir, run_len)
 }
 
+pub(crate) fn decode_wide_op(op: [u8; 2]) -> (u8, u8, u8) {
+    let [control, color_idx] = op;
+    let run_dir = control >> 7;
+    let run_len = (control & 0b0111_1111) + 1;
+
+    (color_idx, run_dir, run_len)
+}
+
+/// Fills the first `run_len` pixels not filled yet, starting from `cursor`
+/// and stepping by `stride`. Returns the number of pixels filled.
 pub(crate) fn perform_op(
     data: &mut [u8],
     cursor: usize,
     color_idx: u8,
     stride: usize,
     run_len: u8,
-) {
+) -> usize {
     let mut i = 0;
     let mut filled = 0;
     while filled < run_len && cursor + i * stride < data.len() {
@@ -63,42 +116,767 @@ pub(crate) fn perform_op(
         }
         i += 1;
     }
+    filled as usize
+}
+
+/// Decodes a varint operand of the op at `op_offset`.
+pub(crate) fn decode_operand(
+    input: &[u8],
+    pos: &mut usize,
+    op_offset: usize,
+) -> Result<usize, DecodeError> {
+    decode_varint(input, pos).map_err(|error| match error {
+        VarintError::Truncated => DecodeError::PartialOp,
+        VarintError::Overflow => DecodeError::InvalidOp { offset: op_offset },
+    })
+}
+
+/// Decodes the op at `pos`, leaving `pos` after its operands. The indices of
+/// a literal are not consumed.
+pub(crate) fn decode_ext_op(input: &[u8], pos: &mut usize) -> Result<ExtOp, DecodeError> {
+    let op_offset = *pos;
+    let &op = input.get(*pos).ok_or(DecodeError::PartialOp)?;
+    *pos += 1;
+
+    let mut len = (op & ext::LEN_MASK) as usize + 1;
+    if len > ext::LEN_INLINE_MAX {
+        len = decode_operand(input, pos, op_offset)? + ext::LEN_INLINE_MAX + 1;
+    }
+
+    Ok(match op & ext::KIND_MASK {
+        ext::RUN => {
+            let &color_idx = input.get(*pos).ok_or(DecodeError::PartialOp)?;
+            *pos += 1;
+            ExtOp::Run { len, color_idx }
+        }
+        ext::COPY_ROW => ExtOp::CopyRow { len },
+        ext::REPEAT => {
+            let distance = decode_operand(input, pos, op_offset)?;
+            ExtOp::Repeat { len, distance }
+        }
+        ext::LITERAL => ExtOp::Literal { len },
+        // UNREACHABLE: the kind has two bits, and all four values are covered
+        _ => unreachable!("UNREACHABLE: all kinds covered"),
+    })
+}
+
+fn copy_back(data: &mut [u8], cursor: usize, distance: usize, len: usize) {
+    // Panic: the caller checks the distance. The copy may overlap the pixels
+    // it produces, so it's done pixel by pixel.
+    for i in cursor..cursor + len {
+        data[i] = data[i - distance];
+    }
+}
+
+/// Decodes the encoded pixel data into a buffer one op at a time, so that
+/// the rows can be handed out as soon as they are filled.
+#[derive(Clone)]
+struct DataDecoder<'a> {
+    width: usize,
+    pal_len: u8,
+    op_set: OpSet,
+    encoded_data: &'a [u8],
+    /// Position of the next op in the encoded data
+    pos: usize,
+    /// Number of pixels in the decoded data
+    len: usize,
+    /// The pixels before the cursor are all filled
+    cursor: usize,
+    /// Number of pixels filled, including the ones after the cursor
+    filled: usize,
+}
+
+impl<'a> DataDecoder<'a> {
+    fn new(
+        header: &Header,
+        op_set: OpSet,
+        frame_count: usize,
+        encoded_data: &'a [u8],
+    ) -> Result<Self, DecodeError> {
+        let width = header.width as usize;
+        let len = width * header.height as usize * frame_count;
+        if len > MAX_DATA_LEN {
+            return Err(DecodeError::TooLarge { data_len: len });
+        }
+
+        if let OpSet::Runs(index_width) = op_set {
+            let op_len = index_width.op_len();
+            if encoded_data.len().is_multiple_of(op_len).not() {
+                return Err(DecodeError::PartialOp);
+            }
+            // Each op fills at most `run_max` pixels: check before the data is
+            // allocated, so that the memory used stays proportional to the
+            // encoded data.
+            let max_len = encoded_data.len() / op_len * index_width.run_max();
+            if max_len < len {
+                return Err(DecodeError::IncompleteData {
+                    missing_pixels: len - max_len,
+                });
+            }
+        }
+
+        Ok(Self {
+            width,
+            pal_len: header.pal_len,
+            op_set,
+            encoded_data,
+            pos: 0,
+            len,
+            cursor: 0,
+            filled: 0,
+        })
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.encoded_data.len()
+    }
+
+    /// Prepares a buffer of `self.len` pixels for decoding.
+    fn init(&self, data: &mut [u8]) {
+        if let OpSet::Runs(_) = self.op_set {
+            // Marks the pixels that aren't filled yet
+            data.fill(u8::MAX);
+        }
+    }
+
+    fn check_color(&self, color_idx: u8) -> Result<(), DecodeError> {
+        if color_idx > self.pal_len {
+            return Err(DecodeError::ColorOutOfRange {
+                color_idx,
+                pal_len: self.pal_len,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_excess(&self) -> Result<(), DecodeError> {
+        if self.filled == self.len {
+            return Err(DecodeError::ExcessData {
+                excess_bytes: self.encoded_data.len() - self.pos,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes the next op into `data`, prepared with `init`.
+    fn step(&mut self, data: &mut [u8]) -> Result<(), DecodeError> {
+        self.check_excess()?;
+        match self.op_set {
+            OpSet::Runs(index_width) => self.step_runs(index_width, data),
+            OpSet::Extended => self.step_extended(Some(data)),
+        }
+    }
+
+    fn step_runs(&mut self, index_width: IndexWidth, data: &mut [u8]) -> Result<(), DecodeError> {
+        // Panic: `new` checks that the data has only whole ops
+        let op = &self.encoded_data[self.pos..self.pos + index_width.op_len()];
+        self.pos += op.len();
+
+        let (color_idx, run_dir, run_len) = match index_width {
+            IndexWidth::Narrow => decode_op(op[0]),
+            IndexWidth::Wide => decode_wide_op([op[0], op[1]]),
+        };
+        self.check_color(color_idx)?;
+
+        let stride = if run_dir == 0 { 1 } else { self.width };
+
+        self.filled += perform_op(data, self.cursor, color_idx, stride, run_len);
+
+        while self.cursor < data.len() && data[self.cursor] != u8::MAX {
+            self.cursor += 1;
+        }
+        Ok(())
+    }
+
+    /// Decodes the next extended op into `data`, or without `data`, just
+    /// validates it.
+    fn step_extended(&mut self, data: Option<&mut [u8]>) -> Result<(), DecodeError> {
+        let op_offset = self.pos;
+        let op = decode_ext_op(self.encoded_data, &mut self.pos)?;
+        let invalid = DecodeError::InvalidOp { offset: op_offset };
+
+        let (ExtOp::Run { len: op_len, .. }
+        | ExtOp::CopyRow { len: op_len }
+        | ExtOp::Repeat { len: op_len, .. }
+        | ExtOp::Literal { len: op_len }) = op;
+        if op_len > self.len - self.cursor {
+            return Err(invalid);
+        }
+
+        let cursor = self.cursor;
+        match op {
+            ExtOp::Run { len, color_idx } => {
+                self.check_color(color_idx)?;
+                if let Some(data) = data {
+                    data[cursor..cursor + len].fill(color_idx);
+                }
+            }
+            ExtOp::CopyRow { len } => {
+                if cursor < self.width {
+                    return Err(invalid);
+                }
+                if let Some(data) = data {
+                    copy_back(data, cursor, self.width, len);
+                }
+            }
+            ExtOp::Repeat { len, distance } => {
+                if distance == 0 || distance > cursor {
+                    return Err(invalid);
+                }
+                if let Some(data) = data {
+                    copy_back(data, cursor, distance, len);
+                }
+            }
+            ExtOp::Literal { len } => {
+                let literal = self
+                    .encoded_data
+                    .get(self.pos..self.pos + len)
+                    .ok_or(DecodeError::PartialOp)?;
+                for &color_idx in literal {
+                    self.check_color(color_idx)?;
+                }
+                if let Some(data) = data {
+                    data[cursor..cursor + len].copy_from_slice(literal);
+                }
+                self.pos += len;
+            }
+        }
+
+        self.cursor += op_len;
+        self.filled += op_len;
+        Ok(())
+    }
+
+    /// Checks that all the pixels are filled, once all the ops are decoded.
+    fn finish(&self) -> Result<(), DecodeError> {
+        if self.filled < self.len {
+            return Err(DecodeError::IncompleteData {
+                missing_pixels: self.len - self.filled,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes all the ops into `data` of `self.len` pixels, prepared with
+    /// `init`.
+    fn decode(mut self, data: &mut [u8]) -> Result<(), DecodeError> {
+        while self.is_done().not() {
+            self.step(data)?;
+        }
+        self.finish()
+    }
+
+    /// Validates the extended ops without decoding them. The result is the
+    /// same as decoding, as the validity of the ops doesn't depend on the
+    /// pixels.
+    fn validate_extended(mut self) -> Result<(), DecodeError> {
+        while self.is_done().not() {
+            self.check_excess()?;
+            self.step_extended(None)?;
+        }
+        self.finish()
+    }
 }
 
-pub(crate) fn decode_main(header: &Header, encoded_data: &[u8]) -> Vec<u8> {
-    let max_len = encoded_data.len() * 8;
-    let len = usize::min(header.width as usize * header.height as usize, max_len);
-    let mut data = vec![u8::MAX; len];
+/// Decodes the pixel data into a new buffer.
+pub(crate) fn decode_data(
+    header: &Header,
+    op_set: OpSet,
+    frame_count: usize,
+    encoded_data: &[u8],
+) -> Result<Vec<u8>, DecodeError> {
+    let decoder = DataDecoder::new(header, op_set, frame_count, encoded_data)?;
+    if op_set == OpSet::Extended {
+        // Unlike runs, a single extended op can fill all the pixels, up to
+        // `MAX_DATA_LEN`: validate the ops before allocating, so that at
+        // least invalid data doesn't allocate.
+        decoder.clone().validate_extended()?;
+    }
+    let mut data = vec![0; decoder.len];
+    decoder.init(&mut data);
+    decoder.decode(&mut data)?;
+    Ok(data)
+}
 
-    let mut cursor = 0;
-    for &op in encoded_data {
-        let (color_idx, run_dir, run_len) = decode_op(op);
+/// An encoded paltex split into its parts. Everything but the pixel data is
+/// validated.
+struct Layout<'a> {
+    info: PalTexInfo,
+    header: Header,
+    /// The palette without the implicit transparent
+    palette: &'a [u8],
+    /// The frame durations, if an animation
+    durations: &'a [u8],
+    loop_start: u16,
+    encoded_data: &'a [u8],
+}
+
+impl<'a> Layout<'a> {
+    fn new(
+        version: u8,
+        header: Header,
+        op_set: OpSet,
+        anim_header: Option<AnimHeader>,
+        body: &'a [u8],
+    ) -> Self {
+        // Panic: the callers check that the body fits all the headers
+        let palette_end = headers_len(&header);
+        let palette = &body[size_of::<Header>()..palette_end];
+        let (durations, loop_start) = match &anim_header {
+            Some(anim_header) => (
+                &body[palette_end + size_of::<AnimHeader>()
+                    ..palette_end + anim_headers_len(anim_header)],
+                anim_header.loop_start,
+            ),
+            None => (&[][..], NO_LOOP),
+        };
+        let encoded_data = &body[palette_end + anim_header.as_ref().map_or(0, anim_headers_len)..];
 
-        let stride = if run_dir == 0 {
-            1
-        } else {
-            header.width as usize
+        let info = PalTexInfo {
+            version,
+            width: header.width as u32,
+            height: header.height as u32,
+            palette_len: header.pal_len as usize + 1,
+            frame_count: anim_header.as_ref().map_or(1, |a| a.frame_count as usize),
+            is_animation: anim_header.is_some(),
+            op_set,
         };
 
-        perform_op(&mut data, cursor, color_idx, stride, run_len);
+        Self {
+            info,
+            header,
+            palette,
+            durations,
+            loop_start,
+            encoded_data,
+        }
+    }
+
+    fn data_decoder(&self) -> Result<DataDecoder<'a>, DecodeError> {
+        DataDecoder::new(
+            &self.header,
+            self.info.op_set,
+            self.info.frame_count,
+            self.encoded_data,
+        )
+    }
+
+    /// Decodes the palette into the start of `palette`.
+    fn decode_palette_into(&self, palette: &mut [Color]) -> Result<(), DecodeError> {
+        let palette_len = self.info.palette_len;
+        if palette.len() < palette_len {
+            return Err(DecodeError::BufferTooSmall {
+                needed: palette_len,
+                actual: palette.len(),
+            });
+        }
+        palette[0] = Color::TRANSPARENT;
+        palette[1..palette_len]
+            .as_bytes_mut()
+            .copy_from_slice(self.palette);
+        Ok(())
+    }
+
+    /// Decodes the palette into the start of `palette`, and prepares the
+    /// start of `data` for decoding the pixel data into.
+    fn prepare<'d>(
+        &self,
+        palette: &mut [Color],
+        data: &'d mut [u8],
+    ) -> Result<(DataDecoder<'a>, &'d mut [u8]), DecodeError> {
+        self.decode_palette_into(palette)?;
+        let data_len = self.info.data_len();
+        if data.len() < data_len {
+            return Err(DecodeError::BufferTooSmall {
+                needed: data_len,
+                actual: data.len(),
+            });
+        }
+        let decoder = self.data_decoder()?;
+        let data = &mut data[..data_len];
+        decoder.init(data);
+        Ok((decoder, data))
+    }
+
+    fn decode(&self) -> Result<(Vec<Color>, Vec<u8>), DecodeError> {
+        let mut palette = vec![Color::TRANSPARENT; self.info.palette_len];
+        self.decode_palette_into(&mut palette)?;
+        let data = decode_data(
+            &self.header,
+            self.info.op_set,
+            self.info.frame_count,
+            self.encoded_data,
+        )?;
+        Ok((palette, data))
+    }
 
-        while cursor < data.len() && data[cursor] != u8::MAX {
-            cursor += 1;
+    fn durations(&self) -> (Vec<u16>, Option<u16>) {
+        if self.info.is_animation.not() {
+            return (vec![0], None);
         }
+        let mut durations = vec![0_u16; self.info.frame_count];
+        durations.as_bytes_mut().copy_from_slice(self.durations);
+        let loop_start = (self.loop_start != NO_LOOP).then_some(self.loop_start);
+        (durations, loop_start)
     }
+}
 
-    data
+/// Splits a version 1 paltex, which has no container: no magic bytes,
+/// version, flags, data length or checksum.
+fn split_v1(input: &[u8]) -> Result<Layout<'_>, DecodeError> {
+    let op_set = OpSet::Runs(IndexWidth::Narrow);
+    let header = decode_header(input, op_set)?;
+    let palette_end = headers_len(&header);
+    if input.len() < palette_end {
+        return Err(DecodeError::Truncated {
+            expected: palette_end,
+            actual: input.len(),
+        });
+    }
+    Ok(Layout::new(1, header, op_set, None, input))
 }
 
-pub fn decode(input: &[u8]) -> Result<PalTex, InvalidInput> {
-    let header = decode_header(input)?;
-    let (palette, encoded_data) = decode_palette(&header, input)?;
-    let data = decode_main(&header, encoded_data);
+pub(crate) fn decode_v1(input: &[u8]) -> Result<PalTex, DecodeError> {
+    let layout = split_v1(input)?;
+    let (palette, data) = layout.decode()?;
 
     Ok(PalTex {
-        width: header.width as u32,
-        height: header.height as u32,
+        width: layout.info.width,
+        height: layout.info.height,
         palette,
         data,
     })
 }
+
+/// Length of the animation header and the frame durations
+fn anim_headers_len(anim_header: &AnimHeader) -> usize {
+    size_of::<AnimHeader>() + anim_header.frame_count as usize * size_of::<u16>()
+}
+
+pub(crate) fn read_anim_header(input: &[u8]) -> Result<AnimHeader, DecodeError> {
+    let mut anim_header = AnimHeader::default();
+    let anim_header_end = size_of::<AnimHeader>();
+
+    if input.len() < anim_header_end {
+        return Err(DecodeError::Truncated {
+            expected: anim_header_end,
+            actual: input.len(),
+        });
+    }
+
+    anim_header
+        .as_bytes_mut()
+        .copy_from_slice(&input[..anim_header_end]);
+
+    Ok(anim_header)
+}
+
+pub(crate) fn validate_anim_header(anim_header: &AnimHeader) -> Result<(), DecodeError> {
+    if anim_header.frame_count == 0 {
+        return Err(DecodeError::NoFrames);
+    }
+    if anim_header.loop_start != NO_LOOP && anim_header.loop_start >= anim_header.frame_count {
+        return Err(DecodeError::InvalidLoopStart {
+            loop_start: anim_header.loop_start,
+            frame_count: anim_header.frame_count,
+        });
+    }
+
+    Ok(())
+}
+
+/// Splits a paltex of version 2 or later, checking the container.
+fn split_v2(input: &[u8]) -> Result<Layout<'_>, DecodeError> {
+    let (container, op_set, body) = decode_container(input)?;
+    let header = read_header(body)?;
+    let is_animation = container.flags & FLAG_ANIMATION != 0;
+    let truncated = |expected| DecodeError::Truncated {
+        expected: size_of::<Container>() + expected,
+        actual: input.len(),
+    };
+
+    let mut headers_len = headers_len(&header);
+    let anim_header = if is_animation {
+        let anim_header = read_anim_header(body.get(headers_len..).unwrap_or_default())
+            .map_err(|_| truncated(headers_len + size_of::<AnimHeader>()))?;
+        headers_len += anim_headers_len(&anim_header);
+        Some(anim_header)
+    } else {
+        None
+    };
+
+    let body_len = headers_len + container.data_len as usize;
+    if body.len() < body_len {
+        return Err(truncated(body_len));
+    }
+    if body.len() > body_len {
+        return Err(DecodeError::TrailingBytes(body.len() - body_len));
+    }
+
+    let crc = crc32(body);
+    if crc != container.crc {
+        return Err(DecodeError::ChecksumMismatch {
+            expected: container.crc,
+            actual: crc,
+        });
+    }
+
+    validate_header(&header, op_set)?;
+    if let Some(anim_header) = &anim_header {
+        validate_anim_header(anim_header)?;
+    }
+
+    Ok(Layout::new(
+        container.version,
+        header,
+        op_set,
+        anim_header,
+        body,
+    ))
+}
+
+/// Decodes a paltex of version 2 or later. A still image is returned as an
+/// animation of a single frame, along with a flag telling whether the
+/// paltex is an animation.
+pub(crate) fn decode_v2(input: &[u8]) -> Result<(PalTexAnim, bool), DecodeError> {
+    let layout = split_v2(input)?;
+    let (palette, data) = layout.decode()?;
+    let (durations, loop_start) = layout.durations();
+
+    let anim = PalTexAnim {
+        width: layout.info.width,
+        height: layout.info.height,
+        palette,
+        data,
+        durations,
+        loop_start,
+    };
+    Ok((anim, layout.info.is_animation))
+}
+
+/// Without the magic bytes, the input may well be something else entirely,
+/// so it's decoded as version 1 only if its header is plausible: valid, with
+/// zero padding, and with no more data bytes than pixels, as each op fills
+/// at least one. Otherwise it's reported as `DecodeError::NotPaltex`, and
+/// the errors of decoding a plausible one as is.
+fn check_v1(input: &[u8]) -> Result<(), DecodeError> {
+    let op_set = OpSet::Runs(IndexWidth::Narrow);
+    let Ok(header) = decode_header(input, op_set) else {
+        return Err(DecodeError::NotPaltex);
+    };
+    let pixels = header.width as usize * header.height as usize;
+    let data_len = input.len().saturating_sub(headers_len(&header));
+    if header.padding != 0 || data_len > pixels {
+        return Err(DecodeError::NotPaltex);
+    }
+    Ok(())
+}
+
+/// Splits a paltex of any version.
+fn split(input: &[u8]) -> Result<Layout<'_>, DecodeError> {
+    if input.starts_with(&MAGIC) {
+        split_v2(input)
+    } else {
+        check_v1(input)?;
+        split_v1(input)
+    }
+}
+
+/// Decodes a paltex. Version 2 and later are recognized by their magic
+/// bytes; input without them is decoded as version 1. Animations are
+/// rejected with `DecodeError::Animation`.
+pub fn decode(input: &[u8]) -> Result<PalTex, DecodeError> {
+    if input.starts_with(&MAGIC) {
+        let (anim, is_animation) = decode_v2(input)?;
+        if is_animation {
+            return Err(DecodeError::Animation);
+        }
+        Ok(PalTex {
+            width: anim.width,
+            height: anim.height,
+            palette: anim.palette,
+            data: anim.data,
+        })
+    } else {
+        check_v1(input)?;
+        decode_v1(input)
+    }
+}
+
+/// Decodes a paltex animation. Still images are decoded as animations of
+/// a single frame that doesn't loop.
+pub fn decode_anim(input: &[u8]) -> Result<PalTexAnim, DecodeError> {
+    if input.starts_with(&MAGIC) {
+        Ok(decode_v2(input)?.0)
+    } else {
+        check_v1(input)?;
+        let paltex = decode_v1(input)?;
+        Ok(PalTexAnim {
+            width: paltex.width,
+            height: paltex.height,
+            palette: paltex.palette,
+            data: paltex.data,
+            durations: vec![0],
+            loop_start: None,
+        })
+    }
+}
+
+/// Decodes a standalone palette file. The implicit transparent is added at
+/// index 0, as in decoded paltex files.
+pub fn decode_palette(input: &[u8]) -> Result<Vec<Color>, DecodeError> {
+    let mut header = PaletteHeader::default();
+    let header_end = size_of::<PaletteHeader>();
+    if input.len() < header_end {
+        return Err(DecodeError::Truncated {
+            expected: header_end,
+            actual: input.len(),
+        });
+    }
+    header.as_bytes_mut().copy_from_slice(&input[..header_end]);
+
+    if header.magic != PALETTE_MAGIC {
+        return Err(DecodeError::NotPalette);
+    }
+    if header.version != PALETTE_VERSION {
+        return Err(DecodeError::UnsupportedVersion(header.version));
+    }
+
+    let palette_end = header_end + header.pal_len as usize * size_of::<Color>();
+    if input.len() < palette_end {
+        return Err(DecodeError::Truncated {
+            expected: palette_end,
+            actual: input.len(),
+        });
+    }
+    if input.len() > palette_end {
+        return Err(DecodeError::TrailingBytes(input.len() - palette_end));
+    }
+
+    let colors = &input[header_end..];
+    let crc = crc32(colors);
+    if crc != header.crc {
+        return Err(DecodeError::ChecksumMismatch {
+            expected: header.crc,
+            actual: crc,
+        });
+    }
+
+    let mut palette = vec![Color::TRANSPARENT; header.pal_len as usize + 1];
+    palette[1..].as_bytes_mut().copy_from_slice(colors);
+    Ok(palette)
+}
+
+/// Reads the size and the format of a paltex without decoding the palette
+/// or the pixel data, for sizing the buffers of `decode_into` and
+/// `RowDecoder`. The checksum is verified.
+pub fn decode_info(input: &[u8]) -> Result<PalTexInfo, DecodeError> {
+    Ok(split(input)?.info)
+}
+
+/// Decodes a paltex into caller-provided buffers without allocating: the
+/// palette into the start of `palette` and the pixel data of all the frames
+/// into the start of `data`. Their needed lengths are `palette_len` and
+/// `data_len()` of the returned info, available beforehand from
+/// `decode_info`. Shorter buffers fail with `DecodeError::BufferTooSmall`.
+///
+/// Animations are decoded too; the frame durations are only available from
+/// `decode_anim`.
+pub fn decode_into(
+    input: &[u8],
+    palette: &mut [Color],
+    data: &mut [u8],
+) -> Result<PalTexInfo, DecodeError> {
+    let layout = split(input)?;
+    let (decoder, data) = layout.prepare(palette, data)?;
+    decoder.decode(data)?;
+    Ok(layout.info)
+}
+
+/// Decodes a paltex row by row into a caller-provided buffer, without
+/// allocating. Each row is handed out as soon as all of its pixels are
+/// decoded, so that it can be converted or uploaded while the rest is still
+/// being decoded.
+///
+/// The rows of the frames of an animation follow each other, as if the
+/// frames were a single image.
+///
+/// The rows borrow the buffer of the decoder, so instead of implementing
+/// `Iterator`, it's used with `while let Some(row) = decoder.next_row()`.
+pub struct RowDecoder<'a> {
+    info: PalTexInfo,
+    decoder: DataDecoder<'a>,
+    data: &'a mut [u8],
+    /// The next row to hand out
+    row: usize,
+    /// Set after the last row or an error
+    done: bool,
+}
+
+impl<'a> RowDecoder<'a> {
+    /// Decodes the palette right away, and prepares to decode the pixel data.
+    /// The buffers are as in `decode_into`, but the decoder keeps using
+    /// `data` while the rows are decoded: a pixel may be filled before the
+    /// rows before it.
+    pub fn new(
+        input: &'a [u8],
+        palette: &mut [Color],
+        data: &'a mut [u8],
+    ) -> Result<Self, DecodeError> {
+        let layout = split(input)?;
+        let (decoder, data) = layout.prepare(palette, data)?;
+
+        Ok(Self {
+            info: layout.info,
+            decoder,
+            data,
+            row: 0,
+            done: false,
+        })
+    }
+
+    pub fn info(&self) -> &PalTexInfo {
+        &self.info
+    }
+
+    /// Decodes up to the end of the next row, and returns it. Returns `None`
+    /// after the last row, or after an error.
+    pub fn next_row(&mut self) -> Option<Result<&[u8], DecodeError>> {
+        if self.done {
+            return None;
+        }
+        match self.decode_row() {
+            Ok(Some(row)) => Some(Ok(&self.data[row])),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+
+    fn decode_row(&mut self) -> Result<Option<Range<usize>>, DecodeError> {
+        let width = self.info.width as usize;
+        let row = self.row * width..(self.row + 1) * width;
+
+        if row.end > self.data.len() {
+            // All the rows are handed out: the encoded data must end too
+            if self.decoder.is_done().not() {
+                self.decoder.step(self.data)?;
+            }
+            return Ok(None);
+        }
+
+        while self.decoder.cursor < row.end {
+            if self.decoder.is_done() {
+                return self.decoder.finish().map(|()| None);
+            }
+            self.decoder.step(self.data)?;
+        }
+        self.row += 1;
+        Ok(Some(row))
+    }
+}
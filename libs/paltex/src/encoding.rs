@@ -2,26 +2,78 @@ use std::ops::Not;
 
 use bang_core::draw::AsBytes;
 
-use crate::common::{Header, PalTex};
+use crate::common::{
+    AnimHeader, Color, Container, FLAG_ANIMATION, Header, IndexWidth, MAGIC, MAX_DATA_LEN,
+    MAX_PAL_LEN, NO_LOOP, OpSet, PALETTE_MAGIC, PALETTE_VERSION, PalTex, PalTexAnim, PaletteHeader,
+    VERSION, crc32, encode_varint,
+    ext::{self, ExtOp},
+};
 
-const RUN_MAX: usize = 0b0000_0111 + 1;
+/// Repeated patterns are looked for up to this distance back
+const REPEAT_DISTANCE_MAX: usize = 64;
 
 pub(crate) fn encode_op(color_idx: u8, run_dir: u8, run_len: u8) -> u8 {
+    let run_max = IndexWidth::Narrow.run_max() as u8;
     let mut op = u8::min(color_idx, 15) & 0b0000_1111;
     op |= u8::min(run_dir, 1) << 7;
-    op |= (u8::min(run_len, RUN_MAX as u8).saturating_sub(1) & 0b0000_0111) << 4;
+    op |= (u8::min(run_len, run_max).saturating_sub(1) & 0b0000_0111) << 4;
     op
 }
 
-pub(crate) fn encode_headers(paltex: &PalTex, output: &mut Vec<u8>) {
+pub(crate) fn encode_wide_op(color_idx: u8, run_dir: u8, run_len: u8) -> [u8; 2] {
+    let run_max = IndexWidth::Wide.run_max() as u8;
+    let mut control = u8::min(run_dir, 1) << 7;
+    control |= u8::min(run_len, run_max).saturating_sub(1) & 0b0111_1111;
+    [control, color_idx]
+}
+
+pub(crate) fn encode_headers(width: u32, height: u32, palette: &[Color], output: &mut Vec<u8>) {
+    assert!(
+        palette.len() <= MAX_PAL_LEN as usize,
+        "The palette has {} colors, the maximum is {MAX_PAL_LEN}",
+        palette.len()
+    );
+    assert!(
+        width <= u16::MAX as u32 && height <= u16::MAX as u32,
+        "The size {width}x{height} is over the maximum of {}x{}",
+        u16::MAX,
+        u16::MAX
+    );
     let header = Header {
-        width: paltex.width as u16,
-        height: paltex.height as u16,
-        pal_len: paltex.palette.len() as u8,
+        width: width as u16,
+        height: height as u16,
+        pal_len: palette.len() as u8,
         padding: 0,
     };
     output.extend_from_slice(header.as_bytes());
-    output.extend_from_slice(paltex.palette.as_bytes());
+    output.extend_from_slice(palette.as_bytes());
+}
+
+pub(crate) fn encode_anim_headers(
+    durations: &[u16],
+    loop_start: Option<u16>,
+    output: &mut Vec<u8>,
+) {
+    assert!(durations.is_empty().not(), "An animation needs frames");
+    assert!(
+        durations.len() < NO_LOOP as usize,
+        "{} frames is over the maximum of {}",
+        durations.len(),
+        NO_LOOP - 1
+    );
+    if let Some(loop_start) = loop_start {
+        assert!(
+            (loop_start as usize) < durations.len(),
+            "Loops back to frame {loop_start}, but there are only {} frames",
+            durations.len()
+        );
+    }
+    let anim_header = AnimHeader {
+        frame_count: durations.len() as u16,
+        loop_start: loop_start.unwrap_or(NO_LOOP),
+    };
+    output.extend_from_slice(anim_header.as_bytes());
+    output.extend_from_slice(durations.as_bytes());
 }
 
 pub(crate) fn test_run_len(
@@ -30,9 +82,10 @@ pub(crate) fn test_run_len(
     stride: usize,
     mut target: usize,
     color_idx: u8,
+    run_max: usize,
 ) -> usize {
     let mut run_len = 1;
-    while run_len < RUN_MAX {
+    while run_len < run_max {
         target += stride;
         if target >= data.len() || data[target] != color_idx {
             break;
@@ -44,15 +97,21 @@ pub(crate) fn test_run_len(
     run_len
 }
 
-pub(crate) fn encode_main(data: &[u8], width: usize, output: &mut Vec<u8>) {
+pub(crate) fn encode_main(
+    data: &[u8],
+    width: usize,
+    index_width: IndexWidth,
+    output: &mut Vec<u8>,
+) {
+    let run_max = index_width.run_max();
     let mut cursor = 0;
     let mut drained = vec![false; data.len()];
 
     while cursor < data.len() {
         let color_idx = data[cursor];
 
-        let h_run_len = test_run_len(data, &drained, 1, cursor, color_idx);
-        let v_run_len = test_run_len(data, &drained, width, cursor, color_idx);
+        let h_run_len = test_run_len(data, &drained, 1, cursor, color_idx, run_max);
+        let v_run_len = test_run_len(data, &drained, width, cursor, color_idx, run_max);
 
         let (run_len, run_dir) = if h_run_len > v_run_len {
             (h_run_len, 0)
@@ -71,7 +130,12 @@ pub(crate) fn encode_main(data: &[u8], width: usize, output: &mut Vec<u8>) {
             target += stride;
         }
 
-        output.push(encode_op(color_idx, run_dir, run_len as u8));
+        match index_width {
+            IndexWidth::Narrow => output.push(encode_op(color_idx, run_dir, run_len as u8)),
+            IndexWidth::Wide => {
+                output.extend_from_slice(&encode_wide_op(color_idx, run_dir, run_len as u8))
+            }
+        }
 
         while cursor < data.len() && drained[cursor] {
             cursor += 1;
@@ -79,7 +143,278 @@ pub(crate) fn encode_main(data: &[u8], width: usize, output: &mut Vec<u8>) {
     }
 }
 
+fn varint_len(value: usize) -> usize {
+    (usize::BITS - value.leading_zeros()).div_ceil(7).max(1) as usize
+}
+
+fn ext_op_len(op: ExtOp) -> usize {
+    match op {
+        ExtOp::Run { len, .. }
+        | ExtOp::CopyRow { len }
+        | ExtOp::Repeat { len, .. }
+        | ExtOp::Literal { len } => len,
+    }
+}
+
+/// Size of the encoded op, not counting the indices of a literal
+fn ext_op_cost(op: ExtOp) -> usize {
+    let len = ext_op_len(op);
+    let len_cost = if len <= ext::LEN_INLINE_MAX {
+        1
+    } else {
+        1 + varint_len(len - ext::LEN_INLINE_MAX - 1)
+    };
+    match op {
+        ExtOp::Run { .. } => len_cost + 1,
+        ExtOp::Repeat { distance, .. } => len_cost + varint_len(distance),
+        ExtOp::CopyRow { .. } | ExtOp::Literal { .. } => len_cost,
+    }
+}
+
+/// Encodes the op, not including the indices of a literal
+pub(crate) fn encode_ext_op(op: ExtOp, output: &mut Vec<u8>) {
+    let len = ext_op_len(op);
+    let kind = match op {
+        ExtOp::Run { .. } => ext::RUN,
+        ExtOp::CopyRow { .. } => ext::COPY_ROW,
+        ExtOp::Repeat { .. } => ext::REPEAT,
+        ExtOp::Literal { .. } => ext::LITERAL,
+    };
+    if len <= ext::LEN_INLINE_MAX {
+        output.push(kind | (len - 1) as u8);
+    } else {
+        output.push(kind | ext::LEN_MASK);
+        encode_varint(len - ext::LEN_INLINE_MAX - 1, output);
+    }
+    match op {
+        ExtOp::Run { color_idx, .. } => output.push(color_idx),
+        ExtOp::Repeat { distance, .. } => encode_varint(distance, output),
+        ExtOp::CopyRow { .. } | ExtOp::Literal { .. } => (),
+    }
+}
+
+/// How many pixels starting from `cursor` match the ones `distance` before
+fn match_len(data: &[u8], cursor: usize, distance: usize) -> usize {
+    (cursor..data.len())
+        .take_while(|&i| data[i] == data[i - distance])
+        .count()
+}
+
+fn encode_literal(literal: &[u8], output: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    encode_ext_op(ExtOp::Literal { len: literal.len() }, output);
+    output.extend_from_slice(literal);
+}
+
+/// Encodes greedily: at each pixel, picks the op that saves the most bytes
+/// compared to storing its pixels in a literal. Pixels that no op saves
+/// bytes on are gathered into literals.
+/// `frame_len` is the size of one frame of an animation, or of the whole
+/// image if it's a still: the same pixels of the previous frame are also
+/// tried as a repeat.
+pub(crate) fn encode_main_extended(
+    data: &[u8],
+    width: usize,
+    frame_len: usize,
+    output: &mut Vec<u8>,
+) {
+    let mut cursor = 0;
+    let mut literal_start = 0;
+
+    while cursor < data.len() {
+        let color_idx = data[cursor];
+        let savings = |op| ext_op_len(op) as isize - ext_op_cost(op) as isize;
+
+        let len = data[cursor..]
+            .iter()
+            .take_while(|&&c| c == color_idx)
+            .count();
+        let mut best = ExtOp::Run { len, color_idx };
+        let mut consider = |op| {
+            if savings(op) > savings(best) {
+                best = op;
+            }
+        };
+
+        if cursor >= width {
+            consider(ExtOp::CopyRow {
+                len: match_len(data, cursor, width),
+            });
+        }
+        for distance in 2..=REPEAT_DISTANCE_MAX.min(cursor) {
+            if distance != width {
+                consider(ExtOp::Repeat {
+                    len: match_len(data, cursor, distance),
+                    distance,
+                });
+            }
+        }
+        if cursor >= frame_len && frame_len > REPEAT_DISTANCE_MAX && frame_len != width {
+            consider(ExtOp::Repeat {
+                len: match_len(data, cursor, frame_len),
+                distance: frame_len,
+            });
+        }
+
+        if savings(best) > 0 {
+            encode_literal(&data[literal_start..cursor], output);
+            encode_ext_op(best, output);
+            cursor += ext_op_len(best);
+            literal_start = cursor;
+        } else {
+            cursor += 1;
+        }
+    }
+
+    encode_literal(&data[literal_start..cursor], output);
+}
+
+/// The frame durations and the loop start of an animation
+type AnimParts<'a> = (&'a [u16], Option<u16>);
+
+fn encode_parts(
+    width: u32,
+    height: u32,
+    palette: &[Color],
+    data: &[u8],
+    anim: Option<AnimParts>,
+    op_set: OpSet,
+    output: &mut Vec<u8>,
+) {
+    assert!(
+        palette.len() <= op_set.max_pal_len() as usize,
+        "The palette has {} colors, the maximum with {op_set:?} ops is {}",
+        palette.len(),
+        op_set.max_pal_len()
+    );
+    let frame_len = width as usize * height as usize;
+    let frame_count = anim.map_or(1, |(durations, _)| durations.len());
+    assert_eq!(
+        data.len(),
+        frame_len * frame_count,
+        "The data must have {frame_count} frames of {width}x{height} pixels"
+    );
+    assert!(
+        data.len() <= MAX_DATA_LEN,
+        "{} pixels is over the maximum of {MAX_DATA_LEN}",
+        data.len()
+    );
+
+    let container_start = output.len();
+    output.extend_from_slice(Container::default().as_bytes());
+
+    let body_start = output.len();
+    encode_headers(width, height, palette, output);
+    let mut flags = op_set.flags();
+    if let Some((durations, loop_start)) = anim {
+        encode_anim_headers(durations, loop_start, output);
+        flags |= FLAG_ANIMATION;
+    }
+
+    let data_start = output.len();
+    let width = width as usize;
+    match op_set {
+        OpSet::Runs(index_width) => encode_main(data, width, index_width, output),
+        OpSet::Extended => encode_main_extended(data, width, frame_len, output),
+    }
+
+    let container = Container {
+        magic: MAGIC,
+        version: VERSION,
+        flags,
+        reserved: 0,
+        data_len: (output.len() - data_start) as u32,
+        crc: crc32(&output[body_start..]),
+    };
+    output[container_start..body_start].copy_from_slice(container.as_bytes());
+}
+
+/// Encodes with each op set that fits the palette, and keeps the smallest.
+fn encode_smallest(
+    pal_len: usize,
+    output: &mut Vec<u8>,
+    encode_with: impl Fn(OpSet, &mut Vec<u8>),
+) {
+    assert!(
+        pal_len <= MAX_PAL_LEN as usize,
+        "The palette has {pal_len} colors, the maximum is {MAX_PAL_LEN}"
+    );
+
+    let mut extended = Vec::new();
+    encode_with(OpSet::Extended, &mut extended);
+
+    if let Some(index_width) = IndexWidth::fitting(pal_len) {
+        let mut runs = Vec::new();
+        encode_with(OpSet::Runs(index_width), &mut runs);
+        if runs.len() <= extended.len() {
+            output.extend_from_slice(&runs);
+            return;
+        }
+    }
+    output.extend_from_slice(&extended);
+}
+
+/// Encodes a paltex in the current format version, appending it to `output`.
+/// Encodes with each op set that fits the palette, and keeps the smallest.
 pub fn encode(paltex: &PalTex, output: &mut Vec<u8>) {
-    encode_headers(paltex, output);
-    encode_main(&paltex.data, paltex.width as usize, output);
+    encode_smallest(paltex.palette.len(), output, |op_set, output| {
+        encode_with(paltex, op_set, output)
+    });
+}
+
+/// Encodes a paltex in the current format version with the given op set,
+/// appending it to `output`.
+pub fn encode_with(paltex: &PalTex, op_set: OpSet, output: &mut Vec<u8>) {
+    let PalTex {
+        width,
+        height,
+        palette,
+        data,
+    } = paltex;
+    encode_parts(*width, *height, palette, data, None, op_set, output);
+}
+
+/// Encodes an animation in the current format version, appending it to
+/// `output`. Encodes with each op set that fits the palette, and keeps the
+/// smallest.
+pub fn encode_anim(anim: &PalTexAnim, output: &mut Vec<u8>) {
+    encode_smallest(anim.palette.len(), output, |op_set, output| {
+        encode_anim_with(anim, op_set, output)
+    });
+}
+
+/// Encodes an animation in the current format version with the given op
+/// set, appending it to `output`.
+pub fn encode_anim_with(anim: &PalTexAnim, op_set: OpSet, output: &mut Vec<u8>) {
+    let PalTexAnim {
+        width,
+        height,
+        palette,
+        data,
+        durations,
+        loop_start,
+    } = anim;
+    let anim = Some((durations.as_slice(), *loop_start));
+    encode_parts(*width, *height, palette, data, anim, op_set, output);
+}
+
+/// Encodes a standalone palette file, appending it to `output`. The palette
+/// doesn't include the implicit transparent, as in `PalTex`.
+pub fn encode_palette(palette: &[Color], output: &mut Vec<u8>) {
+    assert!(
+        palette.len() <= MAX_PAL_LEN as usize,
+        "The palette has {} colors, the maximum is {MAX_PAL_LEN}",
+        palette.len()
+    );
+    let header = PaletteHeader {
+        magic: PALETTE_MAGIC,
+        version: PALETTE_VERSION,
+        pal_len: palette.len() as u8,
+        reserved: 0,
+        crc: crc32(palette.as_bytes()),
+    };
+    output.extend_from_slice(header.as_bytes());
+    output.extend_from_slice(palette.as_bytes());
 }
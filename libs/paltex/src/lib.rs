@@ -5,18 +5,27 @@ mod decoding;
 mod encoding;
 
 #[cfg(any(feature = "decoding", test))]
-pub use decoding::decode;
+pub use decoding::{RowDecoder, decode, decode_anim, decode_info, decode_into, decode_palette};
 
 #[cfg(any(feature = "encoding", test))]
-pub use encoding::encode;
+pub use encoding::{encode, encode_anim, encode_anim_with, encode_palette, encode_with};
 
-pub use common::{Color, PalTex};
+pub use common::{
+    Color, DecodeError, FLAG_ANIMATION, FLAG_EXTENDED_OPS, FLAG_WIDE_INDICES, IndexWidth, MAGIC,
+    MAX_DATA_LEN, MAX_PAL_LEN, OpSet, PALETTE_MAGIC, PALETTE_VERSION, PalTex, PalTexAnim,
+    PalTexInfo, VERSION, VarintError, crc32, decode_varint, encode_varint,
+};
 
 #[cfg(test)]
 mod tests {
-    use crate::common::Header;
+    use crate::common::{
+        AnimHeader, Color, Container, DecodeError, FLAG_ANIMATION, FLAG_EXTENDED_OPS,
+        FLAG_WIDE_INDICES, Header, IndexWidth, MAGIC, MAX_DATA_LEN, OpSet, PALETTE_MAGIC, PalTex,
+        PalTexAnim, VERSION, VarintError, crc32, decode_varint, encode_varint, ext::ExtOp,
+    };
     use crate::decoding::*;
     use crate::encoding::*;
+    use crate::{RowDecoder, decode_info, decode_into};
 
     #[test]
     fn test_op_roundtrip() {
@@ -47,13 +56,18 @@ mod tests {
     #[test]
     fn test_main_encode() {
         let mut output = Vec::new();
-        encode_main(&[0, 0, 0, 0, 0], 5, &mut output);
+        encode_main(&[0, 0, 0, 0, 0], 5, IndexWidth::Narrow, &mut output);
         assert_eq!(output, &[encode_op(0, 0, 5)]);
         output.clear();
-        encode_main(&[1, 1, 1, 0, 0], 5, &mut output);
+        encode_main(&[1, 1, 1, 0, 0], 5, IndexWidth::Narrow, &mut output);
         assert_eq!(output, &[encode_op(1, 0, 3), encode_op(0, 0, 2)]);
         output.clear();
-        encode_main(&[2, 0, 0, 0, 0, 2, 1, 1, 1, 1], 5, &mut output);
+        encode_main(
+            &[2, 0, 0, 0, 0, 2, 1, 1, 1, 1],
+            5,
+            IndexWidth::Narrow,
+            &mut output,
+        );
         assert_eq!(
             output,
             &[encode_op(2, 1, 2), encode_op(0, 0, 4), encode_op(1, 0, 4)]
@@ -70,23 +84,913 @@ mod tests {
             0, 0, 0, 0, 0, //
         ];
         let mut output = Vec::new();
-        encode_main(input, 5, &mut output);
+        encode_main(input, 5, IndexWidth::Narrow, &mut output);
         let header = Header {
             width: 5,
             height: 5,
             pal_len: 15,
             padding: 0,
         };
-        let decoded = decode_main(&header, &output);
+        let decoded = decode_data(&header, OpSet::Runs(IndexWidth::Narrow), 1, &output).unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_wide_op_roundtrip() {
+        for control in 0..=255 {
+            for color_idx in [0, 1, 15, 16, 128, 255] {
+                let (c, dir, len) = decode_wide_op([control, color_idx]);
+                assert_eq!(c, color_idx);
+                assert_eq!(encode_wide_op(c, dir, len), [control, color_idx]);
+            }
+        }
+        assert_eq!(decode_wide_op(encode_wide_op(7, 0, 0)), (7, 0, 1));
+        assert_eq!(decode_wide_op(encode_wide_op(7, 1, 128)), (7, 1, 128));
+        assert_eq!(decode_wide_op(encode_wide_op(7, 255, 255)), (7, 1, 128));
+    }
+
+    #[test]
+    fn test_wide_main_roundtrip() {
+        let width = 200;
+        let mut input = Vec::new();
+        for y in 0..50 {
+            for x in 0..width {
+                input.push(if x < 150 { (y % 40) as u8 } else { 200 });
+            }
+        }
+        let header = Header {
+            width: width as u16,
+            height: 50,
+            pal_len: 255,
+            padding: 0,
+        };
+
+        let mut output = Vec::new();
+        encode_main(&input, width, IndexWidth::Wide, &mut output);
+        // Rows of 150 pixels take two ops, vertical runs of 50 one op per column
+        assert_eq!(output.len(), (50 * 2 + 50) * 2);
+        let decoded = decode_data(&header, OpSet::Runs(IndexWidth::Wide), 1, &output).unwrap();
         assert_eq!(decoded, input);
+
+        assert_eq!(
+            decode_data(
+                &header,
+                OpSet::Runs(IndexWidth::Wide),
+                1,
+                &output[..output.len() - 1]
+            ),
+            Err(DecodeError::PartialOp)
+        );
+    }
+
+    fn test_paltex() -> PalTex {
+        PalTex {
+            width: 5,
+            height: 3,
+            palette: vec![
+                Color::from_rgba_u8([255, 0, 0, 255]),
+                Color::from_rgba_u8([0, 0, 255, 255]),
+            ],
+            data: vec![
+                0, 1, 1, 1, 0, //
+                0, 2, 0, 2, 0, //
+                1, 1, 1, 1, 1, //
+            ],
+        }
+    }
+
+    fn encode_v1(paltex: &PalTex) -> Vec<u8> {
+        let mut output = Vec::new();
+        encode_headers(paltex.width, paltex.height, &paltex.palette, &mut output);
+        encode_main(
+            &paltex.data,
+            paltex.width as usize,
+            IndexWidth::Narrow,
+            &mut output,
+        );
+        output
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let paltex = test_paltex();
+        let mut encoded = Vec::new();
+        encode(&paltex, &mut encoded);
+        assert_eq!(encoded[..4], MAGIC);
+        assert_eq!(encoded[4], VERSION);
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.width, paltex.width);
+        assert_eq!(decoded.height, paltex.height);
+        assert_eq!(decoded.palette[0], Color::TRANSPARENT);
+        assert_eq!(decoded.palette[1..], paltex.palette);
+        assert_eq!(decoded.data, paltex.data);
+    }
+
+    #[test]
+    fn test_wide_roundtrip() {
+        let palette: Vec<_> = (0..40)
+            .map(|i| Color::from_rgba_u8([i * 6, 255 - i * 6, 0, 255]))
+            .collect();
+        let data: Vec<_> = (0..32 * 16).map(|i| ((i / 3) % 41) as u8).collect();
+        let paltex = PalTex {
+            width: 32,
+            height: 16,
+            palette,
+            data,
+        };
+
+        let mut encoded = Vec::new();
+        encode_with(&paltex, OpSet::Runs(IndexWidth::Wide), &mut encoded);
+        assert_eq!(encoded[5], FLAG_WIDE_INDICES);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.palette[1..], paltex.palette);
+        assert_eq!(decoded.data, paltex.data);
+
+        // Wide indices work with small palettes too
+        let paltex = test_paltex();
+        let mut encoded = Vec::new();
+        encode_with(&paltex, OpSet::Runs(IndexWidth::Wide), &mut encoded);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.data, paltex.data);
+    }
+
+    #[test]
+    fn test_full_palette_roundtrip() {
+        let palette: Vec<_> = (0..=255)
+            .map(|i| Color::from_rgba_u8([i, i, i, 255]))
+            .collect();
+        let mut paltex = PalTex {
+            width: 16,
+            height: 16,
+            palette: palette[..254].to_vec(),
+            data: (0..16 * 16).map(|i| (i % 255) as u8).collect(),
+        };
+
+        // Index 254 is the last one that fits wide indices
+        let mut encoded = Vec::new();
+        encode_with(&paltex, OpSet::Runs(IndexWidth::Wide), &mut encoded);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.data, paltex.data);
+
+        // Index 255 only fits the extended ops
+        paltex.palette = palette[..255].to_vec();
+        paltex.data = (0..16 * 16).map(|i| i as u8).collect();
+        let mut encoded = Vec::new();
+        encode(&paltex, &mut encoded);
+        assert_eq!(encoded[5], FLAG_EXTENDED_OPS);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.palette[1..], paltex.palette);
+        assert_eq!(decoded.data, paltex.data);
+    }
+
+    #[test]
+    #[should_panic = "maximum with Runs(Wide) ops is 254"]
+    fn test_wide_palette_too_long() {
+        let mut paltex = test_paltex();
+        paltex.palette = vec![Color::TRANSPARENT; 255];
+        encode_with(&paltex, OpSet::Runs(IndexWidth::Wide), &mut Vec::new());
+    }
+
+    #[test]
+    #[should_panic = "maximum with Runs(Narrow) ops is 15"]
+    fn test_narrow_palette_too_long() {
+        let mut paltex = test_paltex();
+        paltex.palette = vec![Color::TRANSPARENT; 16];
+        encode_with(&paltex, OpSet::Runs(IndexWidth::Narrow), &mut Vec::new());
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX as usize] {
+            let mut output = Vec::new();
+            encode_varint(value, &mut output);
+            let mut pos = 0;
+            assert_eq!(decode_varint(&output, &mut pos), Ok(value));
+            assert_eq!(pos, output.len());
+            assert_eq!(
+                decode_varint(&output[..output.len() - 1], &mut 0),
+                Err(VarintError::Truncated)
+            );
+            assert_eq!(
+                decode_operand(&output[..output.len() - 1], &mut 0, 7),
+                Err(DecodeError::PartialOp)
+            );
+        }
+
+        let too_large = [0xFF, 0xFF, 0xFF, 0xFF, 0x1F];
+        assert_eq!(
+            decode_varint(&too_large, &mut 0),
+            Err(VarintError::Overflow)
+        );
+        assert_eq!(
+            decode_operand(&too_large, &mut 0, 7),
+            Err(DecodeError::InvalidOp { offset: 7 })
+        );
+        let too_long = [0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert_eq!(decode_varint(&too_long, &mut 0), Err(VarintError::Overflow));
+    }
+
+    #[test]
+    fn test_ext_op_roundtrip() {
+        let ops = [
+            ExtOp::Run {
+                len: 1,
+                color_idx: 0,
+            },
+            ExtOp::Run {
+                len: 63,
+                color_idx: 255,
+            },
+            ExtOp::Run {
+                len: 64,
+                color_idx: 3,
+            },
+            ExtOp::CopyRow { len: 1 },
+            ExtOp::CopyRow { len: 100_000 },
+            ExtOp::Repeat {
+                len: 5,
+                distance: 2,
+            },
+            ExtOp::Repeat {
+                len: 1000,
+                distance: 300,
+            },
+            ExtOp::Literal { len: 62 },
+            ExtOp::Literal { len: 65 },
+        ];
+        let mut output = Vec::new();
+        for op in ops {
+            encode_ext_op(op, &mut output);
+        }
+        let mut pos = 0;
+        for op in ops {
+            assert_eq!(decode_ext_op(&output, &mut pos), Ok(op));
+        }
+        assert_eq!(pos, output.len());
+    }
+
+    fn test_images() -> Vec<(usize, Vec<u8>)> {
+        // Noise from a linear congruential generator
+        let mut state = 12345_u32;
+        let noise = (0..40 * 30)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                ((state >> 16) % 200) as u8
+            })
+            .collect();
+        let tiles = (0..64 * 64)
+            .map(|i| [1, 2, 3, 2, 5, 5][(i % 64) % 6] + (i / 64 % 4) as u8)
+            .collect();
+        let stripes = (0..100 * 20).map(|i| (i % 100 / 10) as u8).collect();
+        let flat = vec![7; 300 * 200];
+        let single = vec![1];
+        vec![
+            (40, noise),
+            (64, tiles),
+            (100, stripes),
+            (300, flat),
+            (1, single),
+        ]
+    }
+
+    #[test]
+    fn test_extended_main_roundtrip() {
+        for (width, input) in test_images() {
+            let header = Header {
+                width: width as u16,
+                height: (input.len() / width) as u16,
+                pal_len: 255,
+                padding: 0,
+            };
+            let mut output = Vec::new();
+            encode_main_extended(&input, width, input.len(), &mut output);
+            let decoded = decode_data(&header, OpSet::Extended, 1, &output).unwrap();
+            assert_eq!(decoded, input);
+
+            let mut runs_output = Vec::new();
+            encode_main(&input, width, IndexWidth::Wide, &mut runs_output);
+            assert!(output.len() <= runs_output.len());
+        }
+    }
+
+    #[test]
+    fn test_extended_roundtrip() {
+        let paltex = test_paltex();
+        let mut encoded = Vec::new();
+        encode_with(&paltex, OpSet::Extended, &mut encoded);
+        assert_eq!(encoded[5], FLAG_EXTENDED_OPS);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.palette[1..], paltex.palette);
+        assert_eq!(decoded.data, paltex.data);
+
+        // Picks the smallest op set
+        let (width, data) = test_images().swap_remove(1);
+        let paltex = PalTex {
+            width: width as u32,
+            height: (data.len() / width) as u32,
+            palette: vec![Color::TRANSPARENT; 8],
+            data,
+        };
+        let mut encoded = Vec::new();
+        encode(&paltex, &mut encoded);
+        assert_eq!(encoded[5], FLAG_EXTENDED_OPS);
+        assert_eq!(decode(&encoded).unwrap(), {
+            let mut expected = paltex.clone();
+            expected.palette.insert(0, Color::TRANSPARENT);
+            expected
+        });
+    }
+
+    #[test]
+    fn test_extended_errors() {
+        let header = Header {
+            width: 4,
+            height: 2,
+            pal_len: 3,
+            padding: 0,
+        };
+        let decode_ops = |ops: &[ExtOp]| {
+            let mut output = Vec::new();
+            for &op in ops {
+                encode_ext_op(op, &mut output);
+                if let ExtOp::Literal { len } = op {
+                    output.extend(std::iter::repeat_n(1, len));
+                }
+            }
+            decode_data(&header, OpSet::Extended, 1, &output)
+        };
+        let run = |len| ExtOp::Run { len, color_idx: 1 };
+
+        assert_eq!(
+            decode_ops(&[run(4), ExtOp::CopyRow { len: 4 }]),
+            Ok(vec![1; 8])
+        );
+        assert_eq!(
+            decode_ops(&[run(3), ExtOp::CopyRow { len: 5 }]),
+            Err(DecodeError::InvalidOp { offset: 2 })
+        );
+        assert_eq!(
+            decode_ops(&[
+                run(1),
+                ExtOp::Repeat {
+                    len: 7,
+                    distance: 2
+                }
+            ]),
+            Err(DecodeError::InvalidOp { offset: 2 })
+        );
+        assert_eq!(
+            decode_ops(&[
+                run(2),
+                ExtOp::Repeat {
+                    len: 6,
+                    distance: 0
+                }
+            ]),
+            Err(DecodeError::InvalidOp { offset: 2 })
+        );
+        assert_eq!(
+            decode_ops(&[run(9)]),
+            Err(DecodeError::InvalidOp { offset: 0 })
+        );
+        assert_eq!(
+            decode_ops(&[run(2), ExtOp::Literal { len: 6 }]),
+            Ok(vec![1; 8])
+        );
+        assert_eq!(
+            decode_ops(&[run(8), run(1)]),
+            Err(DecodeError::ExcessData { excess_bytes: 2 })
+        );
+        assert_eq!(
+            decode_ops(&[run(7)]),
+            Err(DecodeError::IncompleteData { missing_pixels: 1 })
+        );
+        assert_eq!(
+            decode_ops(&[ExtOp::Run {
+                len: 8,
+                color_idx: 4
+            }]),
+            Err(DecodeError::ColorOutOfRange {
+                color_idx: 4,
+                pal_len: 3
+            })
+        );
+
+        let mut output = Vec::new();
+        encode_ext_op(ExtOp::Literal { len: 8 }, &mut output);
+        output.extend_from_slice(&[1; 7]);
+        assert_eq!(
+            decode_data(&header, OpSet::Extended, 1, &output),
+            Err(DecodeError::PartialOp)
+        );
+
+        // Checked before the ops, as one op can fill any number of pixels
+        let frame_count = MAX_DATA_LEN / 8 + 1;
+        assert_eq!(
+            decode_data(&header, OpSet::Extended, frame_count, &output),
+            Err(DecodeError::TooLarge {
+                data_len: frame_count * 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_v1() {
+        let paltex = test_paltex();
+        let decoded = decode(&encode_v1(&paltex)).unwrap();
+        assert_eq!(decoded.palette[1..], paltex.palette);
+        assert_eq!(decoded.data, paltex.data);
+    }
+
+    fn test_anim() -> PalTexAnim {
+        let frames: Vec<_> = (0..3)
+            .map(|i| {
+                let mut paltex = test_paltex();
+                paltex.data.rotate_right(i);
+                paltex
+            })
+            .collect();
+        PalTexAnim::from_frames(&frames, vec![4, 4, 8], Some(1))
+    }
+
+    #[test]
+    fn test_anim_roundtrip() {
+        let anim = test_anim();
+        assert_eq!(anim.frame_count(), 3);
+        let mut second = test_paltex().data;
+        second.rotate_right(1);
+        assert_eq!(anim.frame(1), second);
+
+        for op_set in [OpSet::Runs(IndexWidth::Narrow), OpSet::Extended] {
+            let mut encoded = Vec::new();
+            encode_anim_with(&anim, op_set, &mut encoded);
+            assert_eq!(encoded[5] & FLAG_ANIMATION, FLAG_ANIMATION);
+
+            let decoded = decode_anim(&encoded).unwrap();
+            assert_eq!(decoded.palette[1..], anim.palette);
+            assert_eq!(decoded.data, anim.data);
+            assert_eq!(decoded.durations, anim.durations);
+            assert_eq!(decoded.loop_start, anim.loop_start);
+            assert!(decoded.frames().eq(anim.frames()));
+
+            assert_eq!(decode(&encoded).err(), Some(DecodeError::Animation));
+        }
+
+        // Stills decode as single frames
+        let paltex = test_paltex();
+        let mut encoded = Vec::new();
+        encode(&paltex, &mut encoded);
+        let decoded = decode_anim(&encoded).unwrap();
+        assert_eq!(decoded.frame_count(), 1);
+        assert_eq!(decoded.frame(0), paltex.data);
+        assert_eq!(decoded.frame_at(100), 0);
+        let decoded = decode_anim(&encode_v1(&paltex)).unwrap();
+        assert_eq!(decoded.frame(0), paltex.data);
+    }
+
+    #[test]
+    fn test_anim_repeats_frames() {
+        let (width, noise) = test_images().swap_remove(0);
+        let frame = PalTex {
+            width: width as u32,
+            height: (noise.len() / width) as u32,
+            palette: vec![Color::TRANSPARENT; 200],
+            data: noise,
+        };
+        let mut still = Vec::new();
+        encode(&frame, &mut still);
+
+        let anim =
+            PalTexAnim::from_frames(&[frame.clone(), frame.clone(), frame], vec![1; 3], None);
+        let mut encoded = Vec::new();
+        encode_anim(&anim, &mut encoded);
+        assert!(encoded.len() < still.len() + 20);
+        assert_eq!(decode_anim(&encoded).unwrap().data, anim.data);
+    }
+
+    #[test]
+    fn test_anim_frame_at() {
+        let mut anim = test_anim();
+        anim.durations = vec![2, 3, 1];
+        let frames: Vec<_> = (0..14).map(|tick| anim.frame_at(tick)).collect();
+        assert_eq!(frames, [0, 0, 1, 1, 1, 2, 1, 1, 1, 2, 1, 1, 1, 2]);
+
+        anim.loop_start = Some(0);
+        assert_eq!(anim.frame_at(6), 0);
+        assert_eq!(anim.frame_at(6 * 1000 + 5), 2);
+
+        anim.loop_start = None;
+        assert_eq!(anim.frame_at(5), 2);
+        assert_eq!(anim.frame_at(u64::MAX), 2);
+
+        // Frames of zero duration are skipped
+        anim.durations = vec![1, 0, 1];
+        anim.loop_start = Some(1);
+        let frames: Vec<_> = (0..4).map(|tick| anim.frame_at(tick)).collect();
+        assert_eq!(frames, [0, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_anim_errors() {
+        let mut encoded = Vec::new();
+        encode_anim(&test_anim(), &mut encoded);
+        let container_len = size_of::<Container>();
+        let anim_header_start = container_len + size_of::<Header>() + 2 * size_of::<Color>();
+        let fix_crc = |encoded: &mut Vec<u8>| {
+            let crc = crc32(&encoded[container_len..]);
+            encoded[12..16].copy_from_slice(&crc.to_ne_bytes());
+        };
+
+        let mut invalid_loop = encoded.clone();
+        invalid_loop[anim_header_start + 2..anim_header_start + 4]
+            .copy_from_slice(&3_u16.to_ne_bytes());
+        fix_crc(&mut invalid_loop);
+        assert_eq!(
+            decode_anim(&invalid_loop),
+            Err(DecodeError::InvalidLoopStart {
+                loop_start: 3,
+                frame_count: 3
+            })
+        );
+
+        assert_eq!(
+            decode_anim(&encoded[..anim_header_start + 1]),
+            Err(DecodeError::Truncated {
+                expected: anim_header_start + size_of::<AnimHeader>(),
+                actual: anim_header_start + 1,
+            })
+        );
+
+        // Without the flag, the animation header is read as data
+        let mut no_flag = encoded.clone();
+        no_flag[5] &= !FLAG_ANIMATION;
+        assert!(decode_anim(&no_flag).is_err());
+    }
+
+    #[test]
+    #[should_panic = "Loops back to frame 3, but there are only 3 frames"]
+    fn test_anim_invalid_loop_start() {
+        let mut anim = test_anim();
+        anim.loop_start = Some(3);
+        encode_anim(&anim, &mut Vec::new());
+    }
+
+    #[test]
+    fn test_decode_assets() {
+        for asset in [
+            &include_bytes!("../../../assets/paltex/bubu.paltex")[..],
+            &include_bytes!("../../../assets/paltex/lima.paltex")[..],
+            &include_bytes!("../../../assets/paltex/toge.paltex")[..],
+        ] {
+            let decoded = decode(asset).unwrap();
+            assert_eq!(
+                decoded.data.len(),
+                decoded.width as usize * decoded.height as usize
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_v1_errors() {
+        let mut encoded = encode_v1(&test_paltex());
+        // With a plausible header, the errors are reported as is
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::IncompleteData { missing_pixels: 5 })
+        );
+        assert_eq!(
+            decode_anim(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::IncompleteData { missing_pixels: 5 })
+        );
+        assert_eq!(
+            decode_info(&encoded[..7]),
+            Err(DecodeError::Truncated {
+                expected: 14,
+                actual: 7
+            })
+        );
+
+        encoded.push(encode_op(0, 0, 1));
+        assert_eq!(
+            decode(&encoded),
+            Err(DecodeError::ExcessData { excess_bytes: 1 })
+        );
+        encoded.pop();
+
+        // Otherwise, the input is likely something else
+        let mut padded = encoded.clone();
+        padded[5] = 1;
+        assert_eq!(decode(&padded), Err(DecodeError::NotPaltex));
+        let mut too_long = encoded.clone();
+        too_long.resize(14 + 15 + 1, encode_op(0, 0, 1));
+        assert_eq!(decode(&too_long), Err(DecodeError::NotPaltex));
+        assert_eq!(decode_anim(&too_long), Err(DecodeError::NotPaltex));
+        assert_eq!(decode_info(&too_long), Err(DecodeError::NotPaltex));
+
+        // Used to be clamped silently
+        encoded[4] = 16;
+        assert_eq!(
+            decode_v1(&encoded),
+            Err(DecodeError::PaletteTooLong {
+                pal_len: 16,
+                op_set: OpSet::Runs(IndexWidth::Narrow)
+            })
+        );
+        encoded[4] = 2;
+
+        let last = encoded.last_mut().unwrap();
+        let (_, run_dir, run_len) = decode_op(*last);
+        *last = encode_op(3, run_dir, run_len);
+        assert_eq!(
+            decode_v1(&encoded),
+            Err(DecodeError::ColorOutOfRange {
+                color_idx: 3,
+                pal_len: 2
+            })
+        );
+
+        encoded[0] = 0;
+        encoded[1] = 0;
+        assert_eq!(
+            decode_v1(&encoded),
+            Err(DecodeError::ZeroSize {
+                width: 0,
+                height: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_v2_errors() {
+        let mut encoded = Vec::new();
+        encode(&test_paltex(), &mut encoded);
+        let container_len = size_of::<Container>();
+
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::Truncated {
+                expected: encoded.len(),
+                actual: encoded.len() - 1,
+            })
+        );
+        assert_eq!(decode(&encoded[..3]), Err(DecodeError::NotPaltex));
+        assert!(matches!(
+            decode(&encoded[..container_len - 1]),
+            Err(DecodeError::Truncated { .. })
+        ));
+
+        let mut trailing = encoded.clone();
+        trailing.extend_from_slice(&[0, 0]);
+        assert_eq!(decode(&trailing), Err(DecodeError::TrailingBytes(2)));
+
+        let pal_len_idx = container_len + 4;
+        let mut wrong_pal_len = encoded.clone();
+        wrong_pal_len[pal_len_idx] += 1;
+        assert!(matches!(
+            decode(&wrong_pal_len),
+            Err(DecodeError::Truncated { .. })
+        ));
+
+        for i in (container_len..encoded.len()).filter(|&i| i != pal_len_idx) {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0b0001_0000;
+            assert!(matches!(
+                decode(&corrupted),
+                Err(DecodeError::ChecksumMismatch { .. })
+            ));
+        }
+
+        let mut wrong_version = encoded.clone();
+        wrong_version[4] = 3;
+        assert_eq!(
+            decode(&wrong_version),
+            Err(DecodeError::UnsupportedVersion(3))
+        );
+
+        let mut wide_flag = encoded.clone();
+        wide_flag[5] = FLAG_WIDE_INDICES;
+        assert!(decode(&wide_flag).is_err());
+
+        let mut both_flags = encoded.clone();
+        both_flags[5] = FLAG_WIDE_INDICES | FLAG_EXTENDED_OPS;
+        assert_eq!(
+            decode(&both_flags),
+            Err(DecodeError::UnsupportedFlags(
+                FLAG_WIDE_INDICES | FLAG_EXTENDED_OPS
+            ))
+        );
+
+        let mut wrong_flags = encoded.clone();
+        wrong_flags[5] = 0b1000_0000;
+        assert_eq!(
+            decode(&wrong_flags),
+            Err(DecodeError::UnsupportedFlags(0b1000_0000))
+        );
+
+        assert_eq!(decode(b"\x89PNG\r\n\x1a\n"), Err(DecodeError::NotPaltex));
+    }
+
+    /// Encodings of the test images and the test animation with each op set
+    fn test_encodings() -> Vec<Vec<u8>> {
+        let mut encodings = vec![encode_v1(&test_paltex())];
+        for (width, data) in test_images() {
+            let paltex = PalTex {
+                width: width as u32,
+                height: (data.len() / width) as u32,
+                palette: vec![Color::TRANSPARENT; 200],
+                data,
+            };
+            for op_set in [OpSet::Runs(IndexWidth::Wide), OpSet::Extended] {
+                let mut encoded = Vec::new();
+                encode_with(&paltex, op_set, &mut encoded);
+                encodings.push(encoded);
+            }
+        }
+        for op_set in [OpSet::Runs(IndexWidth::Narrow), OpSet::Extended] {
+            let mut encoded = Vec::new();
+            encode_anim_with(&test_anim(), op_set, &mut encoded);
+            encodings.push(encoded);
+        }
+        encodings
+    }
+
+    #[test]
+    fn test_decode_into() {
+        let mut palette = [Color::TRANSPARENT; 256];
+        let mut data = vec![0; 300 * 200 + 1];
+        for encoded in test_encodings() {
+            let decoded = decode_anim(&encoded).unwrap();
+            let info = decode_info(&encoded).unwrap();
+            assert_eq!(info.frame_count, decoded.frame_count());
+            assert_eq!(info.data_len(), decoded.data.len());
+
+            assert_eq!(decode_into(&encoded, &mut palette, &mut data), Ok(info));
+            assert_eq!(palette[..info.palette_len], decoded.palette);
+            assert_eq!(data[..info.data_len()], decoded.data);
+
+            let mut decoder = RowDecoder::new(&encoded, &mut palette, &mut data).unwrap();
+            assert_eq!(decoder.info(), &info);
+            let mut rows = Vec::new();
+            while let Some(row) = decoder.next_row() {
+                let row = row.unwrap();
+                assert_eq!(row.len(), info.width as usize);
+                rows.extend_from_slice(row);
+            }
+            assert_eq!(rows, decoded.data);
+        }
+    }
+
+    #[test]
+    fn test_decode_into_errors() {
+        let mut encoded = Vec::new();
+        encode_with(&test_paltex(), OpSet::Extended, &mut encoded);
+        let mut palette = [Color::TRANSPARENT; 3];
+        let mut data = [0; 15];
+
+        assert_eq!(
+            decode_into(&encoded, &mut palette[..2], &mut data),
+            Err(DecodeError::BufferTooSmall {
+                needed: 3,
+                actual: 2
+            })
+        );
+        assert!(matches!(
+            RowDecoder::new(&encoded, &mut palette, &mut data[..14]),
+            Err(DecodeError::BufferTooSmall {
+                needed: 15,
+                actual: 14
+            })
+        ));
+
+        // Changes the height, keeping the checksum valid
+        let container_len = size_of::<Container>();
+        let resized = |height: u8| {
+            let mut resized = encoded.clone();
+            resized[container_len + 2] = height;
+            let crc = crc32(&resized[container_len..]);
+            resized[12..16].copy_from_slice(&crc.to_le_bytes());
+            resized
+        };
+
+        let taller = resized(4);
+        let mut data = [0; 20];
+        assert_eq!(
+            decode_into(&taller, &mut palette, &mut data),
+            Err(DecodeError::IncompleteData { missing_pixels: 5 })
+        );
+        let mut decoder = RowDecoder::new(&taller, &mut palette, &mut data).unwrap();
+        for _ in 0..3 {
+            assert!(decoder.next_row().unwrap().is_ok());
+        }
+        assert_eq!(
+            decoder.next_row(),
+            Some(Err(DecodeError::IncompleteData { missing_pixels: 5 }))
+        );
+        assert_eq!(decoder.next_row(), None);
+
+        let shorter = resized(2);
+        let mut decoder = RowDecoder::new(&shorter, &mut palette, &mut data).unwrap();
+        for _ in 0..2 {
+            assert!(decoder.next_row().unwrap().is_ok());
+        }
+        assert!(matches!(
+            decoder.next_row(),
+            Some(Err(DecodeError::ExcessData { .. }))
+        ));
+        assert_eq!(decoder.next_row(), None);
+
+        // Errors of version 1 are reported as is, as with `decode`
+        let encoded = encode_v1(&test_paltex());
+        let truncated = &encoded[..encoded.len() - 1];
+        assert_eq!(
+            decode_into(truncated, &mut palette, &mut data),
+            Err(DecodeError::IncompleteData { missing_pixels: 5 })
+        );
+        assert_eq!(
+            decode_into(&encoded, &mut palette, &mut data[..14]),
+            Err(DecodeError::BufferTooSmall {
+                needed: 15,
+                actual: 14
+            })
+        );
+        let mut decoder = RowDecoder::new(truncated, &mut palette, &mut data).unwrap();
+        for _ in 0..2 {
+            assert!(decoder.next_row().unwrap().is_ok());
+        }
+        assert_eq!(
+            decoder.next_row(),
+            Some(Err(DecodeError::IncompleteData { missing_pixels: 5 }))
+        );
+    }
+
+    #[test]
+    fn test_palette_file() {
+        let palette = test_paltex().palette;
+        let mut encoded = Vec::new();
+        encode_palette(&palette, &mut encoded);
+        assert_eq!(encoded[..4], PALETTE_MAGIC);
+        let decoded = decode_palette(&encoded).unwrap();
+        assert_eq!(decoded[0], Color::TRANSPARENT);
+        assert_eq!(decoded[1..], palette);
+
+        let mut empty = Vec::new();
+        encode_palette(&[], &mut empty);
+        assert_eq!(decode_palette(&empty), Ok(vec![Color::TRANSPARENT]));
+
+        assert_eq!(
+            decode_palette(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::Truncated {
+                expected: encoded.len(),
+                actual: encoded.len() - 1
+            })
+        );
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode_palette(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        let mut corrupted = encoded.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decode_palette(&corrupted),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        let mut wrong_version = encoded.clone();
+        wrong_version[4] = 2;
+        assert_eq!(
+            decode_palette(&wrong_version),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+
+        let mut paltex = Vec::new();
+        encode(&test_paltex(), &mut paltex);
+        assert_eq!(decode_palette(&paltex), Err(DecodeError::NotPalette));
+        assert_eq!(decode(&encoded), Err(DecodeError::NotPaltex));
     }
 
     #[test]
     fn fuzz_results() {
-        let _ = decode(&[]);
-        let _ = decode(&[180, 16, 0, 0, 180, 0]);
-        let _ = decode(&[0, 0, 0, 0, 0, 10]);
-        let _ = decode(&[76, 36, 1, 0, 0, 218, 255]);
-        let _ = decode(&[76, 255, 254, 254, 0, 10, 0, 10]); // Used to eat too much memory
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[180, 16, 0, 0, 180, 0]).is_err());
+        assert!(decode(&[0, 0, 0, 0, 0, 10]).is_err());
+        assert!(decode(&[76, 36, 1, 0, 0, 218, 255]).is_err());
+        assert!(decode(&[76, 255, 254, 254, 0, 10, 0, 10]).is_err()); // Used to eat too much memory
+        assert!(decode(b"PTEX\x02\x00\x00\x00\xff\xff\xff\xff").is_err());
+        // A 65535x65535 extended run: used to allocate 4 GiB
+        let huge_run = b"PTEX\x02\x02\x00\x00\x07\x00\x00\x00\xba\x5a\x54\x05\xff\xff\xff\xff\x01\x00\xff\x00\x00\xff\x3f\xc1\xff\xf7\xff\x0f\x01";
+        assert_eq!(
+            decode(huge_run),
+            Err(DecodeError::TooLarge {
+                data_len: 65535 * 65535
+            })
+        );
     }
 }
@@ -7,7 +7,7 @@ use bang_core::{
     ffi::{RtCtx, Tex},
 };
 use bang_rt_common::{
-    die,
+    assets, die,
     draw::{Color, DrawReceiver, PalTex},
     end::Ender,
     error::OrDie,
@@ -242,7 +242,7 @@ impl Vertex {
 
 unsafe impl AsBytes for Vertex {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct BoundPalTex {
     quad_size: [u16; 2],
     pal: MTLTexture::PPtr,
@@ -328,7 +328,7 @@ pub fn load_textures<'f>(rt_ctx: &mut RtCtx, tex: &[&str], mem: &mut Mem<'f>) ->
     let mut ids = mem.sink();
     let rt = RtState::unwrap_from(rt_ctx);
     for &t in tex {
-        let tex = PalTex::from_encoded(&std::fs::read(t).unwrap()).unwrap();
+        let tex = PalTex::from_encoded(&assets::read(t)).unwrap();
         let bound_tex = BoundPalTex::new(&tex, &mut rt.device);
         ids.push(rt.textures.alloc_upcast(bound_tex));
     }
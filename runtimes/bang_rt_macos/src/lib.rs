@@ -8,7 +8,9 @@ use bang_core::{
     alloc::Managed,
     ffi::{RtCtx, RtKind, SendableErasedPtr, Tex},
 };
-use bang_rt_common::{draw::DrawReceiver, end::Ender, input::InputGatherer, runtime::Runtime};
+use bang_rt_common::{
+    alloc::SharedAllocState, draw::DrawReceiver, end::Ender, input::InputGatherer, runtime::Runtime,
+};
 
 use draw::BoundPalTex;
 use objc::wrappers::MTLDevice;
@@ -43,24 +45,26 @@ impl Runtime for MacOSRT {
         Window::notify_end(ender)
     }
 
-    fn new_ctx(&self) -> RtCtx {
+    fn new_ctx(&self, alloc_state: &'static SharedAllocState) -> RtCtx {
         let device = MTLDevice::PPtr::get_default();
         let rt_state = Box::new(RtState {
             device,
-            textures: Managed::default(),
+            textures: Managed::new(alloc_state),
         });
         RtCtx {
             frame: 0,
             rt_kind: RtKind::MacOS,
             load_textures_ptr: draw::load_textures,
+            load_palette_ptr: bang_rt_common::assets::load_palette,
             rt_state: SendableErasedPtr::wrap(rt_state),
+            alloc_state,
         }
     }
 }
 
 struct RtState {
     device: MTLDevice::PPtr,
-    textures: Managed<BoundPalTex, Tex>,
+    textures: Managed<'static, BoundPalTex, Tex>,
 }
 
 impl RtState {
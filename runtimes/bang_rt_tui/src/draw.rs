@@ -1,13 +1,33 @@
-use std::io::{StdoutLock, Write};
+use std::{
+    io::{ErrorKind, StdoutLock, Write},
+    ops::Not,
+    sync::{Arc, PoisonError, RwLock},
+    thread::sleep,
+    time::Duration,
+};
 
 use bang_core::{
-    alloc::{Id, Mem},
-    draw::{Cmd, DrawFrame},
+    Config,
+    alloc::{Id, Managed, ManagedReader, Mem},
+    draw::{Cmd, DrawFrame, ScreenPos},
     ffi::{RtCtx, Tex},
 };
-use bang_rt_common::{die, error::OrDie};
+use bang_rt_common::{
+    assets, die,
+    draw::{Color, PalTex},
+    error::OrDie,
+};
+
+use crate::RtState;
 
 const CSI: &str = "\x1b[";
+/// Each terminal cell shows two vertically stacked pixels: the upper one as
+/// the foreground color of the half block, the lower one as the background.
+const UPPER_HALF_BLOCK: char = '▀';
+const CLEAR_COLOR: Color = Color::from_rgba_u8([0, 0, 0, 255]);
+const LETTERBOX_COLOR: Color = Color::from_rgba_u8([0, 0, 0, 255]);
+const DUMMY_COLOR: Color = Color::from_rgba_u8([255, 0, 255, 255]);
+const DUMMY_SIZE: u32 = 8;
 
 fn move_to(buf: &mut Vec<u8>, row: u32, col: u32) {
     write!(buf, "{CSI}{row};{col}H").or_(die!("Error moving cursor"));
@@ -21,40 +41,529 @@ fn hide_cursor(buf: &mut Vec<u8>) {
     write!(buf, "{CSI}?25l").or_(die!("Error hiding cursor"));
 }
 
-pub fn show_cursor(buf: &mut Vec<u8>) {
+fn show_cursor(buf: &mut Vec<u8>) {
     write!(buf, "{CSI}?25h").or_(die!("Error showing cursor"));
 }
 
-pub fn flush(buf: &mut Vec<u8>, output_stream: &mut StdoutLock<'static>) {
-    move_to(buf, 0, 0);
-    output_stream
-        .write_all(buf)
-        .or_(die!("Error writing to stdout"));
-    output_stream.flush().or_(die!("Error flushing stdout"));
+fn enter_alt_screen(buf: &mut Vec<u8>) {
+    write!(buf, "{CSI}?1049h").or_(die!("Error entering alternate screen"));
 }
 
-pub fn draw(frame: &DrawFrame, output_stream: &mut StdoutLock<'static>, buf: &mut Vec<u8>) {
-    buf.clear();
-    erase_screen(buf);
+fn leave_alt_screen(buf: &mut Vec<u8>) {
+    write!(buf, "{CSI}?1049l").or_(die!("Error leaving alternate screen"));
+}
+
+/// Switches to the alternate screen, so that the original contents of the
+/// terminal are shown again after quitting.
+pub fn setup_screen(buf: &mut Vec<u8>) {
+    enter_alt_screen(buf);
     hide_cursor(buf);
-    let chars = "████";
+}
+
+pub fn restore_screen(buf: &mut Vec<u8>) {
+    reset_colors(buf);
+    show_cursor(buf);
+    leave_alt_screen(buf);
+}
+
+fn set_fg(buf: &mut Vec<u8>, Color { r, g, b, .. }: Color) {
+    write!(buf, "{CSI}38;2;{r};{g};{b}m").or_(die!("Error setting foreground color"));
+}
+
+fn set_bg(buf: &mut Vec<u8>, Color { r, g, b, .. }: Color) {
+    write!(buf, "{CSI}48;2;{r};{g};{b}m").or_(die!("Error setting background color"));
+}
+
+fn reset_colors(buf: &mut Vec<u8>) {
+    write!(buf, "{CSI}0m").or_(die!("Error resetting colors"));
+}
+
+/// Asks the terminal to hold off presenting until the whole frame is written
+/// (DECSET 2026). Terminals that don't support it ignore it.
+fn begin_sync_update(buf: &mut Vec<u8>) {
+    write!(buf, "{CSI}?2026h").or_(die!("Error beginning synchronized update"));
+}
+
+fn end_sync_update(buf: &mut Vec<u8>) {
+    write!(buf, "{CSI}?2026l").or_(die!("Error ending synchronized update"));
+}
+
+fn flush(mut buf: &[u8], output_stream: &mut StdoutLock<'static>) {
+    // Stdout shares the non-blocking mode set on stdin when both are the
+    // same terminal, so a slow terminal might not take everything at once.
+    while buf.is_empty().not() {
+        match output_stream.write(buf) {
+            Ok(written) => buf = &buf[written..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(Duration::from_millis(1)),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => die!("Error writing to stdout").end_with(e),
+        }
+    }
+    output_stream.flush().or_(die!("Error flushing stdout"));
+}
+
+/// The decoded textures, owned by the runtime. They are loaded on the logic
+/// thread and read on the render thread.
+pub type TexStore = Arc<RwLock<Vec<PalTex>>>;
+
+/// Texture for the software renderer: the index of its decoded data in the
+/// `TexStore`, as `Managed` only holds values that don't need to be dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct TuiTex {
+    idx: usize,
+}
+
+fn pixel(paltex: &PalTex, x: u32, y: u32) -> Color {
+    let idx = paltex.data[(y * paltex.width + x) as usize];
+    paltex
+        .palette
+        .get(idx as usize)
+        .copied()
+        .unwrap_or(Color::TRANSPARENT)
+}
+
+/// Framebuffer in `Config::resolution` that the frames are rasterized into
+/// before being turned into terminal cells.
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Canvas {
+    pub fn new(config: &Config) -> Self {
+        let (width, height) = config.resolution;
+        Self {
+            width,
+            height,
+            pixels: vec![CLEAR_COLOR; (width * height) as usize],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pixels.fill(CLEAR_COLOR);
+    }
+
+    fn get(&self, x: u32, y: u32) -> Color {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize]
+        } else {
+            CLEAR_COLOR
+        }
+    }
+
+    fn blend(&mut self, x: i64, y: i64, src: Color) {
+        if src.a == 0 || x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let dst = &mut self.pixels[(y * self.width as i64 + x) as usize];
+        if src.a == u8::MAX {
+            *dst = src;
+            return;
+        }
+        let a = src.a as u32;
+        let mix = |s: u8, d: u8| ((s as u32 * a + d as u32 * (255 - a)) / 255) as u8;
+        *dst = Color {
+            r: mix(src.r, dst.r),
+            g: mix(src.g, dst.g),
+            b: mix(src.b, dst.b),
+            a: u8::MAX,
+        };
+    }
+
+    /// Draws a `width` x `height` quad whose bottom-left corner is at `pos`.
+    /// `ScreenPos` has its origin at the center of the screen with y growing
+    /// upwards, whereas texture and canvas rows grow downwards.
+    fn blit(&mut self, pos: ScreenPos, width: u32, height: u32, pixel: impl Fn(u32, u32) -> Color) {
+        let left = (pos.x + self.width as f32 / 2.0).floor() as i64;
+        let top = (self.height as f32 / 2.0 - pos.y - height as f32).floor() as i64;
+        for y in 0..height {
+            for x in 0..width {
+                self.blend(left + x as i64, top + y as i64, pixel(x, y));
+            }
+        }
+    }
+}
+
+/// Where the canvas ends up on the terminal grid, scaled to fit and
+/// centered, with the rest of the grid letterboxed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Viewport {
+    scale: f32,
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Viewport {
+    /// `grid` is the terminal size in cells, each having two pixels stacked.
+    fn fit(resolution: (u32, u32), grid: (u32, u32)) -> Self {
+        let (res_w, res_h) = resolution;
+        let (grid_w, grid_h) = (grid.0, grid.1 * 2);
+        let scale = f32::min(grid_w as f32 / res_w as f32, grid_h as f32 / res_h as f32);
+        // Upscale only by whole multiples to keep the pixels uniformly sized
+        let scale = if scale >= 1.0 { scale.floor() } else { scale };
+        let width = ((res_w as f32 * scale) as u32).min(grid_w);
+        let height = ((res_h as f32 * scale) as u32).min(grid_h);
+        Self {
+            scale,
+            left: (grid_w - width) / 2,
+            top: (grid_h - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// Maps a pixel on the terminal grid to a pixel on the canvas.
+    fn canvas_pixel(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let x = x.checked_sub(self.left).filter(|&x| x < self.width)?;
+        let y = y.checked_sub(self.top).filter(|&y| y < self.height)?;
+        Some((
+            (x as f32 / self.scale) as u32,
+            (y as f32 / self.scale) as u32,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    upper: Color,
+    lower: Color,
+}
+
+/// Keeps track of what is currently shown on the terminal, so that only the
+/// changed cells, and only the changed colors, need to be written out.
+pub struct Screen {
+    canvas: Canvas,
+    cols: u32,
+    rows: u32,
+    viewport: Viewport,
+    front: Vec<Cell>,
+    /// The terminal contents are unknown, e.g. at startup, so everything
+    /// needs to be redrawn.
+    stale: bool,
+    /// Zero-based (row, col) the next character is going to be written to
+    cursor: Option<(u32, u32)>,
+    fg: Option<Color>,
+    bg: Option<Color>,
+}
+
+impl Screen {
+    /// `grid` is the terminal size in (columns, rows).
+    pub fn new(config: &Config, grid: (u32, u32)) -> Self {
+        let mut screen = Self {
+            canvas: Canvas::new(config),
+            cols: 0,
+            rows: 0,
+            viewport: Viewport::fit(config.resolution, (0, 0)),
+            front: Vec::new(),
+            stale: true,
+            cursor: None,
+            fg: None,
+            bg: None,
+        };
+        screen.resize(grid);
+        screen
+    }
+
+    /// The terminal grid needed for showing the resolution unscaled.
+    pub fn natural_grid(config: &Config) -> (u32, u32) {
+        let (width, height) = config.resolution;
+        (width, height.div_ceil(2))
+    }
+
+    pub fn resize(&mut self, (cols, rows): (u32, u32)) {
+        let blank = Cell {
+            upper: LETTERBOX_COLOR,
+            lower: LETTERBOX_COLOR,
+        };
+        self.cols = cols;
+        self.rows = rows;
+        self.viewport = Viewport::fit((self.canvas.width, self.canvas.height), (cols, rows));
+        self.front.clear();
+        self.front.resize((cols * rows) as usize, blank);
+        self.stale = true;
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Color {
+        match self.viewport.canvas_pixel(x, y) {
+            Some((x, y)) => self.canvas.get(x, y),
+            None => LETTERBOX_COLOR,
+        }
+    }
+
+    fn cell(&self, col: u32, row: u32) -> Cell {
+        Cell {
+            upper: self.pixel(col, row * 2),
+            lower: self.pixel(col, row * 2 + 1),
+        }
+    }
+
+    /// Writes out the changes needed for the terminal to match the canvas.
+    fn diff(&mut self, buf: &mut Vec<u8>) {
+        if self.stale {
+            reset_colors(buf);
+            erase_screen(buf);
+            hide_cursor(buf);
+            self.cursor = None;
+            self.fg = None;
+            self.bg = None;
+        }
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let cell = self.cell(col, row);
+                let shown = &mut self.front[(row * self.cols + col) as usize];
+                if *shown == cell && !self.stale {
+                    continue;
+                }
+                *shown = cell;
+                self.move_cursor(buf, row, col);
+                self.write_cell(buf, cell);
+            }
+        }
+        self.stale = false;
+    }
+
+    fn move_cursor(&mut self, buf: &mut Vec<u8>, row: u32, col: u32) {
+        match self.cursor {
+            Some(cursor) if cursor == (row, col) => (),
+            Some((cursor_row, cursor_col)) if cursor_row == row && cursor_col < col => {
+                write!(buf, "{CSI}{}C", col - cursor_col).or_(die!("Error moving cursor"))
+            }
+            _ => move_to(buf, row + 1, col + 1),
+        }
+        self.cursor = Some((row, col));
+    }
+
+    fn write_cell(&mut self, buf: &mut Vec<u8>, cell: Cell) {
+        if self.bg != Some(cell.lower) {
+            set_bg(buf, cell.lower);
+            self.bg = Some(cell.lower);
+        }
+        // A uniformly colored cell doesn't care about the foreground color
+        let ch = if cell.upper == cell.lower {
+            ' '
+        } else {
+            if self.fg != Some(cell.upper) {
+                set_fg(buf, cell.upper);
+                self.fg = Some(cell.upper);
+            }
+            UPPER_HALF_BLOCK
+        };
+        write!(buf, "{ch}").or_(die!("Error writing to buffer"));
+        self.cursor = match self.cursor {
+            // After writing to the last column, the cursor position depends on the terminal
+            Some((row, col)) if col + 1 < self.cols => Some((row, col + 1)),
+            _ => None,
+        };
+    }
+}
+
+pub fn draw(
+    frame: &DrawFrame,
+    textures: &ManagedReader<TuiTex, Tex>,
+    tex_store: &RwLock<Vec<PalTex>>,
+    screen: &mut Screen,
+    output_stream: &mut StdoutLock<'static>,
+    buf: &mut Vec<u8>,
+) {
+    let tex_store = tex_store.read().unwrap_or_else(PoisonError::into_inner);
+    let canvas = &mut screen.canvas;
+    canvas.clear();
     for cmd in frame.cmds {
         match cmd {
-            Cmd::DrawSQuads { pos, .. } | Cmd::DrawDummies { pos } => {
-                for pos in pos.iter() {
-                    let row = ((pos.y + 200.0) / 20.0) as u32;
-                    let col = ((pos.x + 200.0) / 10.0) as u32;
-                    for r in 0..3 {
-                        move_to(buf, row + r, col);
-                        write!(buf, "{chars}").or_(die!("Error writing to buffer"));
-                    }
+            Cmd::DrawSQuads { tex, pos } => {
+                // Safety: the texture ID comes from `frame`, which is borrowed
+                // from the draw receiver, and isn't retired before the next
+                // frame is picked up.
+                let Some(tex) = (unsafe { textures.get(Managed::downcast(*tex)) }) else {
+                    // Skips a freed texture silently: printing a warning every
+                    // frame would garble the terminal
+                    continue;
+                };
+                let paltex = &tex_store[tex.idx];
+                for &pos in pos.iter() {
+                    canvas.blit(pos, paltex.width, paltex.height, |x, y| pixel(paltex, x, y));
+                }
+            }
+            Cmd::DrawDummies { pos } => {
+                for &pos in pos.iter() {
+                    canvas.blit(pos, DUMMY_SIZE, DUMMY_SIZE, |_, _| DUMMY_COLOR);
                 }
-                flush(buf, output_stream);
             }
         }
     }
+    present(screen, output_stream, buf);
+}
+
+/// Updates the terminal to match what was last drawn.
+pub fn present(screen: &mut Screen, output_stream: &mut StdoutLock<'static>, buf: &mut Vec<u8>) {
+    buf.clear();
+    begin_sync_update(buf);
+    let header_len = buf.len();
+    screen.diff(buf);
+    if buf.len() == header_len {
+        return; // Nothing changed
+    }
+    end_sync_update(buf);
+    flush(buf, output_stream);
+}
+
+pub fn load_textures<'f>(rt_ctx: &mut RtCtx, tex: &[&str], mem: &mut Mem<'f>) -> &'f [Id<Tex>] {
+    let mut ids = mem.sink();
+    let rt = RtState::unwrap_from(rt_ctx);
+    for &t in tex {
+        let bytes = assets::read(t);
+        let paltex = PalTex::from_encoded(&bytes).or_(die!("Failed to decode texture {}", t));
+        let mut tex_store = rt.tex_store.write().unwrap_or_else(PoisonError::into_inner);
+        let tex = TuiTex {
+            idx: tex_store.len(),
+        };
+        tex_store.push(paltex);
+        ids.push(rt.textures.alloc_upcast(tex));
+    }
+    ids.into_slice()
 }
 
-pub fn load_textures<'f>(_: &mut RtCtx, _: &[&str], _: &mut Mem<'f>) -> &'f [Id<Tex>] {
-    unimplemented!()
+#[cfg(test)]
+mod tests {
+    use bang_core::{FramesInFlight, MemBudget};
+
+    use super::*;
+
+    const RED: Color = Color::from_rgba_u8([255, 0, 0, 255]);
+    const HALF_WHITE: Color = Color::from_rgba_u8([255, 255, 255, 128]);
+
+    fn canvas(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![CLEAR_COLOR; (width * height) as usize],
+        }
+    }
+
+    #[test]
+    fn test_blit_coordinates() {
+        let mut canvas = canvas(4, 4);
+        // Bottom-left corner of a 1x1 quad at the origin is the center of the screen
+        canvas.blit(ScreenPos { x: 0.0, y: 0.0 }, 1, 1, |_, _| RED);
+        assert_eq!(canvas.get(2, 1), RED);
+        // The first texture row is the top one
+        canvas.blit(ScreenPos { x: -2.0, y: 0.0 }, 1, 2, |_, y| {
+            [RED, Color::TRANSPARENT][y as usize]
+        });
+        assert_eq!(canvas.get(0, 0), RED);
+        assert_eq!(canvas.get(0, 1), CLEAR_COLOR);
+        // Out of bounds is clipped
+        canvas.blit(ScreenPos { x: 1.0, y: -2.0 }, 4, 4, |_, _| RED);
+        assert_eq!(canvas.get(3, 3), RED);
+        assert_eq!(canvas.get(2, 3), CLEAR_COLOR);
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut canvas = canvas(1, 1);
+        canvas.blend(0, 0, Color::TRANSPARENT);
+        assert_eq!(canvas.get(0, 0), CLEAR_COLOR);
+        canvas.blend(0, 0, HALF_WHITE);
+        assert_eq!(canvas.get(0, 0), Color::from_rgba_u8([128, 128, 128, 255]));
+        canvas.blend(0, 0, RED);
+        assert_eq!(canvas.get(0, 0), RED);
+    }
+
+    fn screen(width: u32, height: u32) -> Screen {
+        let config = Config {
+            name: "test",
+            resolution: (width, height),
+            logic_fps: 1,
+            scale: 1,
+            frame_mem_budget: MemBudget::Unlimited,
+            max_frames_in_flight: FramesInFlight::Unlimited,
+        };
+        let mut screen = Screen::new(&config, Screen::natural_grid(&config));
+        screen.stale = false;
+        screen
+    }
+
+    fn diff(screen: &mut Screen) -> String {
+        let mut buf = Vec::new();
+        screen.diff(&mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_diff_initial() {
+        let mut screen = screen(2, 3);
+        screen.stale = true;
+        screen.canvas.blend(0, 0, RED);
+        assert_eq!(
+            diff(&mut screen),
+            "\x1b[0m\x1b[2J\x1b[?25l\
+             \x1b[1;1H\x1b[48;2;0;0;0m\x1b[38;2;255;0;0m▀ \
+             \x1b[2;1H  "
+        );
+        // Nothing changed
+        assert_eq!(diff(&mut screen), "");
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let mut screen = screen(8, 4);
+        screen.canvas.blend(5, 0, RED);
+        screen.canvas.blend(6, 1, RED);
+        screen.canvas.blend(7, 1, RED);
+        assert_eq!(
+            diff(&mut screen),
+            "\x1b[1;6H\x1b[48;2;0;0;0m\x1b[38;2;255;0;0m▀\
+             \x1b[48;2;255;0;0m\x1b[38;2;0;0;0m▀▀"
+        );
+        screen.canvas.clear();
+        screen.canvas.blend(6, 1, RED);
+        screen.canvas.blend(0, 3, RED);
+        // Unchanged cells are skipped over, and unchanged colors aren't repeated
+        assert_eq!(
+            diff(&mut screen),
+            "\x1b[1;6H\x1b[48;2;0;0;0m \x1b[1C \x1b[2;1H\x1b[48;2;255;0;0m▀"
+        );
+    }
+
+    #[test]
+    fn test_viewport() {
+        // Fits exactly
+        let viewport = Viewport::fit((320, 200), (320, 100));
+        assert_eq!((viewport.scale, viewport.left, viewport.top), (1.0, 0, 0));
+        // Upscaled by a whole multiple and letterboxed
+        let viewport = Viewport::fit((4, 4), (10, 5));
+        assert_eq!((viewport.scale, viewport.left, viewport.top), (2.0, 1, 1));
+        assert_eq!(viewport.canvas_pixel(0, 0), None);
+        assert_eq!(viewport.canvas_pixel(1, 1), Some((0, 0)));
+        assert_eq!(viewport.canvas_pixel(8, 8), Some((3, 3)));
+        assert_eq!(viewport.canvas_pixel(9, 9), None);
+        // Downscaled to fit the width
+        let viewport = Viewport::fit((320, 200), (160, 100));
+        assert_eq!((viewport.scale, viewport.left, viewport.top), (0.5, 0, 50));
+        assert_eq!((viewport.width, viewport.height), (160, 100));
+        assert_eq!(viewport.canvas_pixel(159, 50), Some((318, 0)));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut screen = screen(2, 2);
+        screen.canvas.blend(0, 0, RED);
+        screen.resize((6, 2));
+        assert!(screen.stale);
+        let red = Cell {
+            upper: RED,
+            lower: RED,
+        };
+        // Scaled 2x, with a letterbox column on both sides
+        assert_eq!(screen.viewport.left, 1);
+        assert_ne!(screen.cell(0, 0), red);
+        assert_eq!(screen.cell(1, 0), red);
+        assert_eq!(screen.cell(2, 0), red);
+        assert_ne!(screen.cell(3, 0), red);
+        assert_ne!(screen.cell(1, 1), red);
+        assert_eq!(diff(&mut screen).matches(' ').count(), 12);
+    }
 }
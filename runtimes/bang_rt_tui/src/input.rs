@@ -5,28 +5,54 @@ use std::{
     time::{Duration, Instant},
 };
 
-use bang_core::input::{Key, KeyState};
+use bang_core::input::KeyState;
 use bang_rt_common::{end::Ender, input::InputGatherer};
 
-use crate::LOOP_MS;
+use crate::{
+    LOOP_MS,
+    parser::{KeyEventKind, Parsed, Parser, kitty_reports_release},
+};
 
 pub fn gather(ender: &Ender, input_gatherer: &mut InputGatherer) {
     let mut input_stream = std::io::stdin().lock();
-    let mut input_buf = [0u8; 1];
+    let mut input_buf = [0u8; 256];
+    let mut parser = Parser::new();
+    let mut parsed = Vec::new();
+    // Legacy terminals only report presses; with the kitty keyboard
+    // protocol, we get proper press and release events.
+    let mut has_release_events = false;
     while ender.should_end().not() {
         match input_stream.read(&mut input_buf) {
-            Ok(1) => {}
-            Ok(_) => unreachable!(),
+            Ok(0) => thread::sleep(Duration::from_millis(LOOP_MS)),
+            Ok(n) => parser.feed(&input_buf[..n], &mut parsed),
             Err(e) => {
                 if let ErrorKind::WouldBlock | ErrorKind::Interrupted = e.kind() {
-                    thread::sleep(Duration::from_millis(LOOP_MS));
-                    continue;
+                    // Input is idle, so a pending lone ESC is the escape key
+                    parser.flush(&mut parsed);
+                    if parsed.is_empty() {
+                        thread::sleep(Duration::from_millis(LOOP_MS));
+                        continue;
+                    }
                 } else {
                     panic!("Error reading input: {e}");
                 }
             }
         }
-        let key = Key::from_ascii(input_buf[0]);
-        input_gatherer.update(key, KeyState::Tap, Instant::now());
+        let now = Instant::now();
+        for p in parsed.drain(..) {
+            match p {
+                Parsed::KittyFlags(flags) => has_release_events = kitty_reports_release(flags),
+                Parsed::Interrupt => ender.soft_quit(),
+                Parsed::Key(event) => {
+                    let state = match (has_release_events, event.kind) {
+                        (false, _) => KeyState::Tap,
+                        (true, KeyEventKind::Press) => KeyState::Pressed,
+                        (true, KeyEventKind::Release) => KeyState::Released,
+                        (true, KeyEventKind::Repeat) => continue,
+                    };
+                    input_gatherer.update(event.key, state, now);
+                }
+            }
+        }
     }
 }
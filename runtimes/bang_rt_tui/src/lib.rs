@@ -1,14 +1,17 @@
 mod draw;
 mod input;
+mod parser;
 mod win;
 
-use std::ptr::null_mut;
-
 use bang_core::{
     Config,
-    ffi::{RtCtx, RtKind, SendableErasedPtr},
+    alloc::Managed,
+    ffi::{RtCtx, RtKind, SendableErasedPtr, Tex},
+};
+use bang_rt_common::{
+    alloc::SharedAllocState, draw::DrawReceiver, end::Ender, input::InputGatherer, runtime::Runtime,
 };
-use bang_rt_common::{draw::DrawReceiver, end::Ender, input::InputGatherer, runtime::Runtime};
+use draw::{TexStore, TuiTex};
 use win::Window;
 
 pub struct TuiRT;
@@ -38,12 +41,29 @@ impl Runtime for TuiRT {
 
     fn notify_end(_: &Ender) {}
 
-    fn new_ctx(&self) -> RtCtx {
+    fn new_ctx(&self, alloc_state: &'static SharedAllocState) -> RtCtx {
+        let rt_state = Box::new(RtState {
+            textures: Managed::new(alloc_state),
+            tex_store: TexStore::default(),
+        });
         RtCtx {
             frame: 0,
             rt_kind: RtKind::TUI,
             load_textures_ptr: draw::load_textures,
-            rt_state: SendableErasedPtr(null_mut()),
+            load_palette_ptr: bang_rt_common::assets::load_palette,
+            rt_state: SendableErasedPtr::wrap(rt_state),
+            alloc_state,
         }
     }
 }
+
+struct RtState {
+    textures: Managed<'static, TuiTex, Tex>,
+    tex_store: TexStore,
+}
+
+impl RtState {
+    fn unwrap_from(rt_ctx: &mut RtCtx) -> &mut RtState {
+        unsafe { &mut *(rt_ctx.rt_state.0 as *mut RtState) }
+    }
+}
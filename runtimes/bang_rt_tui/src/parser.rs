@@ -0,0 +1,375 @@
+use bang_core::input::Key;
+
+const ESC: u8 = 0x1b;
+const CTRL_C: u8 = 0x03;
+
+/// Kitty keyboard protocol flags we ask for: disambiguate escape codes (1),
+/// report event types (2) and report all keys as escape codes (8). The last
+/// one is needed to get release events for keys that produce text, like space.
+const KITTY_FLAGS: u8 = 1 | 2 | 8;
+const KITTY_REPORT_EVENT_TYPES: u8 = 2;
+
+/// Pushes our flags on the kitty keyboard protocol flag stack, and queries
+/// the current flags. Terminals not supporting the protocol ignore both.
+pub const KITTY_ENABLE: &str = "\x1b[>11u\x1b[?u";
+/// Pops our flags from the kitty keyboard protocol flag stack.
+pub const KITTY_DISABLE: &str = "\x1b[<u";
+
+// Keep `KITTY_ENABLE` in sync with the flags
+const _: () = assert!(KITTY_FLAGS == 11);
+
+/// Modifier bit flags as encoded by xterm and the kitty keyboard protocol
+/// (the parameter value minus one).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mods(u8);
+
+impl Mods {
+    pub const NONE: Mods = Mods(0);
+    pub const ALT: Mods = Mods(2);
+    pub const CTRL: Mods = Mods(4);
+
+    pub const fn contains(self, other: Mods) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    const fn from_param(param: u32) -> Mods {
+        Mods(param.saturating_sub(1) as u8)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Press,
+    Repeat,
+    Release,
+}
+
+impl KeyEventKind {
+    const fn from_param(param: u32) -> KeyEventKind {
+        match param {
+            2 => KeyEventKind::Repeat,
+            3 => KeyEventKind::Release,
+            _ => KeyEventKind::Press,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub mods: Mods,
+    pub kind: KeyEventKind,
+}
+
+impl KeyEvent {
+    const fn press(key: Key, mods: Mods) -> Self {
+        KeyEvent {
+            key,
+            mods,
+            kind: KeyEventKind::Press,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parsed {
+    Key(KeyEvent),
+    /// Response to the kitty keyboard protocol flag query
+    KittyFlags(u8),
+    /// Ctrl-C; when all keys are reported as escape codes, the terminal
+    /// doesn't send SIGINT, so we have to handle it ourselves.
+    Interrupt,
+}
+
+/// Returns true if the kitty keyboard protocol flags enable release events.
+pub const fn kitty_reports_release(flags: u8) -> bool {
+    flags & KITTY_REPORT_EVENT_TYPES != 0
+}
+
+enum Step {
+    Done(usize, Option<Parsed>),
+    Incomplete,
+}
+
+/// Incremental parser for the input byte stream of a VT/xterm compatible
+/// terminal, with support for the kitty keyboard protocol.
+///
+/// Escape sequences may arrive split between reads, so the incomplete tail
+/// of the input is kept around until more input arrives, or until `flush`
+/// is called when the input is idle.
+#[derive(Debug, Default)]
+pub struct Parser {
+    pending: Vec<u8>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, input: &[u8], out: &mut Vec<Parsed>) {
+        self.pending.extend_from_slice(input);
+        let mut start = 0;
+        while start < self.pending.len() {
+            match parse_one(&self.pending[start..]) {
+                Step::Done(consumed, parsed) => {
+                    out.extend(parsed);
+                    start += consumed;
+                }
+                Step::Incomplete => break,
+            }
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Resolves the pending input when no more input is arriving. A lone ESC
+    /// is the escape key itself, and any other incomplete input is dropped.
+    pub fn flush(&mut self, out: &mut Vec<Parsed>) {
+        if self.pending == [ESC] {
+            out.push(Parsed::Key(KeyEvent::press(Key::Other, Mods::NONE)));
+        }
+        self.pending.clear();
+    }
+}
+
+fn parse_one(input: &[u8]) -> Step {
+    match input[0] {
+        ESC => match input.get(1) {
+            None => Step::Incomplete,
+            Some(b'[') => parse_csi(input),
+            Some(b'O') => match input.get(2) {
+                None => Step::Incomplete,
+                Some(&b) => Step::Done(
+                    3,
+                    Some(Parsed::Key(KeyEvent::press(ss3_key(b), Mods::NONE))),
+                ),
+            },
+            Some(&ESC) => Step::Done(
+                1,
+                Some(Parsed::Key(KeyEvent::press(Key::Other, Mods::NONE))),
+            ),
+            Some(_) => match parse_byte(&input[1..]) {
+                Step::Done(consumed, Some(Parsed::Key(ev))) => Step::Done(
+                    consumed + 1,
+                    Some(Parsed::Key(KeyEvent::press(ev.key, Mods::ALT))),
+                ),
+                Step::Done(consumed, other) => Step::Done(consumed + 1, other),
+                Step::Incomplete => Step::Incomplete,
+            },
+        },
+        _ => parse_byte(input),
+    }
+}
+
+fn parse_byte(input: &[u8]) -> Step {
+    let byte = input[0];
+    let utf8_len = match byte {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    if input.len() < utf8_len {
+        return Step::Incomplete;
+    }
+    let parsed = match byte {
+        CTRL_C => Parsed::Interrupt,
+        _ => Parsed::Key(KeyEvent::press(Key::from_ascii(byte), Mods::NONE)),
+    };
+    Step::Done(utf8_len, Some(parsed))
+}
+
+fn ss3_key(byte: u8) -> Key {
+    match byte {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        _ => Key::Other,
+    }
+}
+
+/// Returns the `sub`th colon-separated part of the `field`th semicolon-
+/// separated parameter.
+fn param(params: &str, field: usize, sub: usize) -> Option<u32> {
+    params
+        .split(';')
+        .nth(field)?
+        .split(':')
+        .nth(sub)?
+        .parse()
+        .ok()
+}
+
+fn parse_csi(input: &[u8]) -> Step {
+    // CSI = ESC [ <parameter bytes 0x30-0x3F>* <intermediate bytes 0x20-0x2F>* <final byte 0x40-0x7E>
+    let body = &input[2..];
+    let Some(end) = body.iter().position(|b| !(0x20..=0x3F).contains(b)) else {
+        return Step::Incomplete;
+    };
+    let consumed = 2 + end + 1;
+    let final_byte = body[end];
+    if !(0x40..=0x7E).contains(&final_byte) {
+        // Malformed, drop everything up to the offending byte
+        return Step::Done(consumed - 1, None);
+    }
+
+    let params = &body[..end];
+    let (marker, params) = match params.first() {
+        Some(&m @ (b'<' | b'=' | b'>' | b'?')) => (Some(m), &params[1..]),
+        _ => (None, params),
+    };
+    // UNREACHABLE: parameter bytes are always ASCII
+    let params = std::str::from_utf8(params).expect("UNREACHABLE: ASCII");
+
+    let parsed = match (marker, final_byte) {
+        (Some(b'?'), b'u') => Some(Parsed::KittyFlags(param(params, 0, 0).unwrap_or(0) as u8)),
+        (Some(_), _) => None, // Other reports, such as device attributes
+        (None, b'A' | b'B' | b'C' | b'D' | b'u' | b'~' | b'H' | b'F' | b'P' | b'Q' | b'S') => {
+            let key_code = param(params, 0, 0).unwrap_or(1);
+            let mods = Mods::from_param(param(params, 1, 0).unwrap_or(1));
+            let kind = KeyEventKind::from_param(param(params, 1, 1).unwrap_or(1));
+            let key = match final_byte {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'u' => Key::from_ascii(u8::try_from(key_code).unwrap_or(0)),
+                _ => Key::Other,
+            };
+            if final_byte == b'u' && key_code == b'c' as u32 && mods.contains(Mods::CTRL) {
+                (kind == KeyEventKind::Press).then_some(Parsed::Interrupt)
+            } else {
+                Some(Parsed::Key(KeyEvent { key, mods, kind }))
+            }
+        }
+        (None, _) => None,
+    };
+    Step::Done(consumed, parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Parsed> {
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        parser.feed(input, &mut out);
+        parser.flush(&mut out);
+        out
+    }
+
+    fn key(key: Key, mods: Mods, kind: KeyEventKind) -> Parsed {
+        Parsed::Key(KeyEvent { key, mods, kind })
+    }
+
+    fn press(k: Key) -> Parsed {
+        key(k, Mods::NONE, KeyEventKind::Press)
+    }
+
+    #[test]
+    fn test_plain_bytes() {
+        assert_eq!(parse(b" a"), &[press(Key::Space), press(Key::Other)]);
+        assert_eq!(parse(b"\x03"), &[Parsed::Interrupt]);
+        // Multi-byte UTF-8 characters produce a single event
+        assert_eq!(
+            parse("ä ".as_bytes()),
+            &[press(Key::Other), press(Key::Space)]
+        );
+    }
+
+    #[test]
+    fn test_arrows() {
+        assert_eq!(
+            parse(b"\x1b[A\x1b[B\x1b[C\x1b[D"),
+            &[
+                press(Key::Up),
+                press(Key::Down),
+                press(Key::Right),
+                press(Key::Left)
+            ]
+        );
+        assert_eq!(
+            parse(b"\x1bOA\x1bOB\x1bOC\x1bOD"),
+            &[
+                press(Key::Up),
+                press(Key::Down),
+                press(Key::Right),
+                press(Key::Left)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(
+            parse(b"\x1b[1;5A"),
+            &[key(Key::Up, Mods::CTRL, KeyEventKind::Press)]
+        );
+        assert_eq!(
+            parse(b"\x1b[1;2D"),
+            &[key(Key::Left, Mods(1), KeyEventKind::Press)]
+        );
+        assert_eq!(
+            parse(b"\x1b "),
+            &[key(Key::Space, Mods::ALT, KeyEventKind::Press)]
+        );
+        assert_eq!(parse(b"\x1b[3~"), &[press(Key::Other)]);
+    }
+
+    #[test]
+    fn test_kitty() {
+        assert_eq!(parse(b"\x1b[?11u"), &[Parsed::KittyFlags(11)]);
+        assert!(kitty_reports_release(11));
+        assert!(!kitty_reports_release(1));
+        assert_eq!(
+            parse(b"\x1b[32u\x1b[32;1:2u\x1b[32;1:3u"),
+            &[
+                press(Key::Space),
+                key(Key::Space, Mods::NONE, KeyEventKind::Repeat),
+                key(Key::Space, Mods::NONE, KeyEventKind::Release),
+            ]
+        );
+        assert_eq!(
+            parse(b"\x1b[1;1:3A\x1b[1;6:1C"),
+            &[
+                key(Key::Up, Mods::NONE, KeyEventKind::Release),
+                key(Key::Right, Mods(5), KeyEventKind::Press),
+            ]
+        );
+        assert_eq!(parse(b"\x1b[99;5u\x1b[99;5:3u"), &[Parsed::Interrupt]);
+        // Device attributes response is ignored
+        assert_eq!(parse(b"\x1b[?62;22c"), &[]);
+    }
+
+    #[test]
+    fn test_split_input() {
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        parser.feed(b"\x1b", &mut out);
+        parser.feed(b"[1;", &mut out);
+        assert_eq!(out, &[]);
+        parser.feed(b"5A ", &mut out);
+        assert_eq!(
+            out,
+            &[
+                key(Key::Up, Mods::CTRL, KeyEventKind::Press),
+                press(Key::Space)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lone_escape() {
+        assert_eq!(parse(b"\x1b"), &[press(Key::Other)]);
+        assert_eq!(parse(b"\x1b\x1b[A"), &[press(Key::Other), press(Key::Up)]);
+        // Incomplete sequences are dropped on flush
+        assert_eq!(parse(b"\x1b[1;"), &[]);
+        // Malformed sequences are dropped
+        assert_eq!(
+            parse(b"\x1b[1\x01 "),
+            &[press(Key::Other), press(Key::Space)]
+        );
+    }
+}
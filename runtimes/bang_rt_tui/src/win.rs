@@ -1,24 +1,31 @@
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{self, sleep};
 use std::time::Duration;
 use std::{io, ops::Not, os::unix::io::AsRawFd};
 
 use bang_core::Config;
-use bang_core::ffi::RtCtx;
+use bang_core::alloc::ManagedReader;
+use bang_core::ffi::{RtCtx, Tex};
 use bang_rt_common::die;
 use bang_rt_common::end::Ender;
 use bang_rt_common::error::OrDie;
 use bang_rt_common::{draw::DrawReceiver, input::InputGatherer};
 
-use crate::draw::{draw, flush, show_cursor};
-use crate::{LOOP_MS, input};
+use crate::draw::{Screen, TexStore, TuiTex, draw, present, restore_screen, setup_screen};
+use crate::parser::{KITTY_DISABLE, KITTY_ENABLE};
+use crate::{LOOP_MS, RtState, input};
 
 unsafe extern "C" {
     safe fn tcgetattr(fd: i32, termios_p: &mut Termios) -> i32;
     safe fn tcsetattr(fd: i32, optional_actions: i32, termios_p: &Termios) -> i32;
     unsafe fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+    unsafe fn ioctl(fd: i32, request: u64, ...) -> i32;
+    unsafe fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    unsafe fn signal(sig: i32, handler: extern "C" fn(i32)) -> extern "C" fn(i32);
 }
 
-// TODO: untested!
 #[cfg(target_os = "linux")]
 #[allow(nonstandard_style)]
 mod platform {
@@ -44,6 +51,11 @@ mod platform {
         pub c_ispeed: speed_t,
         pub c_ospeed: speed_t,
     }
+
+    pub const F_GETFL: i32 = 3;
+    pub const F_SETFL: i32 = 4;
+    pub const O_NONBLOCK: i32 = 0o4000;
+    pub const TIOCGWINSZ: u64 = 0x5413;
 }
 
 #[cfg(target_os = "macos")]
@@ -74,13 +86,82 @@ mod platform {
     pub const F_GETFL: i32 = 3;
     pub const F_SETFL: i32 = 4;
     pub const O_NONBLOCK: i32 = 0x0004;
+    pub const TIOCGWINSZ: u64 = 0x40087468;
 }
 
 use platform::*;
 
-pub struct TerminalMode {
-    original: Termios,
+// Same on Linux and macOS
+const STDOUT_FD: i32 = 1;
+const SIGWINCH: i32 = 28;
+
+#[repr(C)]
+#[derive(Default)]
+struct Winsize {
+    ws_row: u16,
+    ws_col: u16,
+    ws_xpixel: u16,
+    ws_ypixel: u16,
+}
+
+/// Returns the size of the terminal in (columns, rows).
+fn terminal_size() -> Option<(u32, u32)> {
+    let mut size = Winsize::default();
+    // Safety: TIOCGWINSZ writes a `winsize` struct to the pointer
+    let ret = unsafe { ioctl(STDOUT_FD, TIOCGWINSZ, &raw mut size) };
+    (ret == 0 && size.ws_col > 0 && size.ws_row > 0)
+        .then_some((size.ws_col as u32, size.ws_row as u32))
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn sigwinch_handler(_sig: i32) {
+    RESIZED.store(true, Ordering::Release);
+}
+
+/// Writes to stdout bypassing `Stdout` and its lock, which might be held by
+/// the render loop when some other thread panics.
+fn write_raw(mut bytes: &[u8]) {
+    while bytes.is_empty().not() {
+        // Safety: the pointer and the length come from a valid slice
+        let written = unsafe { write(STDOUT_FD, bytes.as_ptr(), bytes.len()) };
+        if written <= 0 {
+            // Stdout might be non-blocking; retry until the terminal is ready
+            if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock {
+                sleep(Duration::from_millis(1));
+                continue;
+            }
+            return; // Nothing to be done about other errors at this point
+        }
+        bytes = &bytes[written as usize..];
+    }
+}
+
+struct TerminalRestore {
     fd: i32,
+    original: Termios,
+    original_flags: i32,
+    restored: AtomicBool,
+}
+
+impl TerminalRestore {
+    fn restore(&self) {
+        if self.restored.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let mut buf = Vec::new();
+        // The kitty keyboard protocol flags are per screen, so pop them first
+        buf.extend_from_slice(KITTY_DISABLE.as_bytes());
+        restore_screen(&mut buf);
+        write_raw(&buf);
+        tcsetattr(self.fd, TCSANOW, &self.original);
+        // Safety: restores the flags the file descriptor had originally
+        unsafe { fcntl(self.fd, F_SETFL, self.original_flags) };
+    }
+}
+
+pub struct TerminalMode {
+    restore: Arc<TerminalRestore>,
 }
 
 impl TerminalMode {
@@ -102,65 +183,126 @@ impl TerminalMode {
             return Err(io::Error::last_os_error());
         }
 
-        unsafe {
+        // Safety: fcntl with F_GETFL and F_SETFL only reads and sets flags
+        let original_flags = unsafe {
             let flags = fcntl(fd, F_GETFL);
             fcntl(fd, F_SETFL, flags | O_NONBLOCK);
-        }
+            flags
+        };
+
+        let restore = Arc::new(TerminalRestore {
+            fd,
+            original,
+            original_flags,
+            restored: AtomicBool::new(false),
+        });
 
-        Ok(TerminalMode { original, fd })
+        // A panic message printed to the alternate screen would vanish when
+        // leaving it, so restore the terminal before the message is printed.
+        let hook_restore = restore.clone();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook_restore.restore();
+            default_hook(info);
+        }));
+
+        // Safety: the handler only stores to an atomic, which is signal safe
+        unsafe { signal(SIGWINCH, sigwinch_handler) };
+
+        let mut buf = Vec::new();
+        setup_screen(&mut buf);
+        // The reply to the flag query tells the input thread whether we get release events
+        buf.extend_from_slice(KITTY_ENABLE.as_bytes());
+        write_raw(&buf);
+
+        Ok(TerminalMode { restore })
+    }
+
+    pub fn restore(&self) {
+        self.restore.restore();
     }
 }
 
 impl Drop for TerminalMode {
     fn drop(&mut self) {
-        tcsetattr(self.fd, TCSANOW, &self.original);
+        self.restore();
     }
 }
+
 pub struct Window<'l> {
     input_gatherer: InputGatherer<'l>,
     draw_receiver: DrawReceiver<'l>,
     ender: &'l Ender,
-    _terminal_mode: TerminalMode,
+    textures: ManagedReader<TuiTex, Tex>,
+    tex_store: TexStore,
+    screen: Screen,
+    terminal_mode: TerminalMode,
 }
 
 impl<'l> Window<'l> {
     pub fn init(
-        _rt_ctx: &mut RtCtx,
+        rt_ctx: &mut RtCtx,
         input_gatherer: InputGatherer<'l>,
         draw_receiver: DrawReceiver<'l>,
         ender: &'l Ender,
-        _config: &'l Config,
+        config: &'l Config,
     ) -> Self {
-        let _terminal_mode = TerminalMode::new().or_(die!("Failed to initialize terminal mode"));
+        let rt = RtState::unwrap_from(rt_ctx);
+        let terminal_mode = TerminalMode::new().or_(die!("Failed to initialize terminal mode"));
+        let grid = terminal_size().unwrap_or_else(|| Screen::natural_grid(config));
         Window {
             input_gatherer,
             draw_receiver,
             ender,
-            _terminal_mode,
+            textures: rt.textures.reader(),
+            tex_store: rt.tex_store.clone(),
+            screen: Screen::new(config, grid),
+            terminal_mode,
         }
     }
 
     pub fn run(&mut self) {
         let gatherer = &mut self.input_gatherer;
         let draw_receiver = &mut self.draw_receiver;
+        let textures = &self.textures;
+        let tex_store = &self.tex_store;
+        let screen = &mut self.screen;
         thread::scope(|s| {
             s.spawn(|| input::gather(self.ender, gatherer));
-            Self::render_loop(self.ender, draw_receiver);
+            Self::render_loop(self.ender, draw_receiver, textures, tex_store, screen);
         });
+        self.terminal_mode.restore();
     }
 
-    fn render_loop(ender: &'l Ender, draw_receiver: &mut DrawReceiver<'l>) {
+    fn render_loop(
+        ender: &'l Ender,
+        draw_receiver: &mut DrawReceiver<'l>,
+        textures: &ManagedReader<TuiTex, Tex>,
+        tex_store: &TexStore,
+        screen: &mut Screen,
+    ) {
         let mut buf = Vec::new();
         let mut output_stream = std::io::stdout().lock();
 
         while ender.should_end().not() {
+            let resized = RESIZED.swap(false, Ordering::Acquire);
+            if resized && let Some(grid) = terminal_size() {
+                screen.resize(grid);
+            }
             if draw_receiver.has_fresh() {
                 let frame = draw_receiver.get_fresh();
-                draw(frame, &mut output_stream, &mut buf);
+                draw(
+                    frame,
+                    textures,
+                    tex_store,
+                    screen,
+                    &mut output_stream,
+                    &mut buf,
+                );
+            } else if resized {
+                present(screen, &mut output_stream, &mut buf);
             }
             sleep(Duration::from_millis(LOOP_MS));
         }
-        show_cursor(&mut buf);
-        flush(&mut buf, &mut output_stream);
     }
 }
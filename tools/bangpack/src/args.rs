@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use tool_common::{Parser, Result, input};
+
+pub const USAGE: &str = "\
+Usage:
+  bangpack [OPTIONS] -o PACK INPUT...
+  bangpack --list PACK
+
+Packs asset files into a single pack file. Ship it next to the executable as
+assets.bpak, and the runtime reads the assets from it, wherever the game is
+run from. An input can be a file or a directory of them, packed recursively.
+The assets are looked up by their paths as given, relative to the working
+directory, e.g. assets/paltex/bubu.paltex.
+
+Options:
+  -o, --output PATH  The pack file to write
+  --store            Stores the files as they are, without compressing them
+  --list PACK        Lists the entries of a pack
+  -h, --help         Prints this help
+";
+
+pub struct Args {
+    pub help: bool,
+    pub output: Option<PathBuf>,
+    pub compress: bool,
+    pub list: Option<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            help: false,
+            output: None,
+            compress: true,
+            list: None,
+            inputs: Vec::new(),
+        };
+
+        let mut args = Parser::new(args);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "-o" | "--output" => parsed.output = Some(args.value(&arg)?.into()),
+                "--store" => parsed.compress = false,
+                "--list" => parsed.list = Some(args.value(&arg)?.into()),
+                _ => parsed.inputs.push(input(arg)?),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs::{read, read_dir, write},
+    ops::Not,
+    path::{Component, Path},
+    process::ExitCode,
+};
+
+use bang_rt_common::pack::{Pack, PackEntry, write_pack};
+
+use tool_common::Result;
+
+use args::{Args, USAGE};
+
+mod args;
+
+/// The path an asset is looked up with: relative, with `/` separators
+fn asset_path(path: &Path) -> Result<String> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(
+                name.to_str()
+                    .ok_or_else(|| format!("The path {path:?} isn't valid UTF-8"))?,
+            ),
+            Component::CurDir => {}
+            _ => {
+                return Err(format!(
+                    "The path {path:?} must be relative and inside the working directory"
+                ));
+            }
+        }
+    }
+    Ok(components.join("/"))
+}
+
+/// Collects the files, walking directories recursively.
+fn collect_files(path: &Path, compress: bool, entries: &mut Vec<PackEntry>) -> Result<()> {
+    if path.is_dir().not() {
+        let data = read(path).map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+        entries.push(PackEntry {
+            path: asset_path(path)?,
+            data,
+            compress,
+        });
+        return Ok(());
+    }
+
+    let mut paths = Vec::new();
+    for entry in read_dir(path).map_err(|e| format!("Failed to read directory {path:?}: {e}"))? {
+        let entry = entry.map_err(|e| format!("Failed to read directory {path:?}: {e}"))?;
+        // Skips hidden files, such as .DS_Store
+        if entry.file_name().to_string_lossy().starts_with('.').not() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    for path in paths {
+        collect_files(&path, compress, entries)?;
+    }
+    Ok(())
+}
+
+fn make_pack(args: &Args) -> Result<()> {
+    let Some(output) = &args.output else {
+        return Err("Give the pack file to write with -o".to_owned());
+    };
+    if args.inputs.is_empty() {
+        return Err("No input files".to_owned());
+    }
+
+    let mut entries = Vec::new();
+    for input in &args.inputs {
+        collect_files(input, args.compress, &mut entries)?;
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0].path == pair[1].path) {
+        return Err(format!("The file {:?} is given twice", pair[0].path));
+    }
+
+    let mut encoded_output = Vec::new();
+    write_pack(&entries, &mut encoded_output);
+    list(&encoded_output)?;
+
+    eprintln!("Writing {output:?}.");
+    write(output, &encoded_output).map_err(|e| format!("Failed to write {output:?}: {e}"))
+}
+
+/// Lists the entries of a pack, and checks that they can be read.
+fn list(bytes: &[u8]) -> Result<()> {
+    let pack = Pack::new(bytes).map_err(|e| e.to_string())?;
+    println!("{:<40} {:>9} {:>9}", "path", "size", "packed");
+    let mut total_len = 0;
+    for entry in pack.entries() {
+        pack.read(entry).map_err(|e| e.to_string())?;
+        let how = if entry.compressed {
+            "compressed"
+        } else {
+            "stored"
+        };
+        println!(
+            "{:<40} {:>9} {:>9}  {how}",
+            entry.path, entry.len, entry.stored_len
+        );
+        total_len += entry.len;
+    }
+    println!(
+        "{} entries, {total_len} bytes, {} bytes packed",
+        pack.entries().len(),
+        bytes.len()
+    );
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<()> {
+    if args.help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    match &args.list {
+        Some(path) => {
+            let bytes = read(path).map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+            list(&bytes).map_err(|e| format!("{path:?}: {e}"))
+        }
+        None => make_pack(args),
+    }
+}
+
+fn main() -> ExitCode {
+    tool_common::main("bangpack", Args::parse, run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_path() {
+        assert_eq!(
+            asset_path(Path::new("assets/paltex/bubu.paltex")),
+            Ok("assets/paltex/bubu.paltex".to_owned())
+        );
+        assert_eq!(
+            asset_path(Path::new("./assets//a.txt")),
+            Ok("assets/a.txt".to_owned())
+        );
+        assert!(asset_path(Path::new("/assets/a.txt")).is_err());
+        assert!(asset_path(Path::new("../assets/a.txt")).is_err());
+        assert!(asset_path(Path::new("assets/../a.txt")).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{ops::Not, path::PathBuf};
+
+use tool_common::{Parser, Result, input};
+
+pub const USAGE: &str = "\
+Usage: paltex2ascii [OPTIONS] [INPUT]
+
+Prints a paltex file in the terminal with 24-bit colors, packing two rows of
+pixels into each line with half blocks. Without INPUT, reads from stdin.
+
+Options:
+  --blocks      Prints each pixel as two full blocks instead
+  --scale N     Scales the image up N times (1-16, default: 1)
+  --info        Prints the header and the compression stats instead of the
+                image
+  --png PATH    Writes the image into a PNG file instead of printing it. The
+                frames of an animation are laid side by side, as a sprite
+                strip for png2paltex --strip.
+  -h, --help    Prints this help
+";
+
+const MAX_SCALE: usize = 16;
+
+pub struct Args {
+    pub help: bool,
+    pub blocks: bool,
+    pub scale: usize,
+    pub info: bool,
+    pub png: Option<PathBuf>,
+    pub input: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            help: false,
+            blocks: false,
+            scale: 1,
+            info: false,
+            png: None,
+            input: None,
+        };
+
+        let mut args = Parser::new(args);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--blocks" => parsed.blocks = true,
+                "--scale" => {
+                    parsed.scale = args.number(&arg)?;
+                    if (1..=MAX_SCALE).contains(&parsed.scale).not() {
+                        return Err(format!(
+                            "Invalid value of {arg}: {} isn't between 1 and {MAX_SCALE}",
+                            parsed.scale
+                        ));
+                    }
+                }
+                "--info" => parsed.info = true,
+                "--png" => parsed.png = Some(args.value(&arg)?.into()),
+                _ => {
+                    let input = input(arg)?;
+                    if parsed.input.is_some() {
+                        return Err(format!("More than one input: {input:?}"));
+                    }
+                    parsed.input = Some(input);
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = parse(&["--blocks", "--scale", "3", "--png", "out.png", "in.paltex"]).unwrap();
+        assert!(args.blocks);
+        assert_eq!(args.scale, 3);
+        assert_eq!(args.png, Some(PathBuf::from("out.png")));
+        assert_eq!(args.input, Some(PathBuf::from("in.paltex")));
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.scale, 1);
+        assert!(args.blocks.not());
+    }
+
+    #[test]
+    fn test_parse_scale_errors() {
+        assert_eq!(
+            parse(&["--scale", "0"]).err(),
+            Some("Invalid value of --scale: 0 isn't between 1 and 16".to_owned())
+        );
+        assert_eq!(
+            parse(&["--scale", "17"]).err(),
+            Some("Invalid value of --scale: 17 isn't between 1 and 16".to_owned())
+        );
+        assert!(parse(&["--scale", "two"]).is_err());
+        assert!(parse(&["--scale"]).is_err());
+    }
+}
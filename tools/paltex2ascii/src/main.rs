@@ -1,52 +1,385 @@
 use std::{
-    env::args,
-    fs::read,
-    io::{Read, Write, stdin, stdout},
+    fs::{File, read},
+    io::{BufWriter, Read, Write, stdin, stdout},
+    iter::repeat_n,
+    path::Path,
+    process::ExitCode,
 };
 
-use bang_rt_common::{die, error::OrDie};
+use paltex::{Color, PalTexAnim};
+
+use tool_common::Result;
+
+use args::{Args, USAGE};
+
+mod args;
 
 const CSI: &str = "\x1b[";
 
-fn color_block(mut out: impl Write, ansi_color: u8) {
-    write!(out, "{CSI}{}m██", 30 + ansi_color % 8).expect("Failed to write to stdout");
+fn read_input(args: &Args) -> Result<Vec<u8>> {
+    match &args.input {
+        Some(path) => read(path).map_err(|e| format!("Failed to read {path:?}: {e}")),
+        None => {
+            let mut input = Vec::new();
+            stdin()
+                .lock()
+                .read_to_end(&mut input)
+                .map_err(|e| format!("Failed to read from stdin: {e}"))?;
+            Ok(input)
+        }
+    }
+}
+
+/// Scales rows of `width` pixels up `scale` times in both directions
+fn scale_up(data: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    data.chunks_exact(width)
+        .flat_map(|row| {
+            let row: Vec<u8> = row.iter().flat_map(|&idx| repeat_n(idx, scale)).collect();
+            repeat_n(row, scale).flatten()
+        })
+        .collect()
+}
+
+/// The escape sequence that sets the foreground and the background colors.
+/// Transparent ones are left as the terminal defaults.
+fn style(fg: Color, bg: Color) -> String {
+    let mut style = format!("{CSI}0m");
+    if fg.a != 0 {
+        style += &format!("{CSI}38;2;{};{};{}m", fg.r, fg.g, fg.b);
+    }
+    if bg.a != 0 {
+        style += &format!("{CSI}48;2;{};{};{}m", bg.r, bg.g, bg.b);
+    }
+    style
+}
+
+/// Prints a frame either as two rows of pixels per line with half blocks,
+/// or as two full blocks per pixel.
+fn print_frame(out: &mut String, palette: &[Color], frame: &[u8], width: usize, blocks: bool) {
+    let transparent = Color::TRANSPARENT;
+    let rows_per_line = if blocks { 1 } else { 2 };
+    for lines in frame.chunks(width * rows_per_line) {
+        let (top, bottom) = lines.split_at(width);
+        let mut current = String::new();
+        for (x, &top) in top.iter().enumerate() {
+            let top = palette[top as usize];
+            let bottom = bottom
+                .get(x)
+                .map_or(transparent, |&bottom| palette[bottom as usize]);
+            let (cell_style, cell) = match (top.a, bottom.a) {
+                (0, _) if blocks => (style(transparent, transparent), "  "),
+                _ if blocks => (style(top, transparent), "██"),
+                (0, 0) => (style(transparent, transparent), " "),
+                (0, _) => (style(bottom, transparent), "▄"),
+                _ => (style(top, bottom), "▀"),
+            };
+            // Consecutive cells of the same colors share the escape sequence
+            if cell_style != current {
+                *out += &cell_style;
+                current = cell_style;
+            }
+            *out += cell;
+        }
+        *out += &format!("{CSI}0m\n");
+    }
 }
 
-fn main() {
-    let input = if let Some(path) = args().nth(1) {
-        read(&path).or_(die!("Failed to read from file {:?}", path))
+fn print_image(anim: &PalTexAnim, args: &Args) -> String {
+    let mut out = format!(
+        "Palette: (length: {} RGBA colors + 1 implicit transparent)\n",
+        anim.palette.len() - 1
+    );
+    for (i, &color) in anim.palette.iter().enumerate() {
+        out += &format!(
+            "{}{}{CSI}0m {i:>3}: {:?}\n",
+            style(color, Color::TRANSPARENT),
+            if color.a == 0 { "  " } else { "██" },
+            color.to_rgba_u8()
+        );
+    }
+
+    let is_animation = anim.frame_count() > 1;
+    if is_animation {
+        out += &format!(
+            "Animation: {} frames, loops back to frame {:?}\n",
+            anim.frame_count(),
+            anim.loop_start
+        );
+    }
+
+    let width = anim.width as usize;
+    for (i, frame) in anim.frames().enumerate() {
+        if is_animation {
+            out += &format!("Frame {i}: {} ticks\n", anim.durations[i]);
+        }
+        let frame = scale_up(frame, width, args.scale);
+        print_frame(
+            &mut out,
+            &anim.palette,
+            &frame,
+            width * args.scale,
+            args.blocks,
+        );
+    }
+    out
+}
+
+/// The header and the compression stats
+fn print_info(input: &[u8], anim: &PalTexAnim) -> Result<String> {
+    let info = paltex::decode_info(input).map_err(|e| format!("Failed to decode: {e}"))?;
+    let pixels = info.data_len();
+    let percent_of = |len: usize| 100.0 * input.len() as f64 / len.max(1) as f64;
+
+    let mut out = format!("Format version: {}\n", info.version);
+    out += &format!("Op set:         {:?}\n", info.op_set);
+    out += &format!("Size:           {}x{} pixels\n", info.width, info.height);
+    out += &format!(
+        "Palette:        {} RGBA colors + 1 implicit transparent\n",
+        info.palette_len - 1
+    );
+    if info.is_animation {
+        let looping = match anim.loop_start {
+            Some(loop_start) => format!("loops back to frame {loop_start}"),
+            None => "stops at the last frame".to_owned(),
+        };
+        out += &format!("Frames:         {}, {looping}\n", info.frame_count);
+        out += &format!("Durations:      {:?} ticks\n", anim.durations);
+    }
+    out += &format!(
+        "Encoded size:   {} bytes, {:.2} bits per pixel\n",
+        input.len(),
+        input.len() as f64 * 8.0 / pixels.max(1) as f64
+    );
+    out += &format!(
+        "Decoded size:   {pixels} bytes as indices, {} bytes as RGBA\n",
+        pixels * 4
+    );
+    out += &format!(
+        "Compression:    {:.1}% of the indices, {:.1}% of RGBA\n",
+        percent_of(pixels),
+        percent_of(pixels * 4)
+    );
+    Ok(out)
+}
+
+/// Writes the frames side by side as an RGBA PNG image.
+fn write_png(path: &Path, anim: &PalTexAnim, scale: usize) -> Result<()> {
+    let width = anim.width as usize * scale;
+    let frames: Vec<_> = anim
+        .frames()
+        .map(|frame| scale_up(frame, anim.width as usize, scale))
+        .collect();
+    let height = anim.height as usize * scale;
+
+    let mut rgba = Vec::with_capacity(width * height * frames.len() * 4);
+    for y in 0..height {
+        for frame in &frames {
+            let row = &frame[y * width..(y + 1) * width];
+            rgba.extend(
+                row.iter()
+                    .flat_map(|&idx| anim.palette[idx as usize].to_rgba_u8()),
+            );
+        }
+    }
+
+    eprintln!("Writing {path:?}.");
+    let file = File::create(path).map_err(|e| format!("Failed to create {path:?}: {e}"))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        (width * frames.len()) as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgba))
+        .map_err(|e| format!("Failed to write {path:?}: {e}"))
+}
+
+fn run(args: &Args) -> Result<()> {
+    if args.help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    let input = read_input(args)?;
+    let anim = paltex::decode_anim(&input).map_err(|e| format!("Failed to decode: {e}"))?;
+
+    let out = if args.info {
+        print_info(&input, &anim)?
+    } else if args.png.is_none() {
+        print_image(&anim, args)
     } else {
-        let mut input = Vec::new();
-        stdin()
-            .lock()
-            .read_to_end(&mut input)
-            .expect("Failed to read from stdin.");
-        input
+        String::new()
     };
-    let paltex = paltex::decode(&input).expect("Invalid paltex input?");
+    stdout()
+        .lock()
+        .write_all(out.as_bytes())
+        .map_err(|e| format!("Failed to write to stdout: {e}"))?;
 
-    let mut stdout = stdout().lock();
+    if let Some(path) = &args.png {
+        write_png(path, &anim, args.scale)?;
+    }
+    Ok(())
+}
 
-    writeln!(
-        stdout,
-        "Palette: (length: {} RGBA colors + 1 implicit transparent)",
-        paltex.palette.len() - 1
-    )
-    .expect("Failed to write to stdout");
+fn main() -> ExitCode {
+    tool_common::main("paltex2ascii", Args::parse, run)
+}
 
-    for (i, color) in paltex.palette.iter().enumerate() {
-        color_block(&mut stdout, i as u8);
-        writeln!(stdout, "{CSI}0m Color {:?}", color.to_rgba_u8())
-            .expect("Failed to write to stdout");
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_file;
+
+    use super::*;
+
+    const RED: Color = Color::from_rgba_u8([255, 0, 0, 255]);
+    const BLUE: Color = Color::from_rgba_u8([0, 0, 255, 255]);
+    const PALETTE: [Color; 3] = [Color::TRANSPARENT, RED, BLUE];
+
+    fn parse(args: &[&str]) -> Args {
+        match Args::parse(args.iter().map(|&arg| arg.to_owned())) {
+            Ok(args) => args,
+            Err(e) => panic!("{e}"),
+        }
     }
 
-    let mut row = Vec::new();
-    for chunk in paltex.data.chunks(paltex.width as usize) {
-        row.clear();
-        for &color_idx in chunk {
-            color_block(&mut row, color_idx);
+    /// A still image of `width` pixels per row
+    fn image(width: u32, data: Vec<u8>) -> PalTexAnim {
+        PalTexAnim {
+            width,
+            height: data.len() as u32 / width,
+            palette: PALETTE.to_vec(),
+            data,
+            durations: vec![1],
+            loop_start: None,
         }
-        row.push(b'\n');
-        stdout.write_all(&row).expect("Failed to write to stdout");
+    }
+
+    fn frame_text(palette: &[Color], frame: &[u8], width: usize, blocks: bool) -> String {
+        let mut out = String::new();
+        print_frame(&mut out, palette, frame, width, blocks);
+        out
+    }
+
+    #[test]
+    fn test_style() {
+        assert_eq!(
+            style(RED, BLUE),
+            "\x1b[0m\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m"
+        );
+        assert_eq!(style(Color::TRANSPARENT, BLUE), "\x1b[0m\x1b[48;2;0;0;255m");
+        assert_eq!(style(Color::TRANSPARENT, Color::TRANSPARENT), "\x1b[0m");
+    }
+
+    #[test]
+    fn test_scale_up() {
+        assert_eq!(scale_up(&[1, 2, 0, 1], 2, 1), [1, 2, 0, 1]);
+        assert_eq!(
+            scale_up(&[1, 2, 0, 1], 2, 2),
+            [
+                1, 1, 2, 2, //
+                1, 1, 2, 2, //
+                0, 0, 1, 1, //
+                0, 0, 1, 1, //
+            ]
+        );
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        let transparent = Color::TRANSPARENT;
+        // The last line of an odd height has only the top row
+        let frame = [
+            1, 0, //
+            2, 1, //
+            0, 2, //
+        ];
+        let expected = format!(
+            "{}▀{}▄\x1b[0m\n{} {}▀\x1b[0m\n",
+            style(RED, BLUE),
+            style(RED, transparent),
+            style(transparent, transparent),
+            style(BLUE, transparent),
+        );
+        assert_eq!(frame_text(&PALETTE, &frame, 2, false), expected);
+
+        // Consecutive cells of the same colors share the escape sequence
+        let expected = format!("{}▀▀▀\x1b[0m\n", style(RED, BLUE));
+        assert_eq!(
+            frame_text(&PALETTE, &[1, 1, 1, 2, 2, 2], 3, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let transparent = Color::TRANSPARENT;
+        let expected = format!(
+            "{}██{}  \x1b[0m\n{}  {}██\x1b[0m\n",
+            style(RED, transparent),
+            style(transparent, transparent),
+            style(transparent, transparent),
+            style(BLUE, transparent),
+        );
+        assert_eq!(frame_text(&PALETTE, &[1, 0, 0, 2], 2, true), expected);
+    }
+
+    #[test]
+    fn test_print_image_scale() {
+        // A red pixel above a blue one, scaled up to 2x4
+        let anim = image(1, vec![1, 2]);
+        let out = print_image(&anim, &parse(&["--scale", "2"]));
+        let expected = format!(
+            "{}▀▀\x1b[0m\n{}▀▀\x1b[0m\n",
+            style(RED, RED),
+            style(BLUE, BLUE)
+        );
+        assert!(out.ends_with(&expected), "{out:?}");
+
+        // Unscaled, both pixels fit in one half block after the palette
+        let out = print_image(&anim, &parse(&[]));
+        let expected = format!("]\n{}▀\x1b[0m\n", style(RED, BLUE));
+        assert!(out.ends_with(&expected), "{out:?}");
+    }
+
+    #[test]
+    fn test_write_png() {
+        // Two 1x2 frames, laid side by side and scaled up to 4x4
+        let anim = PalTexAnim {
+            width: 1,
+            height: 2,
+            palette: PALETTE.to_vec(),
+            data: vec![1, 0, 2, 1],
+            durations: vec![1, 1],
+            loop_start: Some(0),
+        };
+        let path =
+            std::env::temp_dir().join(format!("paltex2ascii_test_{}.png", std::process::id()));
+        write_png(&path, &anim, 2).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (4, 4));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        let pixels: Vec<Color> = buf
+            .chunks_exact(4)
+            .map(|rgba| Color::from_rgba_u8(rgba.try_into().unwrap()))
+            .collect();
+        let (t, r, b) = (Color::TRANSPARENT, RED, BLUE);
+        assert_eq!(
+            pixels,
+            [
+                r, r, b, b, //
+                r, r, b, b, //
+                t, t, r, r, //
+                t, t, r, r, //
+            ]
+        );
     }
 }
@@ -0,0 +1,162 @@
+//! Building animations out of numbered PNG files or sprite strips:
+//!
+//! - `png2paltex --anim [OPTIONS] walk_0.png walk_1.png ...` takes the frames
+//!   in the order of the numbers in the file names, and writes `walk.paltex`.
+//! - `png2paltex --strip N [OPTIONS] walk.png` splits the image horizontally
+//!   into N frames, and writes `walk.paltex`.
+//!
+//! The frames share the palette, and are cropped to the union of their
+//! opaque pixels, so that they stay aligned.
+
+use std::{ops::Not, path::Path};
+
+use paltex::{Color, PalTex, PalTexAnim};
+
+use crate::{
+    Palettizer, Result,
+    args::{Args, Mode},
+    output_path, palettize_frames, read_png_file, write_output,
+};
+
+/// The number at the end of the file stem, and the stem without it and
+/// the separator before it
+fn frame_number(path: &Path) -> Result<(u64, String)> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = stem[name.len()..]
+        .parse()
+        .map_err(|_| format!("No frame number at the end of the file name: {path:?}"))?;
+    let name = name.trim_end_matches(['_', '-', '.', ' ']);
+    Ok((number, name.to_owned()))
+}
+
+/// Splits a horizontal strip of `n` frames.
+fn split_strip(width: usize, pixels: &[Color], n: usize) -> Result<(usize, Vec<Vec<Color>>)> {
+    if n == 0 || width.is_multiple_of(n).not() {
+        return Err(format!("The width {width} isn't divisible into {n} frames"));
+    }
+    let frame_width = width / n;
+    let frames = (0..n)
+        .map(|i| {
+            pixels
+                .chunks_exact(width)
+                .flat_map(|row| &row[i * frame_width..(i + 1) * frame_width])
+                .copied()
+                .collect()
+        })
+        .collect();
+    Ok((frame_width, frames))
+}
+
+/// Reads the frames, returning the name of the animation, the width of the
+/// frames and their pixels.
+fn read_frames(args: &Args) -> Result<(String, usize, Vec<Vec<Color>>)> {
+    if let Mode::Strip(n) = args.mode {
+        let [path] = args.inputs.as_slice() else {
+            return Err("Give a single sprite strip".to_owned());
+        };
+        let (width, pixels) = read_png_file(path)?;
+        let (frame_width, frames) =
+            split_strip(width, &pixels, n).map_err(|e| format!("{path:?}: {e}"))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        return Ok((name.into_owned(), frame_width, frames));
+    }
+
+    let mut inputs = args
+        .inputs
+        .iter()
+        .map(|path| Ok((frame_number(path)?, path)))
+        .collect::<Result<Vec<_>>>()?;
+    inputs.sort_by_key(|((number, _), _)| *number);
+    let name = inputs[0].0.1.clone();
+    let mut width = None;
+    let mut frames: Vec<Vec<Color>> = Vec::new();
+    for (_, path) in inputs {
+        eprintln!("Reading frame {path:?}.");
+        let (frame_width, pixels) = read_png_file(path)?;
+        if *width.get_or_insert(frame_width) != frame_width
+            || frames
+                .first()
+                .is_some_and(|first| first.len() != pixels.len())
+        {
+            return Err(format!("The frames must be of the same size: {path:?}"));
+        }
+        frames.push(pixels);
+    }
+    // UNREACHABLE: there's at least one input
+    Ok((name, width.expect("UNREACHABLE: has inputs"), frames))
+}
+
+pub fn convert_anim(args: &Args, mut palettizer: Palettizer) -> Result<()> {
+    if args.inputs.is_empty() {
+        return Err("No input files".to_owned());
+    }
+
+    let (name, width, frames) = read_frames(args)?;
+    if let Some(loop_start) = args.loop_start
+        && loop_start as usize >= frames.len()
+    {
+        return Err(format!(
+            "Can't loop back to frame {loop_start} of {} frames",
+            frames.len()
+        ));
+    }
+    let (width, height, frames) = palettize_frames(&frames, width, &mut palettizer, args)?;
+    eprintln!("Frames: {}", frames.len());
+
+    let frames: Vec<_> = frames
+        .into_iter()
+        .map(|data| PalTex {
+            width: width as u32,
+            height: height as u32,
+            palette: palettizer.palette.clone(),
+            data,
+        })
+        .collect();
+    let durations = vec![args.ticks; frames.len()];
+    let anim = PalTexAnim::from_frames(&frames, durations, args.loop_start);
+
+    let mut encoded_output = Vec::new();
+    paltex::encode_anim(&anim, &mut encoded_output);
+    eprintln!("Encoded size: {} bytes", encoded_output.len());
+
+    let output = output_path(args, &args.inputs[0], &name, true)?;
+    write_output(&output, &encoded_output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_number() {
+        assert_eq!(
+            frame_number(Path::new("assets/walk_12.png")),
+            Ok((12, "walk".to_owned()))
+        );
+        assert_eq!(
+            frame_number(Path::new("walk-003.png")),
+            Ok((3, "walk".to_owned()))
+        );
+        assert_eq!(frame_number(Path::new("7.png")), Ok((7, String::new())));
+        assert!(frame_number(Path::new("walk.png")).is_err());
+        assert!(frame_number(Path::new("walk_1a.png")).is_err());
+    }
+
+    #[test]
+    fn test_split_strip() {
+        let pixels: Vec<_> = (0..12)
+            .map(|i| Color::from_rgba_u8([i, 0, 0, 255]))
+            .collect();
+        let (frame_width, frames) = split_strip(6, &pixels, 3).unwrap();
+        assert_eq!(frame_width, 2);
+        let reds: Vec<Vec<u8>> = frames
+            .iter()
+            .map(|frame| frame.iter().map(|c| c.r).collect())
+            .collect();
+        assert_eq!(reds, [[0, 1, 6, 7], [2, 3, 8, 9], [4, 5, 10, 11]]);
+
+        assert!(split_strip(6, &pixels, 4).is_err());
+        assert!(split_strip(6, &pixels, 0).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::{ops::Not, path::PathBuf};
+
+use paltex::MAX_PAL_LEN;
+
+use tool_common::{Parser, Result, input};
+
+pub const USAGE: &str = "\
+Usage:
+  png2paltex [OPTIONS] [INPUT...]
+  png2paltex --anim [OPTIONS] FRAME...
+  png2paltex --strip N [OPTIONS] INPUT
+  png2paltex --make-palette -o PALETTE [OPTIONS] INPUT...
+  png2paltex --report [DIR]
+
+Converts PNG images to paltex. An input can be a PNG file or a directory of
+them. Without inputs, converts from stdin to stdout.
+
+Modes:
+  --anim             Builds an animation out of numbered frames, taken in the
+                     order of the numbers at the end of the file names
+  --strip N          Builds an animation by splitting a horizontal sprite strip
+                     into N frames
+  --make-palette     Writes a palette file out of the colors of the inputs,
+                     to be shared with --palette
+  --report [DIR]     Prints the encoded sizes of the PNG files in DIR
+                     (default: assets/png)
+
+Options:
+  -o, --output PATH  Output file, or directory to write the outputs into.
+                     Default: next to each input, or stdout for stdin.
+  --no-crop          Keeps the transparent borders, so that the images stay
+                     aligned with the originals
+  --palette FILE     Uses a shared palette: that of a palette file or a
+                     paltex file, or the colors of a PNG file in the order
+                     they first appear. Every output gets the same palette,
+                     and other colors are an error.
+  --nearest          Maps the colors that aren't in --palette to the nearest
+                     ones in it
+  --quantize N       Reduces the colors of each output to at most N (1-255)
+  --ticks N          Duration of each animation frame in logic ticks
+                     (default: 5)
+  --loop N           The animation frame to loop back to after the last one
+                     (default: 0)
+  --no-loop          Stops the animation at the last frame
+  -h, --help         Prints this help
+";
+
+const DEFAULT_TICKS: u16 = 5;
+
+pub enum Mode {
+    Convert,
+    Anim,
+    Strip(usize),
+    MakePalette,
+    Report,
+    Help,
+}
+
+pub struct Args {
+    pub mode: Mode,
+    pub output: Option<PathBuf>,
+    pub crop: bool,
+    pub palette: Option<PathBuf>,
+    pub nearest: bool,
+    pub quantize: Option<usize>,
+    pub ticks: u16,
+    pub loop_start: Option<u16>,
+    pub inputs: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            mode: Mode::Convert,
+            output: None,
+            crop: true,
+            palette: None,
+            nearest: false,
+            quantize: None,
+            ticks: DEFAULT_TICKS,
+            loop_start: Some(0),
+            inputs: Vec::new(),
+        };
+
+        let mut args = Parser::new(args);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.mode = Mode::Help,
+                "--anim" => parsed.mode = Mode::Anim,
+                "--strip" => parsed.mode = Mode::Strip(args.number(&arg)?),
+                "--make-palette" => parsed.mode = Mode::MakePalette,
+                "--report" => parsed.mode = Mode::Report,
+                "-o" | "--output" => parsed.output = Some(args.value(&arg)?.into()),
+                "--no-crop" => parsed.crop = false,
+                "--palette" => parsed.palette = Some(args.value(&arg)?.into()),
+                "--nearest" => parsed.nearest = true,
+                "--quantize" => parsed.quantize = Some(args.number(&arg)?),
+                "--ticks" => parsed.ticks = args.number(&arg)?,
+                "--loop" => parsed.loop_start = Some(args.number(&arg)?),
+                "--no-loop" => parsed.loop_start = None,
+                _ => parsed.inputs.push(input(arg)?),
+            }
+        }
+
+        if parsed.nearest && parsed.palette.is_none() {
+            return Err("--nearest needs --palette".to_owned());
+        }
+        if let Some(max_colors) = parsed.quantize {
+            if parsed.palette.is_some() {
+                return Err("--quantize and --palette can't be used together".to_owned());
+            }
+            if (1..=MAX_PAL_LEN as usize).contains(&max_colors).not() {
+                return Err(format!(
+                    "Invalid value of --quantize: {max_colors} isn't between 1 and {MAX_PAL_LEN}"
+                ));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = parse(&["--strip", "4", "--no-loop", "-o", "out", "walk.png"]).unwrap();
+        assert!(matches!(args.mode, Mode::Strip(4)));
+        assert_eq!(args.loop_start, None);
+        assert_eq!(args.output, Some(PathBuf::from("out")));
+        assert_eq!(args.inputs, [PathBuf::from("walk.png")]);
+
+        let args = parse(&["--palette", "shared.ppal", "--nearest"]).unwrap();
+        assert_eq!(args.palette, Some(PathBuf::from("shared.ppal")));
+        assert!(args.nearest);
+        assert_eq!(parse(&["--quantize", "255"]).unwrap().quantize, Some(255));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&["--nearest"]).err(),
+            Some("--nearest needs --palette".to_owned())
+        );
+        assert_eq!(
+            parse(&["--quantize", "8", "--palette", "shared.ppal"]).err(),
+            Some("--quantize and --palette can't be used together".to_owned())
+        );
+        assert!(parse(&["--quantize", "0"]).is_err());
+        assert!(parse(&["--quantize", "256"]).is_err());
+        assert!(parse(&["--ticks", "-1"]).is_err());
+        assert!(parse(&["--loop"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
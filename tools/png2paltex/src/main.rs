@@ -1,153 +1,450 @@
 use std::{
-    collections::HashMap,
-    env::args,
     ffi::OsStr,
-    fs::{read_dir, write},
+    fs::{File, read_dir, write},
     io::{Read, Write, stdin, stdout},
-    ops::Range,
-    path::Path,
+    ops::{Not, Range},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
 };
 
-use png::ColorType;
+use png::{ColorType, Transformations};
 
-use paltex::{Color, PalTex};
+use paltex::{Color, IndexWidth, OpSet, PalTex};
 
-fn from_png(input: impl Read) -> (PalTex, Range<usize>) {
-    let decoder = png::Decoder::new(input);
-    let mut reader = decoder.read_info().expect("Failed to read PNG info");
+use tool_common::Result;
+
+use args::{Args, Mode, USAGE};
+use palette::{Palettizer, Unknown, quantize, read_palette};
+
+mod anim;
+mod args;
+mod palette;
+
+/// Reads a PNG image of any color type, returning its width and pixels.
+fn read_png(input: impl Read) -> Result<(usize, Vec<Color>)> {
+    let mut decoder = png::Decoder::new(input);
+    // Indexed, grey and 16-bit images are expanded to 8-bit grey or RGB, with
+    // alpha if they have transparency.
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Failed to read PNG info: {e}"))?;
     let mut buf = vec![0; reader.output_buffer_size()];
     let info = reader
         .next_frame(&mut buf)
-        .expect("Failed to read PNG image data");
+        .map_err(|e| format!("Failed to read PNG image data: {e}"))?;
     let width = info.width as usize;
-    reader.finish().expect("Failed to finish reading PNG");
+    reader
+        .finish()
+        .map_err(|e| format!("Failed to finish reading PNG: {e}"))?;
     let bytes = &buf[..info.buffer_size()];
-    assert!(info.color_type == ColorType::Rgba);
 
-    let mut color_map = HashMap::new();
-    let mut palette = Vec::new();
-    color_map.insert(Color::TRANSPARENT, 0);
+    let rgba: Vec<[u8; 4]> = match info.color_type {
+        ColorType::Rgba => bytes
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect(),
+        ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        ColorType::Grayscale => bytes.iter().map(|&g| [g, g, g, 255]).collect(),
+        // UNREACHABLE: the transformations expand indexed colors to RGB(A)
+        ColorType::Indexed => unreachable!("UNREACHABLE: indexed colors are expanded"),
+    };
+    let pixels = rgba
+        .into_iter()
+        .map(|rgba| match rgba {
+            // All fully transparent pixels are the same color
+            [_, _, _, 0] => Color::TRANSPARENT,
+            rgba => Color::from_rgba_u8(rgba),
+        })
+        .collect();
+    Ok((width, pixels))
+}
+
+fn read_png_file(path: &Path) -> Result<(usize, Vec<Color>)> {
+    let input = File::open(path).map_err(|e| format!("Failed to open {path:?}: {e}"))?;
+    read_png(input).map_err(|e| format!("{path:?}: {e}"))
+}
+
+/// The range of columns that have opaque pixels, if any
+fn opaque_columns(data: &[u8], width: usize) -> Option<Range<usize>> {
     let transp_idx = 0;
+    let mut columns: Option<Range<usize>> = None;
 
-    let mut data = Vec::new();
-    let mut left = width;
-    let mut right = 0;
-
-    for row in bytes.chunks_exact(4 * width) {
-        let mut leftmost_opaque = width;
-        let mut rightmost_opaque = 0;
-        for (i, pixel) in row.chunks_exact(4).enumerate() {
-            let color = Color::from_rgba_u8(pixel[0..4].try_into().unwrap());
-            let color_idx = *color_map.entry(color).or_insert_with(|| {
-                palette.push(color);
-                palette.len() // Implicit transparent as 0th index, so len() == latest index
-            }) as u8;
-            if color_idx != transp_idx {
-                rightmost_opaque = i;
-            }
-            if color_idx != transp_idx && i < leftmost_opaque {
-                leftmost_opaque = i;
-            }
-            data.push(color_idx);
-        }
-        left = left.min(leftmost_opaque);
-        right = right.max(rightmost_opaque + 1);
+    for row in data.chunks_exact(width) {
+        let Some(leftmost_opaque) = row.iter().position(|&c| c != transp_idx) else {
+            continue;
+        };
+        // UNREACHABLE: the row has an opaque pixel
+        let rightmost_opaque = row.iter().rposition(|&c| c != transp_idx).unwrap();
+        let row_columns = leftmost_opaque..rightmost_opaque + 1;
+        columns = Some(match columns {
+            None => row_columns,
+            Some(columns) => union(columns, row_columns),
+        });
     }
 
-    assert!(palette.len() < 15);
-    let height = (data.len() / width) as u32;
-    let width = width as u32;
+    columns
+}
 
-    (
-        PalTex {
-            palette,
-            width,
-            height,
-            data,
-        },
-        left..right,
-    )
+/// The range of rows that have opaque pixels, if any
+fn opaque_rows(data: &[u8], width: usize) -> Option<Range<usize>> {
+    let transp_idx = 0;
+    let mut rows = data
+        .chunks_exact(width)
+        .enumerate()
+        .filter(|(_, row)| row.iter().any(|&c| c != transp_idx))
+        .map(|(y, _)| y);
+    let top = rows.next()?;
+    let bottom = rows.next_back().unwrap_or(top) + 1;
+    Some(top..bottom)
 }
 
-fn crop(paltex: &PalTex, range: Range<usize>) -> PalTex {
-    let mut data: Vec<u8> = Vec::with_capacity(paltex.data.len());
+fn union(a: Range<usize>, b: Range<usize>) -> Range<usize> {
+    a.start.min(b.start)..a.end.max(b.end)
+}
 
-    for row in paltex.data.chunks_exact(paltex.width as usize) {
-        if row.iter().all(|&color_idx| color_idx == 0) {
+/// Crops the frames to the bounding box of the opaque pixels of all of them,
+/// so that they stay aligned. Returns the columns and the rows kept.
+fn crop(frames: &mut [Vec<u8>], width: usize) -> (Range<usize>, Range<usize>) {
+    let mut bounds = None;
+    for frame in frames.iter() {
+        let (Some(frame_columns), Some(frame_rows)) =
+            (opaque_columns(frame, width), opaque_rows(frame, width))
+        else {
             continue;
-        }
-        data.extend(&row[range.clone()]);
+        };
+        bounds = Some(match bounds {
+            None => (frame_columns, frame_rows),
+            Some((columns, rows)) => (union(columns, frame_columns), union(rows, frame_rows)),
+        });
     }
+    // All transparent: keep as is
+    let (columns, rows) = bounds.unwrap_or((0..width, 0..frames[0].len() / width));
 
-    let crop_img_width = (range.end - range.start) as u32;
-    let crop_img_height = data.len() as u32 / crop_img_width;
-
-    PalTex {
-        width: crop_img_width,
-        height: crop_img_height,
-        palette: paltex.palette.clone(),
-        data,
+    for frame in frames {
+        *frame = frame
+            .chunks_exact(width)
+            .skip(rows.start)
+            .take(rows.len())
+            .flat_map(|row| &row[columns.clone()])
+            .copied()
+            .collect();
     }
+    (columns, rows)
 }
 
-fn convert(input: impl Read) -> Vec<u8> {
-    let (paltex, range) = from_png(input);
+/// Palettizes frames of the same size, and crops them unless `--no-crop`.
+/// Returns the width and the height of the frames.
+fn palettize_frames(
+    frames: &[Vec<Color>],
+    width: usize,
+    palettizer: &mut Palettizer,
+    args: &Args,
+) -> Result<(usize, usize, Vec<Vec<u8>>)> {
+    let height = frames[0].len() / width;
+    eprintln!("Image size: {width}x{height} pixels");
 
+    if let Some(max_colors) = args.quantize {
+        let palette = quantize(&frames.concat(), max_colors);
+        *palettizer = Palettizer::with_palette(palette, Unknown::Nearest);
+    }
+    let mut frames = frames
+        .iter()
+        .map(|pixels| palettizer.palettize(pixels, width))
+        .collect::<Result<Vec<_>>>()?;
     eprintln!(
         "Palette length: {} RGBA colors (+ 1 implicit transparent)",
-        paltex.palette.len()
+        palettizer.palette.len()
     );
-    eprintln!("Original image width: {} pixels", paltex.width);
-    eprintln!("Original image height: {} pixels", paltex.height);
 
-    let cropped = crop(&paltex, range);
+    if args.crop.not() {
+        return Ok((width, height, frames));
+    }
+    let (columns, rows) = crop(&mut frames, width);
+    eprintln!(
+        "Cropped to {}x{} pixels at ({}, {})",
+        columns.len(),
+        rows.len(),
+        columns.start,
+        rows.start
+    );
+    Ok((columns.len(), rows.len(), frames))
+}
 
-    eprintln!("Cropped image length: {} pixels", cropped.width);
-    eprintln!("Cropped image width: {} pixels", cropped.height);
+fn convert(input: impl Read, mut palettizer: Palettizer, args: &Args) -> Result<Vec<u8>> {
+    let (width, pixels) = read_png(input)?;
+    let (width, height, mut frames) = palettize_frames(&[pixels], width, &mut palettizer, args)?;
 
+    let paltex = PalTex {
+        width: width as u32,
+        height: height as u32,
+        palette: palettizer.palette,
+        data: frames.remove(0),
+    };
     let mut encoded_output = Vec::new();
-    paltex::encode(&cropped, &mut encoded_output);
-    encoded_output
-}
-
-fn convert_file(path: &Path) {
-    let paltex_path = path.with_extension("paltex");
-    let fname = paltex_path.file_name().unwrap();
-    let input = std::fs::File::open(path).unwrap();
-    let encoded_output = convert(input);
-    eprintln!("Writing {fname:?}.");
-    write(fname, encoded_output).unwrap();
-}
-
-fn main() {
-    if let Some(path) = args().nth(1) {
-        let path = Path::new(&path);
-        let target_ext = OsStr::new("png");
-        if path.is_dir() {
-            eprintln!("Looking for png files in {path:?}.");
-            for path in read_dir(path).unwrap_or_else(|e| {
-                panic!("Failed to read directory: {path:?}: {e}");
-            }) {
-                let path = path.unwrap().path();
-                if let Some(ext) = path.extension()
-                    && ext == target_ext
-                {
-                    convert_file(&path);
-                }
-            }
-        } else if path.is_file()
-            && let Some(ext) = path.extension()
-            && ext == target_ext
+    paltex::encode(&paltex, &mut encoded_output);
+    eprintln!("Encoded size: {} bytes", encoded_output.len());
+    Ok(encoded_output)
+}
+
+fn encoded_len(paltex: &PalTex, op_set: Option<OpSet>) -> (usize, Duration) {
+    let mut encoded_output = Vec::new();
+    let start = Instant::now();
+    match op_set {
+        Some(op_set) => paltex::encode_with(paltex, op_set, &mut encoded_output),
+        None => paltex::encode(paltex, &mut encoded_output),
+    }
+    (encoded_output.len(), start.elapsed())
+}
+
+/// Prints the encoded sizes of the png files in `dir` with each op set.
+fn report(dir: &Path) -> Result<()> {
+    let paths = png_files(dir)?;
+    println!(
+        "{:<16} {:>9} {:>9} {:>9} {:>9} {:>9} {:>12}",
+        "file", "pixels", "colors", "png", "runs", "extended", "encode time"
+    );
+    let mut totals = [0; 4];
+    for path in paths {
+        let png_len = std::fs::metadata(&path)
+            .map_err(|e| format!("Failed to read {path:?}: {e}"))?
+            .len() as usize;
+        let (width, pixels) = read_png_file(&path)?;
+        let mut palettizer = Palettizer::new();
+        let mut frames = vec![palettizer.palettize(&pixels, width)?];
+        let (columns, rows) = crop(&mut frames, width);
+        let cropped = PalTex {
+            width: columns.len() as u32,
+            height: rows.len() as u32,
+            palette: palettizer.palette,
+            data: frames.remove(0),
+        };
+
+        // Palettes of 255 colors only fit the extended ops
+        let runs_len = IndexWidth::fitting(cropped.palette.len())
+            .map(|index_width| encoded_len(&cropped, Some(OpSet::Runs(index_width))).0);
+        let (extended_len, _) = encoded_len(&cropped, Some(OpSet::Extended));
+        let (_, encode_time) = encoded_len(&cropped, None);
+
+        let pixels = cropped.data.len();
+        println!(
+            "{:<16} {:>9} {:>9} {:>9} {:>9} {:>9} {:>12?}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            pixels,
+            cropped.palette.len(),
+            png_len,
+            runs_len.map_or("-".to_owned(), |len| len.to_string()),
+            extended_len,
+            encode_time,
+        );
+        for (total, len) in
+            totals
+                .iter_mut()
+                .zip([pixels, png_len, runs_len.unwrap_or(0), extended_len])
         {
-            convert_file(path);
+            *total += len;
+        }
+    }
+    println!(
+        "{:<16} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "total", totals[0], "", totals[1], totals[2], totals[3]
+    );
+    Ok(())
+}
+
+fn png_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let target_ext = OsStr::new("png");
+    let mut paths = Vec::new();
+    for entry in read_dir(dir).map_err(|e| format!("Failed to read directory {dir:?}: {e}"))? {
+        let path = entry
+            .map_err(|e| format!("Failed to read directory {dir:?}: {e}"))?
+            .path();
+        if path.extension() == Some(target_ext) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Where to write the output named `name` converted from `input`: next to
+/// the input without `-o`, into the `-o` directory, or to the `-o` file if
+/// there's only one output.
+fn output_path(args: &Args, input: &Path, name: &str, single: bool) -> Result<PathBuf> {
+    let fname = format!("{name}.paltex");
+    match &args.output {
+        None => Ok(input.with_file_name(fname)),
+        Some(output) if output.is_dir() => Ok(output.join(fname)),
+        Some(output) if single => Ok(output.clone()),
+        Some(output) => Err(format!(
+            "The output {output:?} must be a directory to convert several files"
+        )),
+    }
+}
+
+fn write_output(path: &Path, encoded_output: &[u8]) -> Result<()> {
+    eprintln!("Writing {path:?}.");
+    write(path, encoded_output).map_err(|e| format!("Failed to write {path:?}: {e}"))
+}
+
+fn convert_files(args: &Args, palettizer: Palettizer) -> Result<()> {
+    let mut paths = Vec::new();
+    for input in &args.inputs {
+        if input.is_dir() {
+            eprintln!("Looking for png files in {input:?}.");
+            paths.extend(png_files(input)?);
+        } else if input.is_file() {
+            paths.push(input.clone());
+        } else {
+            return Err(format!("No such file or directory: {input:?}"));
         }
-    } else {
-        eprintln!("Converting input from stdin to stdout.");
-        let input = stdin().lock();
-        let encoded_output = convert(input);
+    }
+    if paths.is_empty() {
+        return Err("No png files to convert".to_owned());
+    }
+
+    let single = paths.len() == 1;
+    for path in paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let output = output_path(args, &path, &name, single)?;
+        eprintln!("Converting {path:?}.");
+        let input = File::open(&path).map_err(|e| format!("Failed to open {path:?}: {e}"))?;
+        let encoded_output =
+            convert(input, palettizer.clone(), args).map_err(|e| format!("{path:?}: {e}"))?;
+        write_output(&output, &encoded_output)?;
+    }
+    Ok(())
+}
 
-        stdout()
+fn convert_stdin(args: &Args, palettizer: Palettizer) -> Result<()> {
+    if let Some(output) = &args.output
+        && output.is_dir()
+    {
+        return Err(format!(
+            "The output {output:?} must be a file when converting from stdin"
+        ));
+    }
+
+    eprintln!("Converting input from stdin.");
+    let encoded_output = convert(stdin().lock(), palettizer, args)?;
+
+    match &args.output {
+        Some(output) => write_output(output, &encoded_output),
+        None => stdout()
             .write_all(&encoded_output)
-            .expect("Failed to write to stdout");
+            .map_err(|e| format!("Failed to write to stdout: {e}")),
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    let palettizer = match &args.palette {
+        Some(path) => {
+            let unknown = if args.nearest {
+                Unknown::Nearest
+            } else {
+                Unknown::Error
+            };
+            Palettizer::with_palette(read_palette(path)?, unknown)
+        }
+        None => Palettizer::new(),
+    };
+
+    match args.mode {
+        Mode::Help => print!("{USAGE}"),
+        Mode::Report => {
+            let dir = args
+                .inputs
+                .first()
+                .map_or(Path::new("assets/png"), |dir| dir);
+            report(dir)?;
+        }
+        Mode::MakePalette => palette::make_palette(args)?,
+        Mode::Anim | Mode::Strip(_) => anim::convert_anim(args, palettizer)?,
+        Mode::Convert if args.inputs.is_empty() => convert_stdin(args, palettizer)?,
+        Mode::Convert => convert_files(args, palettizer)?,
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    tool_common::main("png2paltex", Args::parse, run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x3 frame with opaque pixels at the columns 1..3 of the rows 1..2
+    const FRAME: [u8; 12] = [
+        0, 0, 0, 0, //
+        0, 1, 2, 0, //
+        0, 0, 0, 0, //
+    ];
+
+    fn parse(args: &[&str]) -> Args {
+        match Args::parse(args.iter().map(|&arg| arg.to_owned())) {
+            Ok(args) => args,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn test_opaque_bounds() {
+        assert_eq!(opaque_columns(&FRAME, 4), Some(1..3));
+        assert_eq!(opaque_rows(&FRAME, 4), Some(1..2));
+        assert_eq!(opaque_columns(&[0; 8], 4), None);
+        assert_eq!(opaque_rows(&[0; 8], 4), None);
+        assert_eq!(opaque_columns(&[1, 0, 0, 0, 0, 1], 3), Some(0..3));
+        assert_eq!(opaque_rows(&[1, 0, 0, 0, 0, 1], 3), Some(0..2));
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut frames = vec![FRAME.to_vec()];
+        assert_eq!(crop(&mut frames, 4), (1..3, 1..2));
+        assert_eq!(frames, [[1, 2]]);
+
+        // The frames are cropped to the union of their bounds
+        let mut frames = vec![FRAME.to_vec(), vec![0; 12]];
+        frames[1][11] = 3;
+        assert_eq!(crop(&mut frames, 4), (1..4, 1..3));
+        assert_eq!(frames, [[1, 2, 0, 0, 0, 0], [0, 0, 0, 0, 0, 3]]);
+
+        // All transparent: kept as is
+        let mut frames = vec![vec![0; 12]];
+        assert_eq!(crop(&mut frames, 4), (0..4, 0..3));
+        assert_eq!(frames, [[0; 12]]);
+    }
+
+    #[test]
+    fn test_output_path() {
+        let input = Path::new("assets/png/bubu.png");
+        let args = parse(&[]);
+        assert_eq!(
+            output_path(&args, input, "bubu", false),
+            Ok(PathBuf::from("assets/png/bubu.paltex"))
+        );
+
+        let dir = std::env::temp_dir();
+        let args = parse(&["-o", dir.to_str().unwrap()]);
+        assert_eq!(
+            output_path(&args, input, "bubu", false),
+            Ok(dir.join("bubu.paltex"))
+        );
+
+        let args = parse(&["-o", "out.paltex"]);
+        assert_eq!(
+            output_path(&args, input, "bubu", true),
+            Ok(PathBuf::from("out.paltex"))
+        );
+        assert!(output_path(&args, input, "bubu", false).is_err());
     }
 }
@@ -0,0 +1,345 @@
+//! Palettes: building them out of the colors of the images, forcing a shared
+//! one read from a file, and reducing images with too many colors.
+
+use std::{collections::HashMap, fs::read, iter::once, path::Path};
+
+use paltex::{Color, IndexWidth, MAX_PAL_LEN, PALETTE_MAGIC};
+
+use crate::{Result, args::Args, read_png, read_png_file, write_output};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Error reports list up to this many missing colors
+const MISSING_REPORT_MAX: usize = 10;
+
+/// What to do with colors that aren't in the palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unknown {
+    /// Add them to the palette, up to `MAX_PAL_LEN` colors
+    Add,
+    /// Map them to the nearest color in the palette
+    Nearest,
+    /// Report them as an error
+    Error,
+}
+
+/// Maps colors to palette indices, adding new colors to the palette.
+/// Can be shared between images to make them share the palette.
+#[derive(Clone)]
+pub struct Palettizer {
+    color_map: HashMap<Color, u8>,
+    pub palette: Vec<Color>,
+    unknown: Unknown,
+}
+
+impl Palettizer {
+    pub fn new() -> Self {
+        let mut color_map = HashMap::new();
+        color_map.insert(Color::TRANSPARENT, 0);
+        Self {
+            color_map,
+            palette: Vec::new(),
+            unknown: Unknown::Add,
+        }
+    }
+
+    /// A palettizer with a fixed palette, not including the implicit
+    /// transparent
+    pub fn with_palette(palette: Vec<Color>, unknown: Unknown) -> Self {
+        let mut palettizer = Self::new();
+        for color in palette {
+            palettizer.add(color);
+        }
+        palettizer.unknown = unknown;
+        palettizer
+    }
+
+    fn add(&mut self, color: Color) -> u8 {
+        self.palette.push(color);
+        let color_idx = self.palette.len() as u8; // Implicit transparent as 0th index, so len() == latest index
+        // Duplicates map to the first one
+        *self.color_map.entry(color).or_insert(color_idx)
+    }
+
+    fn nearest(&self, color: Color) -> u8 {
+        let distance = |other: Color| {
+            let (a, b) = (color.to_rgba_u8(), other.to_rgba_u8());
+            (0..4)
+                .map(|i| (a[i] as i32 - b[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        once(Color::TRANSPARENT)
+            .chain(self.palette.iter().copied())
+            .enumerate()
+            .min_by_key(|&(_, other)| distance(other))
+            .map_or(0, |(color_idx, _)| color_idx as u8)
+    }
+
+    fn color_idx(&mut self, color: Color) -> Result<Option<u8>> {
+        if let Some(&color_idx) = self.color_map.get(&color) {
+            return Ok(Some(color_idx));
+        }
+        match self.unknown {
+            Unknown::Add if self.palette.len() == MAX_PAL_LEN as usize => Err(format!(
+                "Too many colors: over {MAX_PAL_LEN} (+ 1 implicit transparent), \
+                 or over {} for the run ops. Reduce them with --quantize N.",
+                IndexWidth::Wide.max_pal_len()
+            )),
+            Unknown::Add => Ok(Some(self.add(color))),
+            Unknown::Nearest => {
+                let color_idx = self.nearest(color);
+                self.color_map.insert(color, color_idx);
+                Ok(Some(color_idx))
+            }
+            Unknown::Error => Ok(None),
+        }
+    }
+
+    /// Maps the pixels of an image of `width` to palette indices.
+    pub fn palettize(&mut self, pixels: &[Color], width: usize) -> Result<Vec<u8>> {
+        // The first pixel and the pixel count of each missing color
+        let mut missing: HashMap<Color, (usize, usize)> = HashMap::new();
+        let mut data = Vec::with_capacity(pixels.len());
+        for (i, &color) in pixels.iter().enumerate() {
+            match self.color_idx(color)? {
+                Some(color_idx) => data.push(color_idx),
+                None => {
+                    missing.entry(color).or_insert((i, 0)).1 += 1;
+                    data.push(0);
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(data);
+        }
+        let mut missing: Vec<_> = missing.into_iter().collect();
+        missing.sort_by_key(|&(_, (first, _))| first);
+        let mut report = format!(
+            "{} colors aren't in the palette (map them with --nearest):",
+            missing.len()
+        );
+        for (color, (first, count)) in missing.iter().take(MISSING_REPORT_MAX) {
+            report += &format!(
+                "\n  {:?} at ({}, {}), {count} pixels",
+                color.to_rgba_u8(),
+                first % width,
+                first / width,
+            );
+        }
+        if missing.len() > MISSING_REPORT_MAX {
+            report += &format!("\n  and {} more", missing.len() - MISSING_REPORT_MAX);
+        }
+        Err(report)
+    }
+}
+
+/// The colors of the pixels in the order they first appear, not including
+/// the transparent
+fn unique_colors(pixels: &[Color], width: usize) -> Result<Vec<Color>> {
+    let mut palettizer = Palettizer::new();
+    palettizer.palettize(pixels, width)?;
+    Ok(palettizer.palette)
+}
+
+/// Reduces the colors of the pixels to at most `max_colors` with median cut:
+/// the box of colors with the widest channel range is split at its median
+/// pixel until there are enough boxes, and each box becomes the average of
+/// its colors. Pixels with few enough colors keep them exactly.
+pub fn quantize(pixels: &[Color], max_colors: usize) -> Vec<Color> {
+    let mut counts: HashMap<Color, usize> = HashMap::new();
+    for &color in pixels.iter().filter(|&&c| c != Color::TRANSPARENT) {
+        *counts.entry(color).or_default() += 1;
+    }
+    if counts.len() <= max_colors {
+        let mut palettizer = Palettizer::new();
+        for &color in pixels {
+            // UNREACHABLE: there are at most `max_colors` colors to add
+            palettizer
+                .color_idx(color)
+                .expect("UNREACHABLE: the colors fit");
+        }
+        return palettizer.palette;
+    }
+
+    let channel_range = |colors: &[(Color, usize)], channel: usize| {
+        let values = colors.iter().map(|(c, _)| c.to_rgba_u8()[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+
+    let mut colors: Vec<_> = counts.into_iter().collect();
+    // Sorted, so that the result doesn't depend on the hash map order
+    colors.sort_unstable_by_key(|(c, _)| c.to_rgba_u8());
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| (0..4).map(move |ch| (i, ch, channel_range(colors, ch))))
+            .max_by_key(|&(_, _, range)| range);
+        let Some((box_idx, channel, _)) = widest else {
+            break;
+        };
+
+        let mut lower = boxes.swap_remove(box_idx);
+        lower.sort_by_key(|(c, _)| c.to_rgba_u8()[channel]);
+        let total: usize = lower.iter().map(|(_, count)| count).sum();
+        let mut below = 0;
+        let median = lower
+            .iter()
+            .position(|(_, count)| {
+                below += count;
+                below * 2 >= total
+            })
+            .unwrap_or(0);
+        // Both halves keep at least one color
+        let upper = lower.split_off((median + 1).clamp(1, lower.len() - 1));
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: usize = colors.iter().map(|(_, count)| count).sum();
+            let mut sums = [0; 4];
+            for (color, count) in colors {
+                for (sum, value) in sums.iter_mut().zip(color.to_rgba_u8()) {
+                    *sum += value as usize * count;
+                }
+            }
+            Color::from_rgba_u8(sums.map(|sum| ((sum + total / 2) / total) as u8))
+        })
+        .collect()
+}
+
+/// Reads the palette of a palette file or a paltex file, or the colors of a
+/// PNG image in the order they first appear. The implicit transparent isn't
+/// included.
+pub fn read_palette(path: &Path) -> Result<Vec<Color>> {
+    let bytes = read(path).map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+    let with_err_path = |e: String| format!("{path:?}: {e}");
+
+    if bytes.starts_with(PNG_SIGNATURE) {
+        let (width, pixels) = read_png(bytes.as_slice()).map_err(with_err_path)?;
+        return unique_colors(&pixels, width).map_err(with_err_path);
+    }
+    let palette = if bytes.starts_with(&PALETTE_MAGIC) {
+        paltex::decode_palette(&bytes)
+    } else {
+        paltex::decode_anim(&bytes).map(|anim| anim.palette)
+    };
+    let palette = palette.map_err(|e| with_err_path(e.to_string()))?;
+    Ok(palette[1..].to_vec())
+}
+
+/// Writes a palette file out of the colors of the input images, quantized
+/// with `--quantize`.
+pub fn make_palette(args: &Args) -> Result<()> {
+    let Some(output) = &args.output else {
+        return Err("Give the palette file to write with -o".to_owned());
+    };
+    if args.inputs.is_empty() {
+        return Err("No input files".to_owned());
+    }
+
+    let mut pixels = Vec::new();
+    for path in &args.inputs {
+        eprintln!("Reading {path:?}.");
+        let (width, image) = read_png_file(path)?;
+        // Padded to whole rows, so that the positions of errors make sense
+        pixels.resize(pixels.len().next_multiple_of(width), Color::TRANSPARENT);
+        pixels.extend(image);
+    }
+
+    let palette = match args.quantize {
+        Some(max_colors) => quantize(&pixels, max_colors),
+        None => unique_colors(&pixels, 1)?,
+    };
+    eprintln!(
+        "Palette length: {} RGBA colors (+ 1 implicit transparent)",
+        palette.len()
+    );
+
+    let mut encoded_output = Vec::new();
+    paltex::encode_palette(&palette, &mut encoded_output);
+    write_output(output, &encoded_output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(rgba: [u8; 4]) -> Color {
+        Color::from_rgba_u8(rgba)
+    }
+
+    const RED: Color = Color::from_rgba_u8([255, 0, 0, 255]);
+    const GREEN: Color = Color::from_rgba_u8([0, 255, 0, 255]);
+    const BLUE: Color = Color::from_rgba_u8([0, 0, 255, 255]);
+
+    #[test]
+    fn test_nearest() {
+        let palettizer = Palettizer::with_palette(vec![RED, GREEN, BLUE], Unknown::Nearest);
+        assert_eq!(palettizer.nearest(RED), 1);
+        assert_eq!(palettizer.nearest(rgba([200, 30, 40, 255])), 1);
+        assert_eq!(palettizer.nearest(rgba([10, 20, 180, 240])), 3);
+        // The transparent is a candidate too
+        assert_eq!(palettizer.nearest(rgba([0, 0, 0, 10])), 0);
+    }
+
+    #[test]
+    fn test_palettize_unknown() {
+        let pixels = [RED, rgba([250, 0, 0, 255]), Color::TRANSPARENT, GREEN];
+
+        let mut palettizer = Palettizer::with_palette(vec![RED, GREEN], Unknown::Nearest);
+        assert_eq!(palettizer.palettize(&pixels, 2), Ok(vec![1, 1, 0, 2]));
+        assert_eq!(palettizer.palette, [RED, GREEN]);
+
+        let mut palettizer = Palettizer::with_palette(vec![RED], Unknown::Error);
+        let report = palettizer.palettize(&pixels, 2).unwrap_err();
+        assert!(report.starts_with("2 colors aren't in the palette"));
+        assert!(report.contains("[250, 0, 0, 255] at (1, 0), 1 pixels"));
+        assert!(report.contains("[0, 255, 0, 255] at (1, 1), 1 pixels"));
+
+        let mut palettizer = Palettizer::new();
+        assert_eq!(palettizer.palettize(&pixels, 2), Ok(vec![1, 2, 0, 3]));
+
+        let pixels: Vec<_> = (0..=255).map(|i| rgba([i, 0, 0, 255])).collect();
+        let mut palettizer = Palettizer::new();
+        assert_eq!(palettizer.palettize(&pixels[..255], 255).unwrap()[254], 255);
+        let mut palettizer = Palettizer::new();
+        let error = palettizer.palettize(&pixels, 256).unwrap_err();
+        assert!(error.starts_with(
+            "Too many colors: over 255 (+ 1 implicit transparent), or over 254 for the run ops."
+        ));
+    }
+
+    #[test]
+    fn test_quantize() {
+        // Fitting colors are kept in the order they first appear
+        let pixels = [BLUE, Color::TRANSPARENT, RED, BLUE];
+        assert_eq!(quantize(&pixels, 2), [BLUE, RED]);
+
+        // Two clusters of reds and blues, averaged by the pixel counts
+        let pixels = [
+            rgba([250, 0, 0, 255]),
+            rgba([250, 0, 0, 255]),
+            rgba([240, 0, 0, 255]),
+            rgba([240, 0, 0, 255]),
+            rgba([0, 0, 250, 255]),
+            rgba([0, 0, 240, 255]),
+            Color::TRANSPARENT,
+        ];
+        let mut palette = quantize(&pixels, 2);
+        palette.sort_by_key(|c| c.to_rgba_u8());
+        assert_eq!(palette, [rgba([0, 0, 245, 255]), rgba([245, 0, 0, 255])]);
+
+        let gradient: Vec<_> = (0..=255).map(|v| rgba([v, v, v, 255])).collect();
+        for max_colors in [1, 7, 32, 255] {
+            assert_eq!(quantize(&gradient, max_colors).len(), max_colors);
+        }
+        assert_eq!(quantize(&gradient, 1), [rgba([128, 128, 128, 255])]);
+    }
+}
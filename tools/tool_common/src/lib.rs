@@ -0,0 +1,111 @@
+//! The command line plumbing shared by the tools: parsing the options, and
+//! reporting the errors with the exit codes.
+
+use std::{env, iter::Skip, path::PathBuf, process::ExitCode, str::FromStr};
+
+/// The errors of the tools are messages for the user.
+pub type Result<T, E = String> = std::result::Result<T, E>;
+
+/// The command line arguments, with helpers for the values of the options.
+pub struct Parser<I> {
+    args: I,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    pub fn new(args: impl IntoIterator<IntoIter = I>) -> Self {
+        Parser {
+            args: args.into_iter(),
+        }
+    }
+
+    /// The value of `option`, given as the next argument
+    pub fn value(&mut self, option: &str) -> Result<String> {
+        self.args
+            .next()
+            .ok_or_else(|| format!("Missing the value of {option}"))
+    }
+
+    /// The value of `option`, parsed
+    pub fn number<T: FromStr>(&mut self, option: &str) -> Result<T> {
+        let value = self.value(option)?;
+        value
+            .parse()
+            .map_err(|_| format!("Invalid value of {option}: {value:?}"))
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for Parser<I> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.args.next()
+    }
+}
+
+/// An argument that isn't any of the options: an input path, unless it looks
+/// like an option.
+pub fn input(arg: String) -> Result<PathBuf> {
+    if arg.starts_with('-') {
+        return Err(format!("Unknown option {arg:?}"));
+    }
+    Ok(arg.into())
+}
+
+/// Parses the arguments and runs the tool. The errors are printed prefixed
+/// with the name of the tool, exiting with 2 for invalid arguments, and 1 for
+/// the other failures.
+pub fn main<A>(
+    name: &str,
+    parse: impl FnOnce(Skip<env::Args>) -> Result<A>,
+    run: impl FnOnce(&A) -> Result<()>,
+) -> ExitCode {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{name}: {e}");
+            eprintln!("Try {name} --help");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{name}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser(args: &[&str]) -> Parser<impl Iterator<Item = String>> {
+        Parser::new(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn test_values() {
+        let mut args = parser(&["-o", "out", "--scale", "3", "--scale", "x"]);
+        assert_eq!(args.next().as_deref(), Some("-o"));
+        assert_eq!(args.value("-o"), Ok("out".to_owned()));
+        assert_eq!(args.next().as_deref(), Some("--scale"));
+        assert_eq!(args.number::<u8>("--scale"), Ok(3));
+        args.next();
+        assert_eq!(
+            args.number::<u8>("--scale"),
+            Err("Invalid value of --scale: \"x\"".to_owned())
+        );
+        assert_eq!(args.value("-o"), Err("Missing the value of -o".to_owned()));
+    }
+
+    #[test]
+    fn test_input() {
+        assert_eq!(input("a.png".to_owned()), Ok(PathBuf::from("a.png")));
+        assert_eq!(
+            input("--nope".to_owned()),
+            Err("Unknown option \"--nope\"".to_owned())
+        );
+    }
+}